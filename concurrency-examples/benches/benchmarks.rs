@@ -1,7 +1,11 @@
 use concurrency_examples::{
     matrix_multiply, matrix_multiply_avx, matrix_multiply_avx_rayon, matrix_multiply_rayon,
 };
-use criterion::{criterion_group, criterion_main, Criterion};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use dashmap::DashMap;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::thread;
 
 fn generate_matrices(size: usize) -> (Vec<f32>, Vec<f32>) {
     let mut a = vec![0.0; size * size];
@@ -53,11 +57,623 @@ fn bench_avx_rayon(c: &mut Criterion) {
     });
 }
 
+/// Runs `threads` threads that each call `f` `iterations` times.
+fn contend<S: Send + Sync + 'static>(
+    shared: &Arc<S>,
+    threads: usize,
+    iterations: usize,
+    f: fn(&S),
+) {
+    let handles: Vec<_> = (0..threads)
+        .map(|_| {
+            let shared = Arc::clone(shared);
+            thread::spawn(move || {
+                for _ in 0..iterations {
+                    f(&shared);
+                }
+            })
+        })
+        .collect();
+
+    for handle in handles {
+        handle.join().unwrap();
+    }
+}
+
+fn bench_mutex(c: &mut Criterion) {
+    let mut group = c.benchmark_group("mutex");
+
+    let futex_mutex = Arc::new(concurrency_examples::mutex::Mutex::new(0usize));
+    group.bench_function("futex", |bencher| {
+        bencher.iter(|| contend(&futex_mutex, 4, 10_000, |m| *m.lock() += 1))
+    });
+
+    let std_mutex = Arc::new(std::sync::Mutex::new(0usize));
+    group.bench_function("std", |bencher| {
+        bencher.iter(|| contend(&std_mutex, 4, 10_000, |m| *m.lock().unwrap() += 1))
+    });
+
+    group.finish();
+}
+
+fn bench_condvar(c: &mut Criterion) {
+    let mut group = c.benchmark_group("condvar");
+
+    // Two threads take turns flipping a flag and waking each other
+    group.bench_function("futex", |bencher| {
+        use concurrency_examples::{condvar::Condvar, mutex::Mutex};
+
+        let pair = Arc::new((Mutex::new(false), Condvar::new()));
+        bencher.iter(|| {
+            let th = {
+                let pair = Arc::clone(&pair);
+                thread::spawn(move || {
+                    let (flag, condvar) = &*pair;
+                    for _ in 0..1_000 {
+                        let mut guard = condvar.wait_while(flag.lock(), |f| !*f);
+                        *guard = false;
+                        condvar.notify_one();
+                    }
+                })
+            };
+            let (flag, condvar) = &*pair;
+            for _ in 0..1_000 {
+                let mut guard = condvar.wait_while(flag.lock(), |f| *f);
+                *guard = true;
+                condvar.notify_one();
+            }
+            th.join().unwrap();
+            *flag.lock() = false;
+        })
+    });
+
+    group.bench_function("std", |bencher| {
+        use std::sync::{Condvar, Mutex};
+
+        let pair = Arc::new((Mutex::new(false), Condvar::new()));
+        bencher.iter(|| {
+            let th = {
+                let pair = Arc::clone(&pair);
+                thread::spawn(move || {
+                    let (flag, condvar) = &*pair;
+                    for _ in 0..1_000 {
+                        let guard = flag.lock().unwrap();
+                        let mut guard = condvar.wait_while(guard, |f| !*f).unwrap();
+                        *guard = false;
+                        condvar.notify_one();
+                    }
+                })
+            };
+            let (flag, condvar) = &*pair;
+            for _ in 0..1_000 {
+                let guard = flag.lock().unwrap();
+                let mut guard = condvar.wait_while(guard, |f| *f).unwrap();
+                *guard = true;
+                condvar.notify_one();
+            }
+            th.join().unwrap();
+            *flag.lock().unwrap() = false;
+        })
+    });
+
+    group.finish();
+}
+
+fn bench_seqlock(c: &mut Criterion) {
+    let mut group = c.benchmark_group("seqlock");
+
+    // Four readers and one writer making a write for every 100 reads
+    fn read_mostly<S: Send + Sync + 'static>(shared: &Arc<S>, read: fn(&S), write: fn(&S)) {
+        let writer = {
+            let shared = Arc::clone(shared);
+            thread::spawn(move || {
+                for _ in 0..400 {
+                    write(&shared);
+                }
+            })
+        };
+        contend(shared, 4, 10_000, read);
+        writer.join().unwrap();
+    }
+
+    let seqlock = Arc::new(concurrency_examples::seqlock::SeqLock::new([0u64; 4]));
+    group.bench_function("seqlock", |bencher| {
+        bencher.iter(|| {
+            read_mostly(
+                &seqlock,
+                |l| {
+                    criterion::black_box(l.read());
+                },
+                |l| l.write([1; 4]),
+            )
+        })
+    });
+
+    let rwlock = Arc::new(std::sync::RwLock::new([0u64; 4]));
+    group.bench_function("rwlock", |bencher| {
+        bencher.iter(|| {
+            read_mostly(
+                &rwlock,
+                |l| {
+                    criterion::black_box(*l.read().unwrap());
+                },
+                |l| *l.write().unwrap() = [1; 4],
+            )
+        })
+    });
+
+    group.finish();
+}
+
+const KEYS: usize = 1024;
+
+/// Four threads make one write for every `ratio` reads of a map with `KEYS` keys.
+fn mixed<S: Send + Sync + 'static>(
+    shared: &Arc<S>,
+    ratio: usize,
+    read: fn(&S, usize),
+    write: fn(&S, usize),
+) {
+    let handles: Vec<_> = (0..4)
+        .map(|t| {
+            let shared = Arc::clone(shared);
+            thread::spawn(move || {
+                for i in 0..10_000 {
+                    let key = (i * 31 + t) % KEYS;
+                    if i % (ratio + 1) == 0 {
+                        write(&shared, key);
+                    } else {
+                        read(&shared, key);
+                    }
+                }
+            })
+        })
+        .collect();
+
+    for handle in handles {
+        handle.join().unwrap();
+    }
+}
+
+fn map() -> HashMap<usize, usize> {
+    (0..KEYS).map(|k| (k, k)).collect()
+}
+
+fn bench_rwlock(c: &mut Criterion) {
+    let mut group = c.benchmark_group("rwlock");
+
+    for ratio in [100, 10, 1] {
+        let spin = Arc::new(concurrency_examples::rwlock::RwSpinLock::new(map()));
+        group.bench_with_input(
+            BenchmarkId::new("spin", ratio),
+            &ratio,
+            |bencher, &ratio| {
+                bencher.iter(|| {
+                    mixed(
+                        &spin,
+                        ratio,
+                        |l, k| {
+                            criterion::black_box(l.read().get(&k));
+                        },
+                        |l, k| {
+                            l.write().insert(k, k);
+                        },
+                    )
+                })
+            },
+        );
+
+        let futex = Arc::new(concurrency_examples::rwlock::RwLock::new(map()));
+        group.bench_with_input(
+            BenchmarkId::new("futex", ratio),
+            &ratio,
+            |bencher, &ratio| {
+                bencher.iter(|| {
+                    mixed(
+                        &futex,
+                        ratio,
+                        |l, k| {
+                            criterion::black_box(l.read().get(&k));
+                        },
+                        |l, k| {
+                            l.write().insert(k, k);
+                        },
+                    )
+                })
+            },
+        );
+
+        let std = Arc::new(std::sync::RwLock::new(map()));
+        group.bench_with_input(BenchmarkId::new("std", ratio), &ratio, |bencher, &ratio| {
+            bencher.iter(|| {
+                mixed(
+                    &std,
+                    ratio,
+                    |l, k| {
+                        criterion::black_box(l.read().unwrap().get(&k));
+                    },
+                    |l, k| {
+                        l.write().unwrap().insert(k, k);
+                    },
+                )
+            })
+        });
+
+        let dashmap: Arc<DashMap<usize, usize>> = Arc::new(map().into_iter().collect());
+        group.bench_with_input(
+            BenchmarkId::new("dashmap", ratio),
+            &ratio,
+            |bencher, &ratio| {
+                bencher.iter(|| {
+                    mixed(
+                        &dashmap,
+                        ratio,
+                        |m, k| {
+                            criterion::black_box(m.get(&k));
+                        },
+                        |m, k| {
+                            m.insert(k, k);
+                        },
+                    )
+                })
+            },
+        );
+    }
+
+    group.finish();
+}
+
+fn bench_queue(c: &mut Criterion) {
+    use concurrency_examples::ring_buffer::{spsc, MpmcQueue};
+
+    const MESSAGES: usize = 10_000;
+    const CAPACITY: usize = 64;
+
+    /// Sends `MESSAGES` values from one thread to another.
+    fn pipe<Tx: Send + 'static, Rx>(
+        (mut tx, mut rx): (Tx, Rx),
+        send: fn(&mut Tx, usize),
+        recv: fn(&mut Rx) -> usize,
+    ) {
+        let producer = thread::spawn(move || {
+            for i in 0..MESSAGES {
+                send(&mut tx, i);
+            }
+        });
+        for _ in 0..MESSAGES {
+            criterion::black_box(recv(&mut rx));
+        }
+        producer.join().unwrap();
+    }
+
+    /// Sends `MESSAGES` values from each of two threads to two other threads.
+    fn fan<Tx, Rx>((tx, rx): (Tx, Rx), send: fn(&Tx, usize), recv: fn(&Rx) -> usize)
+    where
+        Tx: Clone + Send + 'static,
+        Rx: Clone + Send + 'static,
+    {
+        let handles: Vec<_> = (0..2)
+            .map(|_| {
+                let tx = tx.clone();
+                thread::spawn(move || {
+                    for i in 0..MESSAGES {
+                        send(&tx, i);
+                    }
+                })
+            })
+            .chain((0..2).map(|_| {
+                let rx = rx.clone();
+                thread::spawn(move || {
+                    for _ in 0..MESSAGES {
+                        criterion::black_box(recv(&rx));
+                    }
+                })
+            }))
+            .collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+    }
+
+    let mut group = c.benchmark_group("queue");
+
+    group.bench_function(BenchmarkId::new("spsc", "ring_spsc"), |bencher| {
+        bencher.iter(|| pipe(spsc(CAPACITY), |tx, i| tx.send(i), |rx| rx.recv()))
+    });
+    group.bench_function(BenchmarkId::new("spsc", "ring_mpmc"), |bencher| {
+        bencher.iter(|| {
+            let queue = Arc::new(MpmcQueue::new(CAPACITY));
+            pipe((queue.clone(), queue), |q, i| q.send(i), |q| q.recv())
+        })
+    });
+    group.bench_function(BenchmarkId::new("spsc", "std_mpsc"), |bencher| {
+        bencher.iter(|| {
+            pipe(
+                std::sync::mpsc::sync_channel(CAPACITY),
+                |tx, i| tx.send(i).unwrap(),
+                |rx| rx.recv().unwrap(),
+            )
+        })
+    });
+    group.bench_function(BenchmarkId::new("spsc", "crossbeam"), |bencher| {
+        bencher.iter(|| {
+            pipe(
+                crossbeam_channel::bounded(CAPACITY),
+                |tx, i| tx.send(i).unwrap(),
+                |rx| rx.recv().unwrap(),
+            )
+        })
+    });
+
+    group.bench_function(BenchmarkId::new("mpmc", "ring_mpmc"), |bencher| {
+        bencher.iter(|| {
+            let queue = Arc::new(MpmcQueue::new(CAPACITY));
+            fan((queue.clone(), queue), |q, i| q.send(i), |q| q.recv())
+        })
+    });
+    group.bench_function(BenchmarkId::new("mpmc", "crossbeam"), |bencher| {
+        bencher.iter(|| {
+            fan(
+                crossbeam_channel::bounded(CAPACITY),
+                |tx, i| tx.send(i).unwrap(),
+                |rx| rx.recv().unwrap(),
+            )
+        })
+    });
+
+    group.finish();
+}
+
+fn bench_atomic_arc(c: &mut Criterion) {
+    use concurrency_examples::atomic_arc::AtomicArc;
+    use concurrency_examples::reclaim::HazardPointers;
+    use std::sync::RwLock;
+
+    /// Four threads read the shared value while one replaces it `writes` times.
+    fn read_mostly<S: Send + Sync + 'static>(
+        shared: &Arc<S>,
+        writes: usize,
+        read: fn(&S),
+        write: fn(&S),
+    ) {
+        let writer = {
+            let shared = Arc::clone(shared);
+            thread::spawn(move || {
+                for _ in 0..writes {
+                    write(&shared);
+                }
+            })
+        };
+        contend(shared, 4, 10_000, read);
+        writer.join().unwrap();
+    }
+
+    let mut group = c.benchmark_group("atomic_arc");
+
+    for writes in [0, 100] {
+        let epoch = Arc::new(AtomicArc::<Vec<u64>>::new(Arc::new(vec![0; 16])));
+        group.bench_with_input(BenchmarkId::new("epoch", writes), &writes, |bencher, &w| {
+            bencher.iter(|| {
+                read_mostly(
+                    &epoch,
+                    w,
+                    |a| {
+                        criterion::black_box(a.load()[0]);
+                    },
+                    |a| a.store(Arc::new(vec![1; 16])),
+                )
+            })
+        });
+
+        let hazard = Arc::new(AtomicArc::<Vec<u64>, HazardPointers>::new(Arc::new(vec![
+            0;
+            16
+        ])));
+        group.bench_with_input(
+            BenchmarkId::new("hazard", writes),
+            &writes,
+            |bencher, &w| {
+                bencher.iter(|| {
+                    read_mostly(
+                        &hazard,
+                        w,
+                        |a| {
+                            criterion::black_box(a.load()[0]);
+                        },
+                        |a| a.store(Arc::new(vec![1; 16])),
+                    )
+                })
+            },
+        );
+
+        let rwlock = Arc::new(RwLock::new(Arc::new(vec![0u64; 16])));
+        group.bench_with_input(
+            BenchmarkId::new("rwlock", writes),
+            &writes,
+            |bencher, &w| {
+                bencher.iter(|| {
+                    read_mostly(
+                        &rwlock,
+                        w,
+                        |l| {
+                            let value = Arc::clone(&l.read().unwrap());
+                            criterion::black_box(value[0]);
+                        },
+                        |l| *l.write().unwrap() = Arc::new(vec![1; 16]),
+                    )
+                })
+            },
+        );
+    }
+
+    group.finish();
+}
+
+fn bench_rcu(c: &mut Criterion) {
+    use concurrency_examples::rcu::map::RcuMap;
+
+    let mut group = c.benchmark_group("rcu");
+
+    for ratio in [1000, 100, 10] {
+        let rcu = Arc::new(RcuMap::with_buckets(KEYS));
+        for k in 0..KEYS {
+            rcu.insert(k, k);
+        }
+        group.bench_with_input(BenchmarkId::new("rcu", ratio), &ratio, |bencher, &ratio| {
+            bencher.iter(|| {
+                mixed(
+                    &rcu,
+                    ratio,
+                    |m, k| {
+                        criterion::black_box(m.get(&k));
+                    },
+                    |m, k| {
+                        m.insert(k, k);
+                    },
+                )
+            })
+        });
+
+        let dashmap: Arc<DashMap<usize, usize>> = Arc::new(map().into_iter().collect());
+        group.bench_with_input(
+            BenchmarkId::new("dashmap", ratio),
+            &ratio,
+            |bencher, &ratio| {
+                bencher.iter(|| {
+                    mixed(
+                        &dashmap,
+                        ratio,
+                        |m, k| {
+                            criterion::black_box(m.get(&k).map(|v| *v));
+                        },
+                        |m, k| {
+                            m.insert(k, k);
+                        },
+                    )
+                })
+            },
+        );
+    }
+
+    group.finish();
+}
+
+fn bench_hash_map(c: &mut Criterion) {
+    use concurrency_examples::hash_map::{lock_free, striped, ConcurrentMap};
+    use criterion::measurement::WallTime;
+    use criterion::BenchmarkGroup;
+
+    fn bench<M: ConcurrentMap<usize, usize> + 'static>(
+        group: &mut BenchmarkGroup<WallTime>,
+        name: &str,
+        ratio: usize,
+    ) {
+        let map = Arc::new(M::default());
+        for k in 0..KEYS {
+            map.insert(k, k);
+        }
+        group.bench_with_input(BenchmarkId::new(name, ratio), &ratio, |bencher, &ratio| {
+            bencher.iter(|| {
+                mixed(
+                    &map,
+                    ratio,
+                    |m, k| {
+                        criterion::black_box(m.get(&k));
+                    },
+                    |m, k| m.upsert(k, k, |v| v + 1),
+                )
+            })
+        });
+    }
+
+    let mut group = c.benchmark_group("hash_map");
+
+    for ratio in [100, 10, 1] {
+        bench::<striped::ConcurrentHashMap<usize, usize>>(&mut group, "striped", ratio);
+        bench::<lock_free::ConcurrentHashMap<usize, usize>>(&mut group, "lock_free", ratio);
+        bench::<DashMap<usize, usize>>(&mut group, "dashmap", ratio);
+    }
+
+    group.finish();
+}
+
+fn bench_thread_log(c: &mut Criterion) {
+    use concurrency_examples::barrier::SpinBarrier;
+    use std::collections::BTreeMap;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    const RUNS: usize = 1_000;
+
+    type Log = fn(&str, bool);
+
+    /// `memory_ordering::relaxed_ordering`, with each thread logging what it loaded before it
+    /// stores.
+    fn relaxed_ordering(log: Log) -> (bool, bool) {
+        let x = Arc::new(AtomicBool::new(false));
+        let y = Arc::new(AtomicBool::new(false));
+        let start = Arc::new(SpinBarrier::new(2));
+        let t1 = {
+            let (x, y, start) = (x.clone(), y.clone(), start.clone());
+            thread::spawn(move || {
+                start.wait();
+                let a = y.load(Ordering::Relaxed);
+                log("y", a);
+                x.store(a, Ordering::Relaxed);
+            })
+        };
+        let t2 = {
+            let (x, y, start) = (x.clone(), y.clone(), start.clone());
+            thread::spawn(move || {
+                start.wait();
+                let b = x.load(Ordering::Relaxed);
+                log("x", b);
+                y.store(true, Ordering::Relaxed);
+            })
+        };
+        t1.join().unwrap();
+        t2.join().unwrap();
+        (x.load(Ordering::Relaxed), y.load(Ordering::Relaxed))
+    }
+
+    let loggers: [(&str, Log); 3] = [
+        ("none", |_, _| {}),
+        ("println", |name, value| println!("loaded {name} = {value}")),
+        ("thread_log", |name, value| {
+            concurrency_examples::thread_log!("loaded {name} = {value}")
+        }),
+    ];
+
+    let mut group = c.benchmark_group("thread_log");
+
+    // Printing holds a thread up between its load and its store, which changes how often the
+    // other thread's store lands in between
+    for (name, log) in loggers {
+        let mut outcomes = BTreeMap::new();
+        for _ in 0..RUNS {
+            *outcomes.entry(relaxed_ordering(log)).or_insert(0) += 1;
+        }
+        println!("(x, y) of relaxed_ordering in {RUNS} runs, logging with {name}: {outcomes:?}");
+        group.bench_function(name, |bencher| bencher.iter(|| relaxed_ordering(log)));
+    }
+
+    group.finish();
+}
+
 criterion_group!(
     benches,
     bench_simple,
     bench_rayon,
     bench_avx,
-    bench_avx_rayon
+    bench_avx_rayon,
+    bench_mutex,
+    bench_condvar,
+    bench_seqlock,
+    bench_rwlock,
+    bench_queue,
+    bench_atomic_arc,
+    bench_rcu,
+    bench_hash_map,
+    bench_thread_log
 );
 criterion_main!(benches);
@@ -29,7 +29,7 @@ impl Handler<Pong> for PingActor {
         if let Some(pong_addr) = &self.pong {
             self.counter += 1;
             if self.counter < 10 {
-                println!("Ping received Pong, counter: {}", self.counter);
+                crate::thread_log!("Ping received Pong, counter: {}", self.counter);
                 pong_addr.do_send(Ping);
             } else {
                 // Could stop here but wouldn't be able to receive messages.
@@ -69,7 +69,7 @@ impl Handler<Ping> for PongActor {
     type Result = ();
 
     fn handle(&mut self, _msg: Ping, _ctx: &mut Context<Self>) {
-        println!("Pong received Ping");
+        crate::thread_log!("Pong received Ping");
         self.ping.do_send(Pong);
     }
 }
@@ -99,6 +99,8 @@ mod tests {
 
         let counter = ping_addr.send(GetCounter).await.unwrap();
         assert_eq!(counter, 10);
+        // Prints what the actors logged, if the `thread-log` feature is on
+        crate::thread_log::flush();
 
         // Define a message to retrieve the actor's counter
         #[derive(Message)]
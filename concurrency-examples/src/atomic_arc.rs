@@ -0,0 +1,168 @@
+//! An `Arc<T>` that can be loaded and replaced atomically, for data that is read often and
+//! replaced rarely.
+//!
+//! The [`AtomicArc`] holds a pointer from `Arc::into_raw`, which owns one strong reference. `load`
+//! bumps the count and hands out a new `Arc`, but between loading the pointer and bumping the
+//! count, a writer may swap the pointer out and drop the last reference. So a writer doesn't drop
+//! the reference it took out of the cell; it retires it with the [`Reclaim`] scheme, which drops it
+//! once no reader can be between those two steps.
+
+use crate::reclaim::{Epoch, Reclaim};
+use crate::sync::{
+    atomic::{AtomicPtr, Ordering},
+    Arc,
+};
+
+/// Drops the cell's reference to a value that was swapped out.
+unsafe fn release<T>(ptr: *mut ()) {
+    drop(Arc::from_raw(ptr as *const T));
+}
+
+pub struct AtomicArc<T, R: Reclaim = Epoch> {
+    ptr: AtomicPtr<T>,
+    reclaim: R,
+}
+
+unsafe impl<T: Send + Sync, R: Reclaim> Send for AtomicArc<T, R> {}
+unsafe impl<T: Send + Sync, R: Reclaim> Sync for AtomicArc<T, R> {}
+
+impl<T, R: Reclaim> AtomicArc<T, R> {
+    pub fn new(value: Arc<T>) -> Self {
+        Self {
+            ptr: AtomicPtr::new(Arc::into_raw(value) as *mut T),
+            reclaim: R::default(),
+        }
+    }
+
+    /// Returns the current value.
+    pub fn load(&self) -> Arc<T> {
+        let guard = self.reclaim.enter();
+        let ptr = self.reclaim.protect(&guard, &self.ptr);
+        // The guard keeps the cell's reference alive until this one is taken
+        unsafe {
+            Arc::increment_strong_count(ptr);
+            Arc::from_raw(ptr)
+        }
+    }
+
+    pub fn store(&self, value: Arc<T>) {
+        drop(self.swap(value));
+    }
+
+    /// Replaces the value, returning the old one.
+    pub fn swap(&self, value: Arc<T>) -> Arc<T> {
+        let guard = self.reclaim.enter();
+        // AcqRel so that the new value is initialised for readers, and the old one for this thread
+        let old = self
+            .ptr
+            .swap(Arc::into_raw(value) as *mut T, Ordering::AcqRel);
+        unsafe {
+            // The cell's reference is still alive, so the count can't be zero
+            Arc::increment_strong_count(old);
+            self.reclaim.retire(&guard, old.cast(), release::<T>);
+            Arc::from_raw(old)
+        }
+    }
+}
+
+impl<T: Default, R: Reclaim> Default for AtomicArc<T, R> {
+    fn default() -> Self {
+        Self::new(Arc::new(T::default()))
+    }
+}
+
+impl<T, R: Reclaim> Drop for AtomicArc<T, R> {
+    fn drop(&mut self) {
+        // Values retired earlier are dropped with `reclaim`
+        unsafe { release::<T>(self.ptr.load(Ordering::Relaxed).cast()) };
+    }
+}
+
+#[cfg(all(test, not(loom), not(pct)))]
+mod tests {
+    use super::*;
+    use crate::linearizability::models::{Register, RegisterOp};
+    use crate::linearizability::{assert_linearizable, programs};
+    use crate::reclaim::HazardPointers;
+    use proptest::prelude::*;
+    use proptest::test_runner::TestCaseError;
+    use std::thread;
+
+    #[test]
+    fn load_sees_latest_store() {
+        let cell: AtomicArc<_> = AtomicArc::new(Arc::new(1));
+        assert_eq!(*cell.load(), 1);
+        cell.store(Arc::new(2));
+        assert_eq!(*cell.load(), 2);
+        assert_eq!(*cell.swap(Arc::new(3)), 2);
+        assert_eq!(*cell.load(), 3);
+    }
+
+    #[test]
+    fn old_values_are_dropped() {
+        let first = Arc::new(());
+        let second = Arc::new(());
+        let cell: AtomicArc<_> = AtomicArc::new(Arc::clone(&first));
+        let loaded = cell.load();
+        cell.store(Arc::clone(&second));
+        drop(loaded);
+        drop(cell);
+        assert_eq!(Arc::strong_count(&first), 1);
+        assert_eq!(Arc::strong_count(&second), 1);
+    }
+
+    /// Readers check that every value they load is one the writer stored, in order.
+    fn read_while_replacing<R: Reclaim>() {
+        let cell = AtomicArc::<Vec<usize>, R>::new(Arc::new(vec![0; 16]));
+        thread::scope(|s| {
+            for _ in 0..3 {
+                s.spawn(|| {
+                    let mut last = 0;
+                    for _ in 0..10_000 {
+                        let value = cell.load();
+                        assert!(value.iter().all(|&v| v == value[0]));
+                        assert!(value[0] >= last);
+                        last = value[0];
+                    }
+                });
+            }
+            for i in 1..=1_000 {
+                cell.store(Arc::new(vec![i; 16]));
+            }
+        });
+        assert_eq!(cell.load()[0], 1_000);
+    }
+
+    fn linearizable<R: Reclaim>(programs: &[Vec<RegisterOp<u64>>]) -> Result<(), TestCaseError> {
+        let new = || AtomicArc::<u64, R>::new(Arc::new(0));
+        assert_linearizable::<Register<u64>, _>(programs, new, |cell, op| match *op {
+            RegisterOp::Write(value) => {
+                cell.store(Arc::new(value));
+                None
+            }
+            RegisterOp::Read => Some(*cell.load()),
+        })
+    }
+
+    #[test]
+    fn hazard_pointers_read_while_replacing() {
+        read_while_replacing::<HazardPointers>();
+    }
+
+    #[test]
+    fn epoch_read_while_replacing() {
+        read_while_replacing::<Epoch>();
+    }
+
+    proptest! {
+        #[test]
+        fn hazard_pointers_linearizable(programs in programs(4, any::<RegisterOp<u64>>())) {
+            linearizable::<HazardPointers>(&programs)?;
+        }
+
+        #[test]
+        fn epoch_linearizable(programs in programs(4, any::<RegisterOp<u64>>())) {
+            linearizable::<Epoch>(&programs)?;
+        }
+    }
+}
@@ -0,0 +1,242 @@
+//! Barriers that hold threads until all of them reach the same point.
+//!
+//! [`SpinBarrier`] is a sense-reversing barrier. Each phase has a sense, `false` or `true`, and a
+//! thread that arrives waits for the global sense to flip to the opposite of what it was on arrival.
+//! The last thread to arrive resets the count and flips the sense, which releases everyone at once
+//! and makes the barrier ready for the next phase.
+//!
+//! [`Barrier`] counts arrivals the same way but sleeps on [`crate::futex`] until the generation
+//! number changes. The last thread to arrive is the leader of that phase.
+//!
+//! [`Phaser`] is a barrier whose number of parties can change between and during phases, in the
+//! style of Java's `Phaser`. It uses [`crate::mutex::Mutex`] and [`crate::condvar::Condvar`] since
+//! registration and arrival update several counts together.
+
+use crate::condvar::Condvar;
+use crate::futex::{atomic_wait, wake_all};
+use crate::mutex::Mutex;
+use crate::sync::{
+    atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering},
+    hint,
+};
+
+pub struct SpinBarrier {
+    parties: usize,
+    /// Threads still to arrive in this phase
+    remaining: AtomicUsize,
+    sense: AtomicBool,
+}
+
+impl SpinBarrier {
+    pub fn new(parties: usize) -> Self {
+        assert!(parties > 0, "a barrier needs at least one party");
+        Self {
+            parties,
+            remaining: AtomicUsize::new(parties),
+            sense: AtomicBool::new(false),
+        }
+    }
+
+    /// Spins until all parties have called `wait`. Returns `true` in the last thread to arrive.
+    pub fn wait(&self) -> bool {
+        // The sense can't flip before this thread has arrived
+        let sense = !self.sense.load(Ordering::Relaxed);
+        // AcqRel so that the last thread to arrive sees what everyone did before arriving
+        if self.remaining.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.remaining.store(self.parties, Ordering::Relaxed);
+            self.sense.store(sense, Ordering::Release);
+            true
+        } else {
+            while self.sense.load(Ordering::Acquire) != sense {
+                hint::spin_loop();
+            }
+            false
+        }
+    }
+}
+
+pub struct Barrier {
+    parties: u32,
+    arrived: AtomicU32,
+    generation: AtomicU32,
+}
+
+impl Barrier {
+    pub fn new(parties: u32) -> Self {
+        assert!(parties > 0, "a barrier needs at least one party");
+        Self {
+            parties,
+            arrived: AtomicU32::new(0),
+            generation: AtomicU32::new(0),
+        }
+    }
+
+    /// Blocks until all parties have called `wait`. Returns `true` in the leader, the last thread
+    /// to arrive.
+    pub fn wait(&self) -> bool {
+        let generation = self.generation.load(Ordering::Relaxed);
+        if self.arrived.fetch_add(1, Ordering::AcqRel) + 1 == self.parties {
+            self.arrived.store(0, Ordering::Relaxed);
+            self.generation.fetch_add(1, Ordering::Release);
+            wake_all(&self.generation);
+            true
+        } else {
+            while self.generation.load(Ordering::Acquire) == generation {
+                atomic_wait(&self.generation, generation);
+            }
+            false
+        }
+    }
+}
+
+struct PhaserState {
+    phase: usize,
+    parties: usize,
+    /// Parties that haven't arrived in the current phase
+    unarrived: usize,
+}
+
+pub struct Phaser {
+    state: Mutex<PhaserState>,
+    advanced: Condvar,
+}
+
+impl Phaser {
+    pub fn new(parties: usize) -> Self {
+        Self {
+            state: Mutex::new(PhaserState {
+                phase: 0,
+                parties,
+                unarrived: parties,
+            }),
+            advanced: Condvar::new(),
+        }
+    }
+
+    /// Adds a party, which takes part from the current phase on. Returns the current phase.
+    pub fn register(&self) -> usize {
+        let mut state = self.state.lock();
+        state.parties += 1;
+        state.unarrived += 1;
+        state.phase
+    }
+
+    /// Arrives without waiting for the others. Returns the phase arrived at.
+    pub fn arrive(&self) -> usize {
+        let mut state = self.state.lock();
+        let phase = state.phase;
+        self.arrive_locked(&mut state);
+        phase
+    }
+
+    /// Arrives and leaves, so later phases wait for one party fewer. Returns the phase arrived at.
+    pub fn arrive_and_deregister(&self) -> usize {
+        let mut state = self.state.lock();
+        assert!(state.parties > 0, "no registered parties");
+        let phase = state.phase;
+        state.parties -= 1;
+        self.arrive_locked(&mut state);
+        phase
+    }
+
+    /// Arrives and waits for the other parties. Returns the new phase.
+    pub fn arrive_and_await_advance(&self) -> usize {
+        let mut state = self.state.lock();
+        let phase = state.phase;
+        self.arrive_locked(&mut state);
+        let state = self
+            .advanced
+            .wait_while(state, |state| state.phase == phase);
+        state.phase
+    }
+
+    pub fn phase(&self) -> usize {
+        self.state.lock().phase
+    }
+
+    pub fn registered_parties(&self) -> usize {
+        self.state.lock().parties
+    }
+
+    fn arrive_locked(&self, state: &mut PhaserState) {
+        assert!(state.unarrived > 0, "more arrivals than parties");
+        state.unarrived -= 1;
+        if state.unarrived == 0 {
+            state.phase += 1;
+            state.unarrived = state.parties;
+            self.advanced.notify_all();
+        }
+    }
+}
+
+#[cfg(all(test, not(loom), not(pct)))]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    /// Runs `threads` threads through `phases` phases, checking that no thread starts a phase
+    /// before every thread has finished the previous one.
+    fn lock_step(threads: usize, phases: usize, wait: impl Fn() -> bool + Sync) {
+        let done = AtomicUsize::new(0);
+        let leaders = AtomicUsize::new(0);
+        thread::scope(|s| {
+            for _ in 0..threads {
+                s.spawn(|| {
+                    for phase in 0..phases {
+                        done.fetch_add(1, Ordering::Relaxed);
+                        if wait() {
+                            leaders.fetch_add(1, Ordering::Relaxed);
+                        }
+                        assert!(done.load(Ordering::Relaxed) >= (phase + 1) * threads);
+                        wait();
+                    }
+                });
+            }
+        });
+        assert_eq!(leaders.load(Ordering::Relaxed), phases);
+    }
+
+    #[test]
+    fn spin_barrier_keeps_lock_step() {
+        let barrier = SpinBarrier::new(4);
+        lock_step(4, 100, || barrier.wait());
+    }
+
+    #[test]
+    fn barrier_keeps_lock_step() {
+        let barrier = Barrier::new(4);
+        lock_step(4, 100, || barrier.wait());
+    }
+
+    #[test]
+    fn phaser_keeps_lock_step() {
+        let phaser = Phaser::new(4);
+        let phase = AtomicUsize::new(0);
+        // The thread that sees the phase change first counts as the leader
+        lock_step(4, 100, || {
+            let new = phaser.arrive_and_await_advance();
+            phase.fetch_max(new, Ordering::Relaxed) < new
+        });
+    }
+
+    #[test]
+    fn phaser_parties_come_and_go() {
+        let phaser = Phaser::new(1);
+        assert_eq!(phaser.register(), 0);
+        assert_eq!(phaser.registered_parties(), 2);
+
+        thread::scope(|s| {
+            s.spawn(|| {
+                assert_eq!(phaser.arrive_and_await_advance(), 1);
+                assert_eq!(phaser.arrive_and_deregister(), 1);
+            });
+            assert_eq!(phaser.arrive_and_await_advance(), 1);
+            // Advances once the other party has left
+            assert_eq!(phaser.arrive_and_await_advance(), 2);
+        });
+
+        assert_eq!(phaser.registered_parties(), 1);
+        assert_eq!(phaser.arrive(), 2);
+        assert_eq!(phaser.phase(), 3);
+    }
+}
@@ -0,0 +1,305 @@
+//! Multi-producer single-consumer channels.
+//!
+//! [`locked`] keeps the queue in a `VecDeque` behind [`crate::mutex::Mutex`] and wakes the receiver
+//! with [`crate::condvar::Condvar`]. [`lock_free`] pushes onto an atomic linked list, so senders
+//! never block each other, and the receiver sleeps on [`crate::futex`].
+//!
+//! Both behave like `std::sync::mpsc::channel` and use its error types: `recv` fails once every
+//! `Sender` is gone and the queue is empty, and `send` fails once the `Receiver` is gone. The
+//! [`Receive`] trait is also implemented for `std::sync::mpsc::Receiver`, so the same code can run
+//! against all three.
+
+pub mod lock_free;
+pub mod locked;
+
+use std::marker::PhantomData;
+pub use std::sync::mpsc::{RecvError, RecvTimeoutError, SendError, TryRecvError};
+use std::time::Duration;
+
+pub trait Receive<T> {
+    /// Waits for a value. Fails once the queue is empty and all senders are gone.
+    fn recv(&self) -> Result<T, RecvError>;
+
+    fn try_recv(&self) -> Result<T, TryRecvError>;
+
+    fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError>;
+
+    /// Iterates over received values until all senders are gone.
+    fn iter(&self) -> Iter<'_, T, Self>
+    where
+        Self: Sized,
+    {
+        Iter {
+            rx: self,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Iterates over the values that are already queued.
+    fn try_iter(&self) -> TryIter<'_, T, Self>
+    where
+        Self: Sized,
+    {
+        TryIter {
+            rx: self,
+            _marker: PhantomData,
+        }
+    }
+}
+
+pub struct Iter<'a, T, R> {
+    rx: &'a R,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T, R: Receive<T>> Iterator for Iter<'_, T, R> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.rx.recv().ok()
+    }
+}
+
+pub struct TryIter<'a, T, R> {
+    rx: &'a R,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T, R: Receive<T>> Iterator for TryIter<'_, T, R> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.rx.try_recv().ok()
+    }
+}
+
+pub struct IntoIter<T, R> {
+    rx: R,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T, R: Receive<T>> IntoIter<T, R> {
+    fn new(rx: R) -> Self {
+        Self {
+            rx,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T, R: Receive<T>> Iterator for IntoIter<T, R> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.rx.recv().ok()
+    }
+}
+
+impl<T> Receive<T> for std::sync::mpsc::Receiver<T> {
+    fn recv(&self) -> Result<T, RecvError> {
+        self.recv()
+    }
+
+    fn try_recv(&self) -> Result<T, TryRecvError> {
+        self.try_recv()
+    }
+
+    fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        self.recv_timeout(timeout)
+    }
+}
+
+/// The same tests for every channel, with `std::sync::mpsc` as the reference.
+#[cfg(all(test, not(loom), not(pct)))]
+mod tests {
+    use super::*;
+    use crate::linearizability::models::{PushPop, Queue};
+    use crate::linearizability::{assert_linearizable, producers_and_consumers};
+    use proptest::prelude::*;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    trait Channel {
+        type Sender: Clone + Send + Sync + 'static;
+        type Receiver: Receive<usize> + Send + 'static;
+
+        fn channel() -> (Self::Sender, Self::Receiver);
+        fn send(tx: &Self::Sender, value: usize) -> Result<(), SendError<usize>>;
+    }
+
+    struct Std;
+    struct Locked;
+    struct LockFree;
+
+    impl Channel for Std {
+        type Sender = std::sync::mpsc::Sender<usize>;
+        type Receiver = std::sync::mpsc::Receiver<usize>;
+
+        fn channel() -> (Self::Sender, Self::Receiver) {
+            std::sync::mpsc::channel()
+        }
+
+        fn send(tx: &Self::Sender, value: usize) -> Result<(), SendError<usize>> {
+            tx.send(value)
+        }
+    }
+
+    impl Channel for Locked {
+        type Sender = locked::Sender<usize>;
+        type Receiver = locked::Receiver<usize>;
+
+        fn channel() -> (Self::Sender, Self::Receiver) {
+            locked::channel()
+        }
+
+        fn send(tx: &Self::Sender, value: usize) -> Result<(), SendError<usize>> {
+            tx.send(value)
+        }
+    }
+
+    impl Channel for LockFree {
+        type Sender = lock_free::Sender<usize>;
+        type Receiver = lock_free::Receiver<usize>;
+
+        fn channel() -> (Self::Sender, Self::Receiver) {
+            lock_free::channel()
+        }
+
+        fn send(tx: &Self::Sender, value: usize) -> Result<(), SendError<usize>> {
+            tx.send(value)
+        }
+    }
+
+    fn receives_in_order<C: Channel>() {
+        let (tx, rx) = C::channel();
+        for i in 0..3 {
+            C::send(&tx, i).unwrap();
+        }
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), [0, 1, 2]);
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+    }
+
+    fn disconnects_when_senders_drop<C: Channel>() {
+        let (tx, rx) = C::channel();
+        let tx2 = tx.clone();
+        C::send(&tx, 1).unwrap();
+        drop(tx);
+        C::send(&tx2, 2).unwrap();
+        drop(tx2);
+        assert_eq!(rx.recv(), Ok(1));
+        assert_eq!(rx.recv(), Ok(2));
+        assert_eq!(rx.recv(), Err(RecvError));
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Disconnected));
+        assert_eq!(
+            rx.recv_timeout(Duration::from_millis(1)),
+            Err(RecvTimeoutError::Disconnected)
+        );
+    }
+
+    fn send_fails_without_receiver<C: Channel>() {
+        let (tx, rx) = C::channel();
+        drop(rx);
+        assert_eq!(C::send(&tx, 1), Err(SendError(1)));
+    }
+
+    fn recv_times_out<C: Channel>() {
+        let (_tx, rx) = C::channel();
+        assert_eq!(
+            rx.recv_timeout(Duration::from_millis(10)),
+            Err(RecvTimeoutError::Timeout)
+        );
+    }
+
+    fn recv_wakes_up<C: Channel>() {
+        let (tx, rx) = C::channel();
+        let th = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            C::send(&tx, 1).unwrap();
+        });
+        assert_eq!(rx.recv_timeout(Duration::from_secs(10)), Ok(1));
+        assert_eq!(rx.recv(), Err(RecvError));
+        th.join().unwrap();
+    }
+
+    fn many_senders<C: Channel>() {
+        let (tx, rx) = C::channel();
+        let handles: Vec<_> = (0..4)
+            .map(|t| {
+                let tx = tx.clone();
+                thread::spawn(move || {
+                    for i in 0..10_000 {
+                        C::send(&tx, t * 10_000 + i).unwrap();
+                    }
+                })
+            })
+            .collect();
+        drop(tx);
+
+        // Values from one sender arrive in the order they were sent
+        let mut next = [0; 4];
+        for value in rx.iter() {
+            let (t, i) = (value / 10_000, value % 10_000);
+            assert_eq!(next[t], i);
+            next[t] += 1;
+        }
+        assert_eq!(next, [10_000; 4]);
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+    }
+
+    fn linearizable<C: Channel>() {
+        proptest!(|(programs in producers_and_consumers(3, 1))| {
+            let new = || {
+                let (tx, rx) = C::channel();
+                (tx, Mutex::new(rx))
+            };
+            assert_linearizable::<Queue<usize>, _>(&programs, new, |(tx, rx), op| match *op {
+                PushPop::Push(value) => {
+                    C::send(tx, value).unwrap();
+                    None
+                }
+                PushPop::Pop => Some(rx.lock().unwrap().recv().unwrap()),
+            })?;
+        });
+    }
+
+    #[test]
+    fn channels_drop_unreceived_values() {
+        let value = Arc::new(());
+        let (tx, rx) = locked::channel();
+        tx.send(Arc::clone(&value)).unwrap();
+        drop((tx, rx));
+        let (tx, rx) = lock_free::channel();
+        tx.send(Arc::clone(&value)).unwrap();
+        tx.send(Arc::clone(&value)).unwrap();
+        drop(rx.recv());
+        drop((tx, rx));
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+
+    macro_rules! channel_tests {
+        ($($test:ident),*) => {
+            mod with_std {
+                $(#[test] fn $test() { super::$test::<super::Std>() })*
+            }
+            mod with_locked {
+                $(#[test] fn $test() { super::$test::<super::Locked>() })*
+            }
+            mod with_lock_free {
+                $(#[test] fn $test() { super::$test::<super::LockFree>() })*
+            }
+        };
+    }
+
+    channel_tests!(
+        receives_in_order,
+        disconnects_when_senders_drop,
+        send_fails_without_receiver,
+        recv_times_out,
+        recv_wakes_up,
+        many_senders,
+        linearizable
+    );
+}
@@ -0,0 +1,229 @@
+//! A channel built on Dmitry Vyukov's MPSC linked-list queue.
+//!
+//! Senders link a new node in with a single `swap` of `head` and then point the previous node at
+//! it. The receiver follows `next` pointers from `tail`, a node whose value has already been taken.
+//! A sender that has swapped `head` but not yet set `next` hides its value and every value sent
+//! after it until it does, so the receiver just sees an empty queue in that window.
+//!
+//! The receiver sleeps on `wakeups`. Before sleeping it sets `receiver_waiting` and checks the queue
+//! again; a sender checks the flag after pushing. The `SeqCst` fences make sure at least one of
+//! the two checks sees the other side.
+
+use super::{IntoIter, Receive, RecvError, RecvTimeoutError, SendError, TryRecvError};
+use crate::futex::{atomic_wait, atomic_wait_timeout, wake_one};
+use crate::ring_buffer::CachePadded;
+use crate::sync::{
+    atomic::{fence, AtomicBool, AtomicPtr, AtomicU32, AtomicUsize, Ordering},
+    Arc, UnsafeCell,
+};
+use std::marker::PhantomData;
+use std::ptr;
+use std::time::{Duration, Instant};
+
+struct Node<T> {
+    value: UnsafeCell<Option<T>>,
+    next: AtomicPtr<Node<T>>,
+}
+
+impl<T> Node<T> {
+    fn new(value: Option<T>) -> *mut Self {
+        Box::into_raw(Box::new(Self {
+            value: UnsafeCell::new(value),
+            next: AtomicPtr::new(ptr::null_mut()),
+        }))
+    }
+}
+
+struct Shared<T> {
+    /// The most recently sent node
+    head: CachePadded<AtomicPtr<Node<T>>>,
+    /// The node before the oldest value, only accessed by the receiver
+    tail: UnsafeCell<*mut Node<T>>,
+    senders: AtomicUsize,
+    receiver: AtomicBool,
+    receiver_waiting: AtomicBool,
+    wakeups: AtomicU32,
+}
+
+unsafe impl<T: Send> Send for Shared<T> {}
+unsafe impl<T: Send> Sync for Shared<T> {}
+
+impl<T> Shared<T> {
+    fn push(&self, value: T) {
+        let node = Node::new(Some(value));
+        // AcqRel so that the next sender's store to `next` comes after this node was initialised
+        let prev = self.head.swap(node, Ordering::AcqRel);
+        // Release so that the receiver sees the value once it sees the node
+        unsafe { (*prev).next.store(node, Ordering::Release) };
+    }
+
+    /// Takes the oldest value. Must only be called by the receiver.
+    fn pop(&self) -> Option<T> {
+        self.tail.with_mut(|tail| unsafe {
+            let next = (**tail).next.load(Ordering::Acquire);
+            if next.is_null() {
+                return None;
+            }
+            // `next` becomes the empty node, and the old one is no longer reachable by senders
+            let value = (*next).value.with_mut(|v| (*v).take());
+            drop(Box::from_raw(*tail));
+            *tail = next;
+            value
+        })
+    }
+
+    fn wake_receiver(&self) {
+        fence(Ordering::SeqCst);
+        if self.receiver_waiting.load(Ordering::Relaxed) {
+            self.wakeups.fetch_add(1, Ordering::Release);
+            wake_one(&self.wakeups);
+        }
+    }
+}
+
+impl<T> Drop for Shared<T> {
+    fn drop(&mut self) {
+        let mut node = self.tail.with(|tail| unsafe { *tail });
+        while !node.is_null() {
+            let boxed = unsafe { Box::from_raw(node) };
+            node = boxed.next.load(Ordering::Relaxed);
+        }
+    }
+}
+
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+    // Only one thread at a time may pop
+    _not_sync: PhantomData<std::cell::Cell<()>>,
+}
+
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let stub = Node::new(None);
+    let shared = Arc::new(Shared {
+        head: CachePadded(AtomicPtr::new(stub)),
+        tail: UnsafeCell::new(stub),
+        senders: AtomicUsize::new(1),
+        receiver: AtomicBool::new(true),
+        receiver_waiting: AtomicBool::new(false),
+        wakeups: AtomicU32::new(0),
+    });
+    (
+        Sender {
+            shared: shared.clone(),
+        },
+        Receiver {
+            shared,
+            _not_sync: PhantomData,
+        },
+    )
+}
+
+impl<T> Sender<T> {
+    pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+        // A value sent while the receiver is being dropped stays in the queue until `Shared` drops
+        if !self.shared.receiver.load(Ordering::Relaxed) {
+            return Err(SendError(value));
+        }
+        self.shared.push(value);
+        self.shared.wake_receiver();
+        Ok(())
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.senders.fetch_add(1, Ordering::Relaxed);
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        // Release so that a receiver that sees no senders also sees every value they sent
+        if self.shared.senders.fetch_sub(1, Ordering::Release) == 1 {
+            self.shared.wake_receiver();
+        }
+    }
+}
+
+impl<T> Receiver<T> {
+    fn recv_deadline(&self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+        loop {
+            match self.try_recv() {
+                Ok(value) => return Ok(value),
+                Err(TryRecvError::Disconnected) => return Err(RecvTimeoutError::Disconnected),
+                Err(TryRecvError::Empty) => {}
+            }
+
+            let wakeups = self.shared.wakeups.load(Ordering::Acquire);
+            self.shared.receiver_waiting.store(true, Ordering::Relaxed);
+            // Pairs with the fence in `wake_receiver`
+            fence(Ordering::SeqCst);
+            let result = match self.try_recv() {
+                Ok(value) => Some(Ok(value)),
+                Err(TryRecvError::Disconnected) => Some(Err(RecvTimeoutError::Disconnected)),
+                Err(TryRecvError::Empty) => match deadline {
+                    None => {
+                        atomic_wait(&self.shared.wakeups, wakeups);
+                        None
+                    }
+                    Some(deadline) => {
+                        let now = Instant::now();
+                        if now >= deadline {
+                            Some(Err(RecvTimeoutError::Timeout))
+                        } else {
+                            atomic_wait_timeout(&self.shared.wakeups, wakeups, deadline - now);
+                            None
+                        }
+                    }
+                },
+            };
+            self.shared.receiver_waiting.store(false, Ordering::Relaxed);
+            if let Some(result) = result {
+                return result;
+            }
+        }
+    }
+}
+
+impl<T> Receive<T> for Receiver<T> {
+    fn recv(&self) -> Result<T, RecvError> {
+        self.recv_deadline(None).map_err(|_| RecvError)
+    }
+
+    fn try_recv(&self) -> Result<T, TryRecvError> {
+        if let Some(value) = self.shared.pop() {
+            return Ok(value);
+        }
+        if self.shared.senders.load(Ordering::Acquire) == 0 {
+            // Values sent before the last sender dropped are visible now
+            return self.shared.pop().ok_or(TryRecvError::Disconnected);
+        }
+        Err(TryRecvError::Empty)
+    }
+
+    fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        self.recv_deadline(Some(Instant::now() + timeout))
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared.receiver.store(false, Ordering::Relaxed);
+    }
+}
+
+impl<T> IntoIterator for Receiver<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T, Self>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter::new(self)
+    }
+}
@@ -0,0 +1,177 @@
+//! A condition variable for [`crate::mutex::Mutex`] that sleeps on [`crate::futex`].
+//!
+//! Every notification bumps `counter`. A waiter reads the counter before it unlocks the mutex and
+//! sleeps only while the counter still holds that value, so a notification sent between the unlock
+//! and the wait is not lost.
+
+use crate::futex::{atomic_wait, atomic_wait_timeout, wake_all, wake_one};
+use crate::mutex::MutexGuard;
+use crate::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::time::Duration;
+
+pub struct Condvar {
+    counter: AtomicU32,
+    num_waiters: AtomicUsize,
+}
+
+impl Condvar {
+    pub fn new() -> Self {
+        Self {
+            counter: AtomicU32::new(0),
+            num_waiters: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn notify_one(&self) {
+        if self.num_waiters.load(Ordering::Relaxed) > 0 {
+            self.counter.fetch_add(1, Ordering::Relaxed);
+            wake_one(&self.counter);
+        }
+    }
+
+    pub fn notify_all(&self) {
+        if self.num_waiters.load(Ordering::Relaxed) > 0 {
+            self.counter.fetch_add(1, Ordering::Relaxed);
+            wake_all(&self.counter);
+        }
+    }
+
+    /// Unlocks the mutex, waits for a notification and locks the mutex again. The wakeup may be
+    /// spurious.
+    pub fn wait<'a, T>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        self.num_waiters.fetch_add(1, Ordering::Relaxed);
+
+        let counter_value = self.counter.load(Ordering::Relaxed);
+        let mutex = guard.mutex;
+        drop(guard);
+        atomic_wait(&self.counter, counter_value);
+
+        self.num_waiters.fetch_sub(1, Ordering::Relaxed);
+        mutex.lock()
+    }
+
+    /// Like `wait` but gives up after `timeout`. Returns `true` if the wait timed out, rather than
+    /// being notified or woken spuriously.
+    pub fn wait_timeout<'a, T>(
+        &self,
+        guard: MutexGuard<'a, T>,
+        timeout: Duration,
+    ) -> (MutexGuard<'a, T>, bool) {
+        self.num_waiters.fetch_add(1, Ordering::Relaxed);
+
+        let counter_value = self.counter.load(Ordering::Relaxed);
+        let mutex = guard.mutex;
+        drop(guard);
+        // Decided before re-locking, so that a slow re-lock after a notify isn't a timeout
+        let timed_out = atomic_wait_timeout(&self.counter, counter_value, timeout);
+
+        self.num_waiters.fetch_sub(1, Ordering::Relaxed);
+        (mutex.lock(), timed_out)
+    }
+
+    /// Waits until `condition` returns `false`.
+    pub fn wait_while<'a, T>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        mut condition: impl FnMut(&mut T) -> bool,
+    ) -> MutexGuard<'a, T> {
+        while condition(&mut guard) {
+            guard = self.wait(guard);
+        }
+        guard
+    }
+}
+
+impl Default for Condvar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(all(test, not(loom), not(pct)))]
+mod tests {
+    use super::*;
+    use crate::mutex::Mutex;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn notify_one_wakes_waiter() {
+        let pair = Arc::new((Mutex::new(false), Condvar::new()));
+
+        let th = {
+            let pair = Arc::clone(&pair);
+            thread::spawn(move || {
+                let (ready, condvar) = &*pair;
+                thread::sleep(Duration::from_millis(10));
+                *ready.lock() = true;
+                condvar.notify_one();
+            })
+        };
+
+        let (ready, condvar) = &*pair;
+        let guard = condvar.wait_while(ready.lock(), |ready| !*ready);
+        assert!(*guard);
+        drop(guard);
+
+        th.join().unwrap();
+    }
+
+    #[test]
+    fn notify_all_wakes_waiters() {
+        let pair = Arc::new((Mutex::new(0), Condvar::new()));
+
+        let waiters: Vec<_> = (0..4)
+            .map(|_| {
+                let pair = Arc::clone(&pair);
+                thread::spawn(move || {
+                    let (started, condvar) = &*pair;
+                    let mut guard = started.lock();
+                    *guard += 1;
+                    drop(condvar.wait_while(guard, |started| *started != 0));
+                })
+            })
+            .collect();
+
+        let (started, condvar) = &*pair;
+        loop {
+            let mut guard = started.lock();
+            if *guard == 4 {
+                *guard = 0;
+                break;
+            }
+            drop(guard);
+            thread::yield_now();
+        }
+        condvar.notify_all();
+
+        for waiter in waiters {
+            waiter.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn wait_timeout_times_out() {
+        let mutex = Mutex::new(());
+        let condvar = Condvar::new();
+        let (_guard, timed_out) = condvar.wait_timeout(mutex.lock(), Duration::from_millis(10));
+        assert!(timed_out);
+    }
+
+    #[test]
+    fn wait_timeout_notified_with_slow_relock() {
+        let mutex = Mutex::new(());
+        let condvar = Condvar::new();
+        let guard = mutex.lock();
+        thread::scope(|s| {
+            s.spawn(|| {
+                // Taken once the waiter has started waiting, and held past its timeout
+                let _guard = mutex.lock();
+                condvar.notify_one();
+                thread::sleep(Duration::from_millis(200));
+            });
+            let (_guard, timed_out) = condvar.wait_timeout(guard, Duration::from_millis(100));
+            assert!(!timed_out);
+        });
+    }
+}
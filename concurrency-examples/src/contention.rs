@@ -0,0 +1,428 @@
+//! Locks that count how often threads wait for them and for how long.
+//!
+//! Each lock has a key, a name given to [`Mutex::named`] or else the place it was created, and
+//! [`report`] adds up the numbers of every lock with the same key: how many times it was taken,
+//! how many of those found it held or already waited for, the total and longest waits, and the
+//! total time it was held. A lock that is taken often with long waits is where threads queue up,
+//! and a long hold time says its critical sections are what to shorten.
+//!
+//! The counters are plain atomics, so recording adds no lock of its own. An acquisition counts as
+//! contended if another thread was holding or waiting for the lock when it started, so waits that
+//! are too short to time still show up. The locks with the same key share one set of counters,
+//! which outlives them, so that the numbers of a lock that was dropped still count, and locks
+//! created over and over, e.g. one per call, don't add up to more memory.
+//!
+//! The locks wrap the [`trace`] ones, so that code profiled here can still be recorded and
+//! replayed.
+
+use crate::trace;
+use std::fmt::{self, Write};
+use std::ops::{Deref, DerefMut};
+use std::panic::Location;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, LockResult, Mutex as StdMutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Every key a lock was ever created with, with the counters of its locks
+static LOCKS: StdMutex<Vec<(Key, Arc<Counters>)>> = StdMutex::new(Vec::new());
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Key {
+    Name(&'static str),
+    CreatedAt(&'static Location<'static>),
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Key::Name(name) => f.write_str(name),
+            Key::CreatedAt(location) => location.fmt(f),
+        }
+    }
+}
+
+/// Times in nanoseconds
+#[derive(Default)]
+struct Counters {
+    /// Locks created with the key
+    locks: AtomicUsize,
+    acquisitions: AtomicU64,
+    contended: AtomicU64,
+    wait: AtomicU64,
+    max_wait: AtomicU64,
+    hold: AtomicU64,
+}
+
+/// The bookkeeping of one lock.
+struct Probe {
+    counters: Arc<Counters>,
+    /// Threads holding or waiting for the lock
+    busy: AtomicUsize,
+}
+
+impl Probe {
+    fn new(key: Key) -> Self {
+        let mut locks = LOCKS.lock().unwrap_or_else(PoisonError::into_inner);
+        let counters = match locks.iter().find(|(k, _)| *k == key) {
+            Some((_, counters)) => Arc::clone(counters),
+            None => {
+                let counters = Arc::<Counters>::default();
+                locks.push((key, Arc::clone(&counters)));
+                counters
+            }
+        };
+        counters.locks.fetch_add(1, Ordering::Relaxed);
+        Self {
+            counters,
+            busy: AtomicUsize::new(0),
+        }
+    }
+
+    /// Called before waiting for the lock; returns when.
+    fn arrive(&self) -> Instant {
+        if self.busy.fetch_add(1, Ordering::Relaxed) > 0 {
+            self.counters.contended.fetch_add(1, Ordering::Relaxed);
+        }
+        Instant::now()
+    }
+
+    /// Called once the lock is taken; returns when.
+    fn acquired(&self, arrived: Instant) -> Instant {
+        let now = Instant::now();
+        let wait = nanos(now - arrived);
+        self.counters.acquisitions.fetch_add(1, Ordering::Relaxed);
+        self.counters.wait.fetch_add(wait, Ordering::Relaxed);
+        self.counters.max_wait.fetch_max(wait, Ordering::Relaxed);
+        now
+    }
+
+    /// Called once the lock is released.
+    fn released(&self, acquired: Instant) {
+        let hold = nanos(acquired.elapsed());
+        self.counters.hold.fetch_add(hold, Ordering::Relaxed);
+        self.busy.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+fn nanos(duration: Duration) -> u64 {
+    duration.as_nanos().try_into().unwrap_or(u64::MAX)
+}
+
+/// `std`'s mutex, which counts its acquisitions and waits.
+pub struct Mutex<T> {
+    probe: Probe,
+    inner: trace::Mutex<T>,
+}
+
+pub struct MutexGuard<'a, T> {
+    mutex: &'a Mutex<T>,
+    acquired: Instant,
+    /// Taken when the guard unlocks
+    inner: Option<trace::MutexGuard<'a, T>>,
+}
+
+impl<T> Mutex<T> {
+    /// A mutex keyed by where it was created.
+    #[track_caller]
+    pub fn new(value: T) -> Self {
+        Self::with_key(Key::CreatedAt(Location::caller()), value)
+    }
+
+    /// A mutex keyed by `name`, whose numbers add up with those of other locks of the same name.
+    pub fn named(name: &'static str, value: T) -> Self {
+        Self::with_key(Key::Name(name), value)
+    }
+
+    fn with_key(key: Key, value: T) -> Self {
+        Self {
+            probe: Probe::new(key),
+            inner: trace::Mutex::new(value),
+        }
+    }
+
+    pub fn lock(&self) -> LockResult<MutexGuard<'_, T>> {
+        let arrived = self.probe.arrive();
+        let result = self.inner.lock();
+        let acquired = self.probe.acquired(arrived);
+        let guard = |inner| MutexGuard {
+            mutex: self,
+            acquired,
+            inner: Some(inner),
+        };
+        result
+            .map(guard)
+            .map_err(|e| PoisonError::new(guard(e.into_inner())))
+    }
+}
+
+impl<T> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.inner.as_ref().unwrap()
+    }
+}
+
+impl<T> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.inner.as_mut().unwrap()
+    }
+}
+
+impl<T> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        drop(self.inner.take());
+        self.mutex.probe.released(self.acquired);
+    }
+}
+
+/// The compare-and-swap spin lock of `memory_ordering`, guarding a value, which counts its
+/// acquisitions and waits.
+pub struct SpinMutex<T> {
+    probe: Probe,
+    lock: trace::atomic::AtomicBool,
+    value: std::cell::UnsafeCell<T>,
+}
+
+// Safety: the lock gives one thread at a time access to the value
+unsafe impl<T: Send> Sync for SpinMutex<T> {}
+
+impl<T> SpinMutex<T> {
+    /// A spin lock keyed by where it was created.
+    #[track_caller]
+    pub fn new(value: T) -> Self {
+        Self::with_key(Key::CreatedAt(Location::caller()), value)
+    }
+
+    /// A spin lock keyed by `name`, whose numbers add up with those of other locks of the same
+    /// name.
+    pub fn named(name: &'static str, value: T) -> Self {
+        Self::with_key(Key::Name(name), value)
+    }
+
+    fn with_key(key: Key, value: T) -> Self {
+        Self {
+            probe: Probe::new(key),
+            lock: trace::atomic::AtomicBool::new(false),
+            value: std::cell::UnsafeCell::new(value),
+        }
+    }
+
+    /// Calls `f` with the value while holding the lock.
+    pub fn with_lock<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        let arrived = self.probe.arrive();
+        let mut acquired = None;
+        let mut result = None;
+        crate::memory_ordering::mutex(&self.lock, || {
+            acquired = Some(self.probe.acquired(arrived));
+            // Safety: the lock is held
+            result = Some(f(unsafe { &mut *self.value.get() }));
+        });
+        self.probe.released(acquired.unwrap());
+        result.unwrap()
+    }
+}
+
+/// The numbers of the locks with one key.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LockStats {
+    /// The name of the locks, or where they were created
+    pub key: String,
+    /// Locks with this key
+    pub locks: usize,
+    pub acquisitions: u64,
+    /// Acquisitions that started while another thread held the lock or waited for it
+    pub contended: u64,
+    pub wait: Duration,
+    pub max_wait: Duration,
+    pub hold: Duration,
+}
+
+/// The numbers of every key, most waited for first.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Report {
+    pub locks: Vec<LockStats>,
+}
+
+/// Adds up the numbers of every lock so far by key.
+pub fn report() -> Report {
+    let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+    let locks = LOCKS.lock().unwrap_or_else(PoisonError::into_inner);
+    let mut stats: Vec<_> = locks
+        .iter()
+        .map(|(key, counters)| LockStats {
+            key: key.to_string(),
+            locks: counters.locks.load(Ordering::Relaxed),
+            acquisitions: load(&counters.acquisitions),
+            contended: load(&counters.contended),
+            wait: Duration::from_nanos(load(&counters.wait)),
+            max_wait: Duration::from_nanos(load(&counters.max_wait)),
+            hold: Duration::from_nanos(load(&counters.hold)),
+        })
+        .collect();
+    stats.sort_by(|a, b| b.wait.cmp(&a.wait).then_with(|| a.key.cmp(&b.key)));
+    Report { locks: stats }
+}
+
+impl Report {
+    /// The report as a JSON array with an object per key, and times in nanoseconds.
+    pub fn to_json(&self) -> String {
+        let mut json = String::from("[");
+        for (i, s) in self.locks.iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+            json.push_str("{\"key\":\"");
+            for c in s.key.chars() {
+                match c {
+                    '"' => json.push_str("\\\""),
+                    '\\' => json.push_str("\\\\"),
+                    c if c < ' ' => write!(json, "\\u{:04x}", c as u32).unwrap(),
+                    c => json.push(c),
+                }
+            }
+            write!(
+                json,
+                "\",\"locks\":{},\"acquisitions\":{},\"contended\":{},\"wait_ns\":{},\
+                 \"max_wait_ns\":{},\"hold_ns\":{}}}",
+                s.locks,
+                s.acquisitions,
+                s.contended,
+                s.wait.as_nanos(),
+                s.max_wait.as_nanos(),
+                s.hold.as_nanos()
+            )
+            .unwrap();
+        }
+        json.push(']');
+        json
+    }
+}
+
+/// A table with a row per key.
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let width = self.locks.iter().map(|s| s.key.len()).fold(4, usize::max);
+        write!(
+            f,
+            "{:<width$}  {:>5}  {:>12}  {:>9}  {:>10}  {:>10}  {:>10}",
+            "lock", "locks", "acquisitions", "contended", "total wait", "max wait", "total hold"
+        )?;
+        for s in &self.locks {
+            let time = |duration: Duration| format!("{duration:.3?}");
+            write!(
+                f,
+                "\n{:<width$}  {:>5}  {:>12}  {:>9}  {:>10}  {:>10}  {:>10}",
+                s.key,
+                s.locks,
+                s.acquisitions,
+                s.contended,
+                time(s.wait),
+                time(s.max_wait),
+                time(s.hold)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, not(loom), not(pct)))]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    fn stats(key: &str) -> LockStats {
+        let mut locks = report()
+            .locks
+            .into_iter()
+            .filter(|s| s.key.starts_with(key));
+        locks.next().unwrap()
+    }
+
+    #[test]
+    fn counts_uncontended_acquisitions() {
+        let mutex = Mutex::named("contention::tests::uncontended", 0);
+        for _ in 0..10 {
+            *mutex.lock().unwrap() += 1;
+        }
+        let stats = stats("contention::tests::uncontended");
+        assert_eq!(
+            (stats.locks, stats.acquisitions, stats.contended),
+            (1, 10, 0)
+        );
+        assert!(stats.max_wait <= stats.wait);
+    }
+
+    #[test]
+    fn counts_waits_for_a_held_lock() {
+        let mutex = Mutex::named("contention::tests::held", ());
+        let spin = SpinMutex::named("contention::tests::held_spin", ());
+        thread::scope(|s| {
+            let guard = mutex.lock().unwrap();
+            spin.with_lock(|_| {
+                s.spawn(|| drop(mutex.lock().unwrap()));
+                s.spawn(|| spin.with_lock(|_| ()));
+                thread::sleep(Duration::from_millis(20));
+            });
+            drop(guard);
+        });
+        for key in ["contention::tests::held", "contention::tests::held_spin"] {
+            let stats = report().locks.into_iter().find(|s| s.key == key).unwrap();
+            assert_eq!((stats.acquisitions, stats.contended), (2, 1), "{key}");
+            assert!(stats.max_wait >= Duration::from_millis(10), "{key}");
+            assert!(stats.hold >= Duration::from_millis(10), "{key}");
+        }
+    }
+
+    #[test]
+    fn adds_up_locks_with_the_same_key() {
+        let line = line!() + 1;
+        let locks: Vec<_> = (0..3).map(|_| Mutex::new(())).collect();
+        for lock in &locks {
+            drop(lock.lock().unwrap());
+        }
+        let stats = stats(&format!("{}:{line}:", file!()));
+        assert_eq!((stats.locks, stats.acquisitions), (3, 3));
+    }
+
+    #[test]
+    fn keeps_one_entry_per_key() {
+        let key = "contention::tests::per_call";
+        for _ in 0..100 {
+            drop(Mutex::named(key, ()).lock().unwrap());
+        }
+        let locks = LOCKS.lock().unwrap();
+        assert_eq!(
+            locks.iter().filter(|(k, _)| *k == Key::Name(key)).count(),
+            1
+        );
+        drop(locks);
+        let stats = stats(key);
+        assert_eq!((stats.locks, stats.acquisitions), (100, 100));
+    }
+
+    #[test]
+    fn reports_a_table_and_json() {
+        let report = Report {
+            locks: vec![LockStats {
+                key: "a \"quoted\" name".to_string(),
+                locks: 2,
+                acquisitions: 10,
+                contended: 3,
+                wait: Duration::from_micros(1500),
+                max_wait: Duration::from_micros(1000),
+                hold: Duration::from_nanos(250),
+            }],
+        };
+        let table = [
+            "lock             locks  acquisitions  contended  total wait    max wait  total hold",
+            "a \"quoted\" name      2            10          3     1.500ms     1.000ms   250.000ns",
+        ];
+        assert_eq!(report.to_string(), table.join("\n"));
+        assert_eq!(
+            report.to_json(),
+            r#"[{"key":"a \"quoted\" name","locks":2,"acquisitions":10,"contended":3,"#.to_string()
+                + r#""wait_ns":1500000,"max_wait_ns":1000000,"hold_ns":250}]"#
+        );
+    }
+}
@@ -0,0 +1,515 @@
+//! Lock-order checking, which finds deadlocks that could happen, not just ones that do.
+//!
+//! Two threads deadlock when each holds a lock the other waits for, but whether they do in a given
+//! run depends on timing: a thread that takes `a` then `b` and one that takes `b` then `a` only get
+//! stuck if their critical sections overlap. The locks here record, for every lock a thread takes
+//! while holding others, an edge from each held lock to the new one in a graph shared by all
+//! threads. An edge that closes a cycle means some interleaving of the threads that took those
+//! locks deadlocks, even if this one didn't, so it is printed to stderr as a [`Cycle`] and kept
+//! for [`cycles`].
+//!
+//! Each edge keeps a backtrace of where the second lock was taken and the location where the
+//! first one was, captured when the edge is first seen; repeats of a known edge only cost a look
+//! up. A read lock counts as taking the lock, as a waiting writer can make a reader wait on
+//! another reader. A `try_lock` that succeeds doesn't wait, so it adds no edges, but later locks
+//! taken while it is held do. A dropped lock leaves the graph, along with its edges.
+
+use crate::trace::atomic::{AtomicBool, Ordering};
+use std::backtrace::Backtrace;
+use std::cell::{RefCell, UnsafeCell};
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::panic::Location;
+use std::sync::atomic::AtomicUsize;
+use std::sync::{self, Arc, LockResult, PoisonError, TryLockError, TryLockResult};
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+static GRAPH: sync::Mutex<Graph> = sync::Mutex::new(Graph {
+    locks: BTreeMap::new(),
+    cycles: Vec::new(),
+});
+
+thread_local! {
+    /// The locks the current thread holds, in the order it took them
+    static HELD: RefCell<Vec<(LockId, &'static Location<'static>)>> =
+        const { RefCell::new(Vec::new()) };
+}
+
+/// Identifies a lock in a [`Cycle`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct LockId {
+    id: usize,
+    created_at: &'static Location<'static>,
+}
+
+impl LockId {
+    /// Where the lock was created.
+    pub fn created_at(&self) -> &'static Location<'static> {
+        self.created_at
+    }
+}
+
+impl fmt::Display for LockId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "lock #{} (created at {})", self.id, self.created_at)
+    }
+}
+
+/// A thread took `taken` while holding `held`.
+#[derive(Clone, Debug)]
+pub struct Edge {
+    pub held: LockId,
+    pub held_at: &'static Location<'static>,
+    pub taken: LockId,
+    pub taken_at: &'static Location<'static>,
+    /// Where `taken` was taken, the first time it was while holding `held`
+    pub backtrace: Arc<Backtrace>,
+}
+
+/// Locks that threads took in an order that can deadlock: each edge's `taken` is the next one's
+/// `held`, and the last one's is the first one's.
+#[derive(Clone, Debug)]
+pub struct Cycle {
+    pub edges: Vec<Edge>,
+}
+
+impl Cycle {
+    pub fn contains(&self, lock: LockId) -> bool {
+        self.edges.iter().any(|edge| edge.held == lock)
+    }
+}
+
+impl fmt::Display for Cycle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "potential deadlock: locks taken in a cycle")?;
+        for edge in &self.edges {
+            write!(
+                f,
+                "\n\n{} taken at {} while holding {} taken at {}, from:\n{}",
+                edge.taken, edge.taken_at, edge.held, edge.held_at, edge.backtrace
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// The cycles found so far, in the order they were found.
+pub fn cycles() -> Vec<Cycle> {
+    graph().cycles.clone()
+}
+
+struct Graph {
+    /// The live locks, each with the edges to the locks taken while holding it
+    locks: BTreeMap<LockId, BTreeMap<LockId, Edge>>,
+    cycles: Vec<Cycle>,
+}
+
+impl Graph {
+    /// The edges of a shortest path from `from` to `to`, or `None` if there isn't one.
+    fn path(&self, from: LockId, to: LockId) -> Option<Vec<Edge>> {
+        let mut parents = BTreeMap::new();
+        let mut queue = VecDeque::from([from]);
+        while let Some(lock) = queue.pop_front() {
+            if lock == to {
+                let mut path = Vec::new();
+                let mut lock = to;
+                while lock != from {
+                    let parent = parents[&lock];
+                    path.push(self.locks[&parent][&lock].clone());
+                    lock = parent;
+                }
+                path.reverse();
+                return Some(path);
+            }
+            for &next in self.locks[&lock].keys() {
+                if next != from && !parents.contains_key(&next) {
+                    parents.insert(next, lock);
+                    queue.push_back(next);
+                }
+            }
+        }
+        None
+    }
+}
+
+// Poisoned only if a panic interrupted an update, which leaves the graph missing at most an edge
+fn graph() -> sync::MutexGuard<'static, Graph> {
+    GRAPH.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// A lock's place in the graph, which it leaves when dropped.
+struct Node(LockId);
+
+impl Node {
+    #[track_caller]
+    fn new() -> Self {
+        let id = LockId {
+            id: NEXT_ID.fetch_add(1, std::sync::atomic::Ordering::Relaxed),
+            created_at: Location::caller(),
+        };
+        graph().locks.insert(id, BTreeMap::new());
+        Self(id)
+    }
+
+    /// Adds an edge to this lock from each one the thread holds, before it waits for this one.
+    fn before_lock(&self, at: &'static Location<'static>) {
+        let held = HELD.with(|held| held.borrow().clone());
+        if held.is_empty() {
+            return;
+        }
+        let mut graph = graph();
+        for (lock, held_at) in held {
+            if graph.locks[&lock].contains_key(&self.0) {
+                continue;
+            }
+            let edge = Edge {
+                held: lock,
+                held_at,
+                taken: self.0,
+                taken_at: at,
+                backtrace: Arc::new(Backtrace::force_capture()),
+            };
+            if let Some(path) = graph.path(self.0, lock) {
+                let cycle = Cycle {
+                    edges: [edge.clone()].into_iter().chain(path).collect(),
+                };
+                eprintln!("{cycle}");
+                graph.cycles.push(cycle);
+            }
+            graph.locks.get_mut(&lock).unwrap().insert(self.0, edge);
+        }
+    }
+
+    /// Marks this lock as held by the thread until the returned [`Held`] drops.
+    fn locked(&self, at: &'static Location<'static>) -> Held {
+        HELD.with(|held| held.borrow_mut().push((self.0, at)));
+        Held(self.0)
+    }
+}
+
+impl Drop for Node {
+    fn drop(&mut self) {
+        let mut graph = graph();
+        graph.locks.remove(&self.0);
+        for edges in graph.locks.values_mut() {
+            edges.remove(&self.0);
+        }
+    }
+}
+
+/// Removes a lock from the thread's held locks when dropped.
+struct Held(LockId);
+
+impl Drop for Held {
+    fn drop(&mut self) {
+        // Guards can be dropped in any order. The thread-local is gone if this runs while the
+        // thread exits, in which case nothing is held any more anyway.
+        let _ = HELD.try_with(|held| {
+            let mut held = held.borrow_mut();
+            if let Some(i) = held.iter().rposition(|&(lock, _)| lock == self.0) {
+                held.remove(i);
+            }
+        });
+    }
+}
+
+/// `std`'s mutex, which adds to the lock-order graph.
+pub struct Mutex<T> {
+    node: Node,
+    inner: sync::Mutex<T>,
+}
+
+pub struct MutexGuard<'a, T> {
+    // Declared first so that it unlocks before the lock stops counting as held
+    inner: sync::MutexGuard<'a, T>,
+    _held: Held,
+}
+
+impl<T> Mutex<T> {
+    #[track_caller]
+    pub fn new(value: T) -> Self {
+        Self {
+            node: Node::new(),
+            inner: sync::Mutex::new(value),
+        }
+    }
+
+    pub fn id(&self) -> LockId {
+        self.node.0
+    }
+
+    #[track_caller]
+    pub fn lock(&self) -> LockResult<MutexGuard<'_, T>> {
+        let at = Location::caller();
+        self.node.before_lock(at);
+        let result = self.inner.lock();
+        let _held = self.node.locked(at);
+        match result {
+            Ok(inner) => Ok(MutexGuard { inner, _held }),
+            Err(e) => Err(PoisonError::new(MutexGuard {
+                inner: e.into_inner(),
+                _held,
+            })),
+        }
+    }
+
+    #[track_caller]
+    pub fn try_lock(&self) -> TryLockResult<MutexGuard<'_, T>> {
+        let at = Location::caller();
+        match self.inner.try_lock() {
+            Ok(inner) => Ok(MutexGuard {
+                inner,
+                _held: self.node.locked(at),
+            }),
+            Err(TryLockError::Poisoned(e)) => {
+                Err(TryLockError::Poisoned(PoisonError::new(MutexGuard {
+                    inner: e.into_inner(),
+                    _held: self.node.locked(at),
+                })))
+            }
+            Err(TryLockError::WouldBlock) => Err(TryLockError::WouldBlock),
+        }
+    }
+}
+
+impl<T> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.inner
+    }
+}
+
+impl<T> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+}
+
+/// `std`'s reader-writer lock, which adds to the lock-order graph.
+pub struct RwLock<T> {
+    node: Node,
+    inner: sync::RwLock<T>,
+}
+
+pub struct RwLockReadGuard<'a, T> {
+    inner: sync::RwLockReadGuard<'a, T>,
+    _held: Held,
+}
+
+pub struct RwLockWriteGuard<'a, T> {
+    inner: sync::RwLockWriteGuard<'a, T>,
+    _held: Held,
+}
+
+impl<T> RwLock<T> {
+    #[track_caller]
+    pub fn new(value: T) -> Self {
+        Self {
+            node: Node::new(),
+            inner: sync::RwLock::new(value),
+        }
+    }
+
+    pub fn id(&self) -> LockId {
+        self.node.0
+    }
+
+    #[track_caller]
+    pub fn read(&self) -> LockResult<RwLockReadGuard<'_, T>> {
+        let at = Location::caller();
+        self.node.before_lock(at);
+        let result = self.inner.read();
+        let _held = self.node.locked(at);
+        match result {
+            Ok(inner) => Ok(RwLockReadGuard { inner, _held }),
+            Err(e) => Err(PoisonError::new(RwLockReadGuard {
+                inner: e.into_inner(),
+                _held,
+            })),
+        }
+    }
+
+    #[track_caller]
+    pub fn write(&self) -> LockResult<RwLockWriteGuard<'_, T>> {
+        let at = Location::caller();
+        self.node.before_lock(at);
+        let result = self.inner.write();
+        let _held = self.node.locked(at);
+        match result {
+            Ok(inner) => Ok(RwLockWriteGuard { inner, _held }),
+            Err(e) => Err(PoisonError::new(RwLockWriteGuard {
+                inner: e.into_inner(),
+                _held,
+            })),
+        }
+    }
+}
+
+impl<T> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.inner
+    }
+}
+
+impl<T> Deref for RwLockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.inner
+    }
+}
+
+impl<T> DerefMut for RwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+}
+
+/// The spin lock of `memory_ordering`, guarding a value, which adds to the lock-order graph.
+pub struct SpinMutex<T> {
+    node: Node,
+    lock: AtomicBool,
+    value: UnsafeCell<T>,
+}
+
+// Safety: the lock gives one thread at a time access to the value
+unsafe impl<T: Send> Sync for SpinMutex<T> {}
+
+impl<T> SpinMutex<T> {
+    #[track_caller]
+    pub fn new(value: T) -> Self {
+        Self {
+            node: Node::new(),
+            lock: AtomicBool::new(false),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn id(&self) -> LockId {
+        self.node.0
+    }
+
+    /// Calls `f` with the value while holding the lock.
+    #[track_caller]
+    pub fn with_lock<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        let at = Location::caller();
+        self.node.before_lock(at);
+        let mut result = None;
+        crate::memory_ordering::mutex(&self.lock, || {
+            let _held = self.node.locked(at);
+            // Safety: the lock is held
+            result = Some(f(unsafe { &mut *self.value.get() }));
+        });
+        result.unwrap()
+    }
+
+    pub fn into_inner(self) -> T {
+        debug_assert!(!self.lock.load(Ordering::Relaxed));
+        self.value.into_inner()
+    }
+}
+
+#[cfg(all(test, not(loom), not(pct)))]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    fn cycles_with(lock: LockId) -> Vec<Cycle> {
+        cycles().into_iter().filter(|c| c.contains(lock)).collect()
+    }
+
+    #[test]
+    fn reports_opposite_orders_that_did_not_deadlock() {
+        let (a, b) = (Mutex::new(()), Mutex::new(()));
+        // One thread after the other, so they can't deadlock in this run
+        thread::scope(|s| {
+            s.spawn(|| {
+                let _a = a.lock().unwrap();
+                let _b = b.lock().unwrap();
+            });
+        });
+        assert!(cycles_with(a.id()).is_empty());
+        thread::scope(|s| {
+            s.spawn(|| {
+                let _b = b.lock().unwrap();
+                let _a = a.lock().unwrap();
+            });
+        });
+
+        let cycles = cycles_with(a.id());
+        assert_eq!(cycles.len(), 1);
+        let edges = &cycles[0].edges;
+        assert_eq!((edges[0].held, edges[0].taken), (b.id(), a.id()));
+        assert_eq!((edges[1].held, edges[1].taken), (a.id(), b.id()));
+        let report = cycles[0].to_string();
+        assert!(report.starts_with("potential deadlock"), "{report}");
+        assert!(report.contains(file!()), "{report}");
+    }
+
+    #[test]
+    fn consistent_order_is_not_reported() {
+        let locks = [Mutex::new(0), Mutex::new(0), Mutex::new(0)];
+        thread::scope(|s| {
+            for t in 0..3 {
+                let locks = &locks;
+                s.spawn(move || {
+                    for i in 0..100 {
+                        // Any subset, but always in the same order
+                        let _guards: Vec<_> = (0..3)
+                            .filter(|&l| (i + t) & (1 << l) != 0)
+                            .map(|l| locks[l].lock().unwrap())
+                            .collect();
+                    }
+                });
+            }
+        });
+        for lock in &locks {
+            assert!(cycles_with(lock.id()).is_empty());
+        }
+    }
+
+    #[test]
+    fn released_locks_order_nothing() {
+        let (a, b) = (Mutex::new(()), Mutex::new(()));
+        drop(a.lock().unwrap());
+        drop(b.lock().unwrap());
+        let _b = b.lock().unwrap();
+        let _a = a.lock().unwrap();
+        assert!(cycles_with(a.id()).is_empty());
+    }
+
+    #[test]
+    fn reports_cycles_across_lock_types() {
+        let (x, y, z) = (RwLock::new(0), SpinMutex::new(0), Mutex::new(0));
+        let read = x.read().unwrap();
+        y.with_lock(|y| *y += *read);
+        drop(read);
+        y.with_lock(|y| *z.lock().unwrap() += *y);
+        assert!(cycles_with(x.id()).is_empty());
+        let value = *z.lock().unwrap();
+        let _z = z.lock().unwrap();
+        *x.write().unwrap() += value;
+
+        let cycles = cycles_with(x.id());
+        assert_eq!(cycles.len(), 1);
+        let held: Vec<_> = cycles[0].edges.iter().map(|edge| edge.held).collect();
+        assert_eq!(held, [z.id(), x.id(), y.id()]);
+    }
+
+    #[test]
+    fn dropped_locks_leave_the_graph() {
+        let a = Mutex::new(());
+        let b = Mutex::new(());
+        drop((a.lock().unwrap(), b.lock().unwrap()));
+        assert!(graph().locks[&a.id()].contains_key(&b.id()));
+        let b_id = b.id();
+        drop(b);
+        assert!(!graph().locks.contains_key(&b_id));
+        assert!(graph().locks[&a.id()].is_empty());
+    }
+}
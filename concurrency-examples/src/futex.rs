@@ -0,0 +1,218 @@
+//! Blocking wait and wake on an `AtomicU32`.
+//!
+//! `atomic_wait` blocks the calling thread for as long as the atomic holds `expected`, and
+//! `wake_one`/`wake_all` wake threads blocked on the same atomic. A wait may return spuriously, so
+//! callers always re-check the value in a loop. On Linux this is the `futex` syscall. Elsewhere it
+//! falls back to the [`parking`] lot: a fixed table of `std` mutex and condvar pairs keyed by the
+//! address of the atomic. Under loom or pct a wait is a yield, which both treat as a spurious
+//! wakeup.
+
+use crate::sync::atomic::AtomicU32;
+use std::time::Duration;
+
+/// Blocks while `a` holds `expected`.
+pub fn atomic_wait(a: &AtomicU32, expected: u32) {
+    imp::wait(a, expected, None);
+}
+
+/// Blocks while `a` holds `expected`, for at most `timeout`. Returns `true` if it gave up because
+/// the timeout passed, rather than returning early or being woken.
+pub fn atomic_wait_timeout(a: &AtomicU32, expected: u32, timeout: Duration) -> bool {
+    imp::wait(a, expected, Some(timeout))
+}
+
+/// Wakes at least one thread blocked on `a`, if there is any.
+pub fn wake_one(a: &AtomicU32) {
+    imp::wake(a, 1);
+}
+
+/// Wakes all threads blocked on `a`.
+pub fn wake_all(a: &AtomicU32) {
+    imp::wake(a, i32::MAX);
+}
+
+#[cfg(all(target_os = "linux", not(loom), not(pct)))]
+mod imp {
+    use std::sync::atomic::AtomicU32;
+    use std::time::Duration;
+
+    pub fn wait(a: &AtomicU32, expected: u32, timeout: Option<Duration>) -> bool {
+        let timespec = timeout.map(|t| libc::timespec {
+            tv_sec: t.as_secs().min(libc::time_t::MAX as u64) as libc::time_t,
+            tv_nsec: t.subsec_nanos() as _,
+        });
+        let timespec_ptr = timespec
+            .as_ref()
+            .map_or(std::ptr::null(), |t| t as *const libc::timespec);
+        // The kernel re-checks `*a == expected` atomically with going to sleep. An `EAGAIN`,
+        // `EINTR` or `ETIMEDOUT` error is just an early return.
+        let result = unsafe {
+            libc::syscall(
+                libc::SYS_futex,
+                a as *const AtomicU32,
+                libc::FUTEX_WAIT | libc::FUTEX_PRIVATE_FLAG,
+                expected,
+                timespec_ptr,
+            )
+        };
+        result == -1 && std::io::Error::last_os_error().raw_os_error() == Some(libc::ETIMEDOUT)
+    }
+
+    pub fn wake(a: &AtomicU32, n: i32) {
+        unsafe {
+            libc::syscall(
+                libc::SYS_futex,
+                a as *const AtomicU32,
+                libc::FUTEX_WAKE | libc::FUTEX_PRIVATE_FLAG,
+                n,
+            );
+        }
+    }
+}
+
+#[cfg(all(not(target_os = "linux"), not(loom), not(pct)))]
+mod imp {
+    pub use super::parking::{wait, wake};
+}
+
+#[cfg(any(loom, pct))]
+mod imp {
+    use crate::sync::{
+        atomic::{AtomicU32, Ordering},
+        thread,
+    };
+    use std::time::Duration;
+
+    pub fn wait(a: &AtomicU32, expected: u32, _timeout: Option<Duration>) -> bool {
+        if a.load(Ordering::Relaxed) == expected {
+            thread::yield_now();
+        }
+        false
+    }
+
+    pub fn wake(_a: &AtomicU32, _n: i32) {}
+}
+
+/// Portable fallback for platforms without a `futex`-like syscall.
+///
+/// Waiters on different atomics can share a bucket, so a wake notifies every thread in the bucket
+/// and the ones waiting on another address see a spurious wakeup.
+#[cfg(not(loom))]
+pub mod parking {
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::{Condvar, Mutex};
+    use std::time::Duration;
+
+    const BUCKETS: usize = 64;
+
+    struct Bucket {
+        mutex: Mutex<()>,
+        condvar: Condvar,
+    }
+
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY_BUCKET: Bucket = Bucket {
+        mutex: Mutex::new(()),
+        condvar: Condvar::new(),
+    };
+
+    static TABLE: [Bucket; BUCKETS] = [EMPTY_BUCKET; BUCKETS];
+
+    fn bucket(a: &AtomicU32) -> &'static Bucket {
+        &TABLE[(a as *const AtomicU32 as usize >> 2) % BUCKETS]
+    }
+
+    pub fn wait(a: &AtomicU32, expected: u32, timeout: Option<Duration>) -> bool {
+        let bucket = bucket(a);
+        let guard = bucket.mutex.lock().unwrap();
+        // A waker changes the value before it takes the bucket lock, so checking under the lock
+        // cannot miss a wakeup.
+        if a.load(Ordering::SeqCst) != expected {
+            return false;
+        }
+        match timeout {
+            Some(timeout) => bucket
+                .condvar
+                .wait_timeout(guard, timeout)
+                .unwrap()
+                .1
+                .timed_out(),
+            None => {
+                drop(bucket.condvar.wait(guard).unwrap());
+                false
+            }
+        }
+    }
+
+    pub fn wake(a: &AtomicU32, _n: i32) {
+        let bucket = bucket(a);
+        let _guard = bucket.mutex.lock().unwrap();
+        bucket.condvar.notify_all();
+    }
+}
+
+#[cfg(all(test, not(loom), not(pct)))]
+mod tests {
+    use super::*;
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Instant;
+
+    fn wait_then_wake(
+        wait: fn(&AtomicU32, u32, Option<Duration>) -> bool,
+        wake: fn(&AtomicU32, i32),
+    ) {
+        let a = Arc::new(AtomicU32::new(0));
+
+        let waiters: Vec<_> = (0..4)
+            .map(|_| {
+                let a = Arc::clone(&a);
+                thread::spawn(move || {
+                    while a.load(Ordering::Acquire) == 0 {
+                        wait(&a, 0, None);
+                    }
+                })
+            })
+            .collect();
+
+        thread::sleep(Duration::from_millis(10));
+        a.store(1, Ordering::Release);
+        wake(&a, i32::MAX);
+
+        for waiter in waiters {
+            waiter.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn wake_all_wakes_waiters() {
+        wait_then_wake(imp::wait, imp::wake);
+    }
+
+    #[test]
+    fn parking_wake_all_wakes_waiters() {
+        wait_then_wake(parking::wait, parking::wake);
+    }
+
+    #[test]
+    fn wait_returns_if_value_differs() {
+        let a = AtomicU32::new(1);
+        atomic_wait(&a, 0);
+        parking::wait(&a, 0, None);
+    }
+
+    #[test]
+    fn wait_times_out() {
+        let a = AtomicU32::new(0);
+        let start = Instant::now();
+        assert!(atomic_wait_timeout(&a, 0, Duration::from_millis(10)));
+        assert!(parking::wait(&a, 0, Some(Duration::from_millis(10))));
+        assert!(start.elapsed() >= Duration::from_millis(10));
+        assert!(!atomic_wait_timeout(
+            &AtomicU32::new(1),
+            0,
+            Duration::from_millis(10)
+        ));
+    }
+}
@@ -0,0 +1,272 @@
+//! Concurrent hash maps.
+//!
+//! [`striped`] splits the map into shards, each a `HashMap` behind [`crate::rwlock::RwLock`], the
+//! way `DashMap` does. Lookups in [`lock_free`] never block: its buckets are linked lists that
+//! writers change with atomic stores while holding one of a set of stripe locks, and removed nodes
+//! are reclaimed with [`crate::reclaim::Epoch`].
+//!
+//! The [`ConcurrentMap`] trait covers what the two maps and `DashMap` have in common, so that the
+//! same tests and benchmarks run against all three. It is also implemented for a `HashMap` behind
+//! `std`'s `Mutex` and `RwLock`, as baselines.
+
+pub mod lock_free;
+pub mod striped;
+
+use dashmap::DashMap;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::{Mutex, RwLock};
+
+pub trait ConcurrentMap<K, V>: Default + Send + Sync {
+    /// Returns a copy of the value for `key`.
+    fn get(&self, key: &K) -> Option<V>;
+
+    /// Inserts a value, returning the one it replaces.
+    fn insert(&self, key: K, value: V) -> Option<V>;
+
+    fn remove(&self, key: &K) -> Option<V>;
+
+    /// Replaces the value for `key` with `f` of it, or inserts `value` if there is none, atomically.
+    fn upsert(&self, key: K, value: V, f: impl FnOnce(&V) -> V);
+
+    /// Removes the entries for which `f` returns `false`.
+    fn retain(&self, f: impl FnMut(&K, &V) -> bool);
+
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Copies the entries out, in no particular order.
+    fn entries(&self) -> Vec<(K, V)>;
+}
+
+impl<K, V> ConcurrentMap<K, V> for DashMap<K, V>
+where
+    K: Hash + Eq + Clone + Send + Sync,
+    V: Clone + Send + Sync,
+{
+    fn get(&self, key: &K) -> Option<V> {
+        DashMap::get(self, key).map(|v| v.clone())
+    }
+
+    fn insert(&self, key: K, value: V) -> Option<V> {
+        DashMap::insert(self, key, value)
+    }
+
+    fn remove(&self, key: &K) -> Option<V> {
+        DashMap::remove(self, key).map(|(_, v)| v)
+    }
+
+    fn upsert(&self, key: K, value: V, f: impl FnOnce(&V) -> V) {
+        self.entry(key).and_modify(|v| *v = f(v)).or_insert(value);
+    }
+
+    fn retain(&self, mut f: impl FnMut(&K, &V) -> bool) {
+        DashMap::retain(self, |k, v| f(k, v))
+    }
+
+    fn len(&self) -> usize {
+        DashMap::len(self)
+    }
+
+    fn entries(&self) -> Vec<(K, V)> {
+        self.iter()
+            .map(|e| (e.key().clone(), e.value().clone()))
+            .collect()
+    }
+}
+
+impl<K, V> ConcurrentMap<K, V> for Mutex<HashMap<K, V>>
+where
+    K: Hash + Eq + Clone + Send + Sync,
+    V: Clone + Send + Sync,
+{
+    fn get(&self, key: &K) -> Option<V> {
+        self.lock().unwrap().get(key).cloned()
+    }
+
+    fn insert(&self, key: K, value: V) -> Option<V> {
+        self.lock().unwrap().insert(key, value)
+    }
+
+    fn remove(&self, key: &K) -> Option<V> {
+        self.lock().unwrap().remove(key)
+    }
+
+    fn upsert(&self, key: K, value: V, f: impl FnOnce(&V) -> V) {
+        self.lock()
+            .unwrap()
+            .entry(key)
+            .and_modify(|v| *v = f(v))
+            .or_insert(value);
+    }
+
+    fn retain(&self, mut f: impl FnMut(&K, &V) -> bool) {
+        self.lock().unwrap().retain(|k, v| f(k, v))
+    }
+
+    fn len(&self) -> usize {
+        self.lock().unwrap().len()
+    }
+
+    fn entries(&self) -> Vec<(K, V)> {
+        let map = self.lock().unwrap();
+        map.iter().map(|(k, v)| (k.clone(), v.clone())).collect()
+    }
+}
+
+impl<K, V> ConcurrentMap<K, V> for RwLock<HashMap<K, V>>
+where
+    K: Hash + Eq + Clone + Send + Sync,
+    V: Clone + Send + Sync,
+{
+    fn get(&self, key: &K) -> Option<V> {
+        self.read().unwrap().get(key).cloned()
+    }
+
+    fn insert(&self, key: K, value: V) -> Option<V> {
+        self.write().unwrap().insert(key, value)
+    }
+
+    fn remove(&self, key: &K) -> Option<V> {
+        self.write().unwrap().remove(key)
+    }
+
+    fn upsert(&self, key: K, value: V, f: impl FnOnce(&V) -> V) {
+        self.write()
+            .unwrap()
+            .entry(key)
+            .and_modify(|v| *v = f(v))
+            .or_insert(value);
+    }
+
+    fn retain(&self, mut f: impl FnMut(&K, &V) -> bool) {
+        self.write().unwrap().retain(|k, v| f(k, v))
+    }
+
+    fn len(&self) -> usize {
+        self.read().unwrap().len()
+    }
+
+    fn entries(&self) -> Vec<(K, V)> {
+        let map = self.read().unwrap();
+        map.iter().map(|(k, v)| (k.clone(), v.clone())).collect()
+    }
+}
+
+/// The same tests for every map, with `DashMap` as the reference.
+#[cfg(all(test, not(loom), not(pct)))]
+mod tests {
+    use super::*;
+    use crate::linearizability::models::{Map, MapOp};
+    use crate::linearizability::{assert_linearizable, map_op, programs};
+    use proptest::prelude::*;
+    use std::thread;
+
+    type Striped = striped::ConcurrentHashMap<usize, usize>;
+    type LockFree = lock_free::ConcurrentHashMap<usize, usize>;
+
+    fn insert_get_remove<M: ConcurrentMap<usize, usize>>() {
+        let map = M::default();
+        assert!(map.is_empty());
+        for k in 0..100 {
+            assert_eq!(map.insert(k, k), None);
+        }
+        assert_eq!(map.insert(7, 70), Some(7));
+        assert_eq!(map.get(&7), Some(70));
+        assert_eq!(map.remove(&8), Some(8));
+        assert_eq!(map.remove(&8), None);
+        assert_eq!(map.get(&8), None);
+        assert_eq!(map.len(), 99);
+    }
+
+    fn entries_lists_everything<M: ConcurrentMap<usize, usize>>() {
+        let map = M::default();
+        for k in 0..1_000 {
+            map.insert(k, k * 2);
+        }
+        let mut entries = map.entries();
+        entries.sort();
+        assert_eq!(entries, (0..1_000).map(|k| (k, k * 2)).collect::<Vec<_>>());
+    }
+
+    fn retain_keeps_matching<M: ConcurrentMap<usize, usize>>() {
+        let map = M::default();
+        for k in 0..1_000 {
+            map.insert(k, k);
+        }
+        map.retain(|k, v| k % 3 == 0 && *v < 900);
+        assert_eq!(map.len(), 300);
+        assert!((0..1_000).all(|k| map.get(&k).is_some() == (k % 3 == 0 && k < 900)));
+    }
+
+    fn upsert_counts_every_update<M: ConcurrentMap<usize, usize>>() {
+        let map = M::default();
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for i in 0..10_000 {
+                        map.upsert(i % 64, 1, |v| v + 1);
+                    }
+                });
+            }
+        });
+        assert_eq!(map.len(), 64);
+        assert_eq!(map.entries().iter().map(|(_, v)| v).sum::<usize>(), 40_000);
+    }
+
+    fn concurrent_inserts_and_removes<M: ConcurrentMap<usize, usize>>() {
+        let map = M::default();
+        thread::scope(|s| {
+            for t in 0..4 {
+                let map = &map;
+                s.spawn(move || {
+                    let keys = t * 10_000..(t + 1) * 10_000;
+                    for k in keys.clone() {
+                        map.insert(k, k);
+                    }
+                    for k in keys.step_by(2) {
+                        assert_eq!(map.remove(&k), Some(k));
+                    }
+                });
+            }
+        });
+        assert_eq!(map.len(), 20_000);
+        assert!((0..40_000).all(|k| map.get(&k) == (k % 2 == 1).then_some(k)));
+    }
+
+    fn linearizable<M: ConcurrentMap<usize, usize>>() {
+        proptest!(|(programs in programs(4, map_op()))| {
+            assert_linearizable::<Map<usize, usize>, _>(&programs, M::default, |map, op| match *op {
+                MapOp::Insert(key, value) => map.insert(key, value),
+                MapOp::Get(key) => map.get(&key),
+                MapOp::Remove(key) => map.remove(&key),
+            })?;
+        });
+    }
+
+    macro_rules! map_tests {
+        ($($test:ident),*) => {
+            mod with_dashmap {
+                $(#[test] fn $test() { super::$test::<dashmap::DashMap<usize, usize>>() })*
+            }
+            mod with_striped {
+                $(#[test] fn $test() { super::$test::<super::Striped>() })*
+            }
+            mod with_lock_free {
+                $(#[test] fn $test() { super::$test::<super::LockFree>() })*
+            }
+        };
+    }
+
+    map_tests!(
+        insert_get_remove,
+        entries_lists_everything,
+        retain_keeps_matching,
+        upsert_counts_every_update,
+        concurrent_inserts_and_removes,
+        linearizable
+    );
+}
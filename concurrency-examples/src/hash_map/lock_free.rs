@@ -0,0 +1,622 @@
+//! A hash map whose lookups never block, in the style of Java's `ConcurrentHashMap`.
+//!
+//! Each bucket is a linked list of nodes. Writers lock one of a fixed set of stripes, which covers
+//! every bucket whose index is congruent to it, and change the lists with atomic stores. Readers
+//! walk the lists without locking. Removed nodes and replaced values are retired to an [`Epoch`],
+//! so they stay readable until every reader that may have seen them has finished. Since readers
+//! may still see them, `insert` and `remove` return copies of the old value.
+//!
+//! The table doubles once it holds more entries than buckets. The thread that grows it takes every
+//! stripe lock, links copies of the nodes into a new table and swaps the table pointer. The copies
+//! take over the keys and values, so the old nodes are freed without dropping them.
+
+use super::ConcurrentMap;
+use crate::mutex::{Mutex, MutexGuard};
+use crate::reclaim::{Epoch, EpochGuard, Reclaim};
+use crate::ring_buffer::CachePadded;
+use crate::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+use std::borrow::Borrow;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+use std::mem::ManuallyDrop;
+use std::ops::Deref;
+use std::ptr;
+
+// A power of two, and the smallest number of buckets
+const STRIPES: usize = if cfg!(any(loom, pct)) { 2 } else { 64 };
+
+struct Node<K, V> {
+    hash: u64,
+    key: ManuallyDrop<K>,
+    value: AtomicPtr<V>,
+    next: AtomicPtr<Node<K, V>>,
+}
+
+struct Table<K, V> {
+    buckets: Box<[AtomicPtr<Node<K, V>>]>,
+}
+
+impl<K, V> Table<K, V> {
+    fn new(buckets: usize) -> Self {
+        Self {
+            buckets: (0..buckets)
+                .map(|_| AtomicPtr::new(ptr::null_mut()))
+                .collect(),
+        }
+    }
+
+    fn bucket(&self, hash: u64) -> &AtomicPtr<Node<K, V>> {
+        &self.buckets[hash as usize & (self.buckets.len() - 1)]
+    }
+}
+
+unsafe fn free_value<V>(value: *mut ()) {
+    drop(Box::from_raw(value as *mut V));
+}
+
+/// Frees a removed node with its key and value.
+unsafe fn free_node<K, V>(node: *mut ()) {
+    let mut node = Box::from_raw(node as *mut Node<K, V>);
+    ManuallyDrop::drop(&mut node.key);
+    free_value::<V>(node.value.load(Ordering::Relaxed).cast());
+}
+
+/// Frees nodes whose keys and values have moved to copies in a bigger table.
+unsafe fn free_moved_nodes<K, V>(nodes: *mut ()) {
+    for node in *Box::from_raw(nodes as *mut Vec<*mut Node<K, V>>) {
+        drop(Box::from_raw(node));
+    }
+}
+
+unsafe fn free_table<K, V>(table: *mut ()) {
+    drop(Box::from_raw(table as *mut Table<K, V>));
+}
+
+pub struct ConcurrentHashMap<K, V, S = RandomState> {
+    table: AtomicPtr<Table<K, V>>,
+    /// Stripe `i` guards the buckets whose index is `i` modulo `STRIPES`
+    stripes: Box<[CachePadded<Mutex<()>>]>,
+    len: AtomicUsize,
+    hasher: S,
+    reclaim: Epoch,
+}
+
+unsafe impl<K: Send + Sync, V: Send + Sync, S: Send> Send for ConcurrentHashMap<K, V, S> {}
+unsafe impl<K: Send + Sync, V: Send + Sync, S: Sync> Sync for ConcurrentHashMap<K, V, S> {}
+
+/// A value in the map. It stays readable while the `Ref` is alive, even if it is replaced or
+/// removed in the meantime. Each `Ref` holds one of the epoch's guards, which are limited.
+pub struct Ref<'a, V> {
+    _guard: EpochGuard<'a>,
+    value: &'a V,
+}
+
+/// A key and its stripe, locked, whether or not the key is in the map.
+pub struct Entry<'a, K, V, S> {
+    map: &'a ConcurrentHashMap<K, V, S>,
+    stripe: MutexGuard<'a, ()>,
+    guard: EpochGuard<'a>,
+    hash: u64,
+    key: K,
+    /// The key's node, or null if the key isn't in the map
+    node: *mut Node<K, V>,
+}
+
+/// Walks the table without locking, copying out the entries. Entries inserted or removed during
+/// the iteration may or may not be seen.
+pub struct Iter<'a, K, V, S> {
+    map: &'a ConcurrentHashMap<K, V, S>,
+    guard: EpochGuard<'a>,
+    table: *const Table<K, V>,
+    next_bucket: usize,
+    node: *mut Node<K, V>,
+}
+
+impl<K: Hash + Eq, V> ConcurrentHashMap<K, V> {
+    pub fn new() -> Self {
+        Self::with_capacity(0)
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self::with_capacity_and_hasher(capacity, RandomState::new())
+    }
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher> ConcurrentHashMap<K, V, S> {
+    pub fn with_capacity_and_hasher(capacity: usize, hasher: S) -> Self {
+        let buckets = capacity.max(STRIPES).next_power_of_two();
+        Self {
+            table: AtomicPtr::new(Box::into_raw(Box::new(Table::new(buckets)))),
+            stripes: (0..STRIPES).map(|_| CachePadded(Mutex::new(()))).collect(),
+            len: AtomicUsize::new(0),
+            hasher,
+            reclaim: Epoch::default(),
+        }
+    }
+
+    /// Returns the node for `key`, or null. Valid while `guard` is alive.
+    fn find<Q>(&self, guard: &EpochGuard<'_>, hash: u64, key: &Q) -> *mut Node<K, V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + ?Sized,
+    {
+        let table = self.reclaim.protect(guard, &self.table);
+        let mut node = self
+            .reclaim
+            .protect(guard, unsafe { (*table).bucket(hash) });
+        while let Some(n) = unsafe { node.as_ref() } {
+            if n.hash == hash && (*n.key).borrow() == key {
+                return node;
+            }
+            node = self.reclaim.protect(guard, &n.next);
+        }
+        ptr::null_mut()
+    }
+
+    fn lock_stripe(&self, hash: u64) -> MutexGuard<'_, ()> {
+        self.stripes[hash as usize & (STRIPES - 1)].lock()
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Option<Ref<'_, V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let hash = self.hasher.hash_one(key);
+        let guard = self.reclaim.enter();
+        let node = self.find(&guard, hash, key);
+        if node.is_null() {
+            return None;
+        }
+        let value = self.reclaim.protect(&guard, unsafe { &(*node).value });
+        Some(Ref {
+            value: unsafe { &*value },
+            _guard: guard,
+        })
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let hash = self.hasher.hash_one(key);
+        let guard = self.reclaim.enter();
+        !self.find(&guard, hash, key).is_null()
+    }
+
+    pub fn entry(&self, key: K) -> Entry<'_, K, V, S> {
+        let hash = self.hasher.hash_one(&key);
+        let stripe = self.lock_stripe(hash);
+        let guard = self.reclaim.enter();
+        let node = self.find(&guard, hash, &key);
+        Entry {
+            map: self,
+            stripe,
+            guard,
+            hash,
+            key,
+            node,
+        }
+    }
+
+    /// Inserts a value, returning a copy of the one it replaces.
+    pub fn insert(&self, key: K, value: V) -> Option<V>
+    where
+        V: Clone,
+    {
+        self.entry(key).insert(value)
+    }
+
+    /// Removes the entry for `key`, returning a copy of its value.
+    pub fn remove<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        V: Clone,
+    {
+        let hash = self.hasher.hash_one(key);
+        let _stripe = self.lock_stripe(hash);
+        let guard = self.reclaim.enter();
+        let table = self.table.load(Ordering::Acquire);
+        let mut value = None;
+        unsafe {
+            self.unlink_where(&guard, (*table).bucket(hash), |node| {
+                let matches = node.hash == hash && (*node.key).borrow() == key;
+                if matches {
+                    value = Some((*node.value.load(Ordering::Relaxed)).clone());
+                }
+                matches
+            })
+        };
+        value
+    }
+
+    /// Removes the entries for which `f` returns `false`, locking one stripe at a time.
+    pub fn retain(&self, mut f: impl FnMut(&K, &V) -> bool) {
+        for stripe in 0..STRIPES {
+            let _stripe = self.stripes[stripe].lock();
+            let guard = self.reclaim.enter();
+            let table = unsafe { &*self.table.load(Ordering::Acquire) };
+            for bucket in table.buckets.iter().skip(stripe).step_by(STRIPES) {
+                unsafe {
+                    self.unlink_where(&guard, bucket, |node| {
+                        !f(&node.key, &*node.value.load(Ordering::Relaxed))
+                    })
+                };
+            }
+        }
+    }
+
+    /// Unlinks and retires the nodes in `bucket` for which `f` returns `true`.
+    ///
+    /// # Safety
+    ///
+    /// The bucket's stripe must be locked.
+    unsafe fn unlink_where(
+        &self,
+        guard: &EpochGuard<'_>,
+        bucket: &AtomicPtr<Node<K, V>>,
+        mut f: impl FnMut(&Node<K, V>) -> bool,
+    ) {
+        // Other writers are locked out, so only this thread changes the links
+        let mut link = bucket;
+        let mut node = link.load(Ordering::Relaxed);
+        while !node.is_null() {
+            let next = (*node).next.load(Ordering::Relaxed);
+            if f(&*node) {
+                // Readers on the node still find the rest of the list through it
+                link.store(next, Ordering::Release);
+                self.len.fetch_sub(1, Ordering::Relaxed);
+                self.reclaim.retire(guard, node.cast(), free_node::<K, V>);
+            } else {
+                link = &(*node).next;
+            }
+            node = next;
+        }
+    }
+
+    /// Links a node for a new key at the head of its bucket. Returns the value's address.
+    ///
+    /// # Safety
+    ///
+    /// The bucket's stripe must be locked, and the key must not be in the map.
+    unsafe fn link(&self, hash: u64, key: K, value: V) -> *mut V {
+        let value = Box::into_raw(Box::new(value));
+        let bucket = (*self.table.load(Ordering::Acquire)).bucket(hash);
+        let node = Box::new(Node {
+            hash,
+            key: ManuallyDrop::new(key),
+            value: AtomicPtr::new(value),
+            next: AtomicPtr::new(bucket.load(Ordering::Relaxed)),
+        });
+        // Release publishes the node and its value to readers
+        bucket.store(Box::into_raw(node), Ordering::Release);
+        self.len.fetch_add(1, Ordering::Relaxed);
+        value
+    }
+
+    /// Swaps in a new value for the node and retires the old one.
+    ///
+    /// # Safety
+    ///
+    /// The node's stripe must be locked.
+    unsafe fn replace(&self, guard: &EpochGuard<'_>, node: &Node<K, V>, value: V) {
+        let value = Box::into_raw(Box::new(value));
+        let old = node.value.swap(value, Ordering::AcqRel);
+        self.reclaim.retire(guard, old.cast(), free_value::<V>);
+    }
+
+    /// The number of entries, which may be out of date by the time it returns.
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V, S> {
+        let guard = self.reclaim.enter();
+        let table = self.reclaim.protect(&guard, &self.table);
+        Iter {
+            map: self,
+            guard,
+            table,
+            next_bucket: 0,
+            node: ptr::null_mut(),
+        }
+    }
+
+    /// Doubles the table if it holds more entries than buckets. Must not be called with a stripe
+    /// locked.
+    fn grow_if_full(&self) {
+        {
+            let guard = self.reclaim.enter();
+            let table = unsafe { &*self.reclaim.protect(&guard, &self.table) };
+            if self.len() <= table.buckets.len() {
+                return;
+            }
+        }
+
+        let _stripes: Vec<_> = self.stripes.iter().map(|stripe| stripe.lock()).collect();
+        let guard = self.reclaim.enter();
+        let old = self.table.load(Ordering::Acquire);
+        let old_buckets = unsafe { &(*old).buckets };
+        // Another thread may have grown it already
+        if self.len() <= old_buckets.len() {
+            return;
+        }
+
+        let new = Table::new(old_buckets.len() * 2);
+        let mut moved = Vec::with_capacity(self.len());
+        for bucket in old_buckets.iter() {
+            let mut node = bucket.load(Ordering::Relaxed);
+            while let Some(n) = unsafe { node.as_ref() } {
+                let bucket = new.bucket(n.hash);
+                let copy = Box::new(Node {
+                    hash: n.hash,
+                    // Readers of the old node only compare keys, so sharing it is fine
+                    key: ManuallyDrop::new(unsafe { ptr::read(&*n.key) }),
+                    value: AtomicPtr::new(n.value.load(Ordering::Relaxed)),
+                    next: AtomicPtr::new(bucket.load(Ordering::Relaxed)),
+                });
+                bucket.store(Box::into_raw(copy), Ordering::Relaxed);
+                moved.push(node);
+                node = n.next.load(Ordering::Relaxed);
+            }
+        }
+        // Release publishes the copies with the table
+        self.table
+            .store(Box::into_raw(Box::new(new)), Ordering::Release);
+
+        // Retired together, since each retirement scans the garbage so far
+        let moved = Box::into_raw(Box::new(moved));
+        unsafe {
+            self.reclaim
+                .retire(&guard, moved.cast(), free_moved_nodes::<K, V>);
+            self.reclaim.retire(&guard, old.cast(), free_table::<K, V>);
+        }
+    }
+}
+
+impl<K: Hash + Eq, V> Default for ConcurrentHashMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V, S> Drop for ConcurrentHashMap<K, V, S> {
+    fn drop(&mut self) {
+        // Retired nodes, values and tables are freed with `reclaim`
+        let table = unsafe { Box::from_raw(self.table.load(Ordering::Relaxed)) };
+        for bucket in table.buckets.iter() {
+            let mut node = bucket.load(Ordering::Relaxed);
+            while !node.is_null() {
+                unsafe {
+                    let next = (*node).next.load(Ordering::Relaxed);
+                    free_node::<K, V>(node.cast());
+                    node = next;
+                }
+            }
+        }
+    }
+}
+
+impl<V> Deref for Ref<'_, V> {
+    type Target = V;
+
+    fn deref(&self) -> &V {
+        self.value
+    }
+}
+
+impl<'a, K: Hash + Eq, V, S: BuildHasher> Entry<'a, K, V, S> {
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// Replaces the value with `f` of it, if there is one.
+    pub fn and_modify(self, f: impl FnOnce(&V) -> V) -> Self {
+        if let Some(node) = unsafe { self.node.as_ref() } {
+            let value = f(unsafe { &*node.value.load(Ordering::Relaxed) });
+            unsafe { self.map.replace(&self.guard, node, value) };
+        }
+        self
+    }
+
+    pub fn or_insert(self, value: V) -> Ref<'a, V> {
+        self.or_insert_with(|| value)
+    }
+
+    pub fn or_insert_with(self, f: impl FnOnce() -> V) -> Ref<'a, V> {
+        let Entry {
+            map,
+            stripe,
+            guard,
+            hash,
+            key,
+            node,
+        } = self;
+        let value = match unsafe { node.as_ref() } {
+            Some(node) => node.value.load(Ordering::Relaxed),
+            None => unsafe { map.link(hash, key, f()) },
+        };
+        drop(stripe);
+        if node.is_null() {
+            map.grow_if_full();
+        }
+        // The guard keeps the value alive even if it is replaced once the stripe is unlocked
+        Ref {
+            value: unsafe { &*value },
+            _guard: guard,
+        }
+    }
+
+    pub fn or_default(self) -> Ref<'a, V>
+    where
+        V: Default,
+    {
+        self.or_insert_with(V::default)
+    }
+
+    /// Sets the value, returning a copy of the one it replaces.
+    pub fn insert(self, value: V) -> Option<V>
+    where
+        V: Clone,
+    {
+        let Entry {
+            map,
+            stripe,
+            guard,
+            hash,
+            key,
+            node,
+        } = self;
+        match unsafe { node.as_ref() } {
+            Some(node) => {
+                let old = unsafe { (*node.value.load(Ordering::Relaxed)).clone() };
+                unsafe { map.replace(&guard, node, value) };
+                Some(old)
+            }
+            None => {
+                unsafe { map.link(hash, key, value) };
+                drop(stripe);
+                map.grow_if_full();
+                None
+            }
+        }
+    }
+}
+
+impl<K: Clone, V: Clone, S> Iterator for Iter<'_, K, V, S> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<(K, V)> {
+        let reclaim = &self.map.reclaim;
+        loop {
+            if let Some(node) = unsafe { self.node.as_ref() } {
+                self.node = reclaim.protect(&self.guard, &node.next);
+                let value = reclaim.protect(&self.guard, &node.value);
+                return Some(((*node.key).clone(), unsafe { (*value).clone() }));
+            }
+            let table = unsafe { &*self.table };
+            let bucket = table.buckets.get(self.next_bucket)?;
+            self.next_bucket += 1;
+            self.node = reclaim.protect(&self.guard, bucket);
+        }
+    }
+}
+
+impl<K, V> ConcurrentMap<K, V> for ConcurrentHashMap<K, V>
+where
+    K: Hash + Eq + Clone + Send + Sync,
+    V: Clone + Send + Sync,
+{
+    fn get(&self, key: &K) -> Option<V> {
+        ConcurrentHashMap::get(self, key).map(|v| v.clone())
+    }
+
+    fn insert(&self, key: K, value: V) -> Option<V> {
+        ConcurrentHashMap::insert(self, key, value)
+    }
+
+    fn remove(&self, key: &K) -> Option<V> {
+        ConcurrentHashMap::remove(self, key)
+    }
+
+    fn upsert(&self, key: K, value: V, f: impl FnOnce(&V) -> V) {
+        self.entry(key).and_modify(f).or_insert(value);
+    }
+
+    fn retain(&self, f: impl FnMut(&K, &V) -> bool) {
+        ConcurrentHashMap::retain(self, f)
+    }
+
+    fn len(&self) -> usize {
+        ConcurrentHashMap::len(self)
+    }
+
+    fn entries(&self) -> Vec<(K, V)> {
+        self.iter().collect()
+    }
+}
+
+#[cfg(all(test, not(loom), not(pct)))]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn entry_api() {
+        let map = ConcurrentHashMap::new();
+        assert_eq!(*map.entry("a").or_insert(1), 1);
+        map.entry("a").and_modify(|v| v * 10).or_insert(0);
+        map.entry("b").and_modify(|v| v * 10).or_default();
+        assert_eq!(*map.get("a").unwrap(), 10);
+        assert_eq!(*map.get("b").unwrap(), 0);
+        assert_eq!(map.entry("b").insert(5), Some(0));
+        assert_eq!(map.entry("c").key(), &"c");
+        assert!(!map.contains_key("c"));
+    }
+
+    #[test]
+    fn ref_outlives_removal() {
+        let map = ConcurrentHashMap::new();
+        map.insert(1, "one".to_string());
+        let value = map.get(&1).unwrap();
+        assert_eq!(map.remove(&1).as_deref(), Some("one"));
+        assert_eq!(*value, "one");
+    }
+
+    #[test]
+    fn grows_while_readers_hold_values() {
+        let map = ConcurrentHashMap::new();
+        map.insert(0, 0);
+        let value = map.get(&0).unwrap();
+        for k in 1..10 * STRIPES {
+            map.insert(k, k);
+        }
+        assert_eq!(*value, 0);
+        assert!((0..10 * STRIPES).all(|k| map.get(&k).as_deref() == Some(&k)));
+    }
+
+    #[test]
+    fn drop_drops_keys_and_values() {
+        let key = Arc::new(());
+        let value = Arc::new(());
+        let map = ConcurrentHashMap::new();
+        for k in 0..2 * STRIPES {
+            map.insert((k, Arc::clone(&key)), Arc::clone(&value));
+        }
+        map.insert((0, Arc::clone(&key)), Arc::clone(&value));
+        map.remove(&(1, Arc::clone(&key)));
+        drop(map);
+        assert_eq!(Arc::strong_count(&key), 1);
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+
+    #[test]
+    fn iterates_while_growing() {
+        let map = ConcurrentHashMap::new();
+        for k in 0..STRIPES {
+            map.insert(k, k);
+        }
+        thread::scope(|s| {
+            s.spawn(|| {
+                for k in STRIPES..10 * STRIPES {
+                    map.insert(k, k);
+                }
+            });
+            // The first keys are there throughout
+            let mut seen: Vec<_> = map
+                .iter()
+                .map(|(k, _)| k)
+                .filter(|&k| k < STRIPES)
+                .collect();
+            seen.sort();
+            assert_eq!(seen, (0..STRIPES).collect::<Vec<_>>());
+        });
+    }
+}
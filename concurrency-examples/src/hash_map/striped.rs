@@ -0,0 +1,325 @@
+//! A hash map split into shards, each a `HashMap` behind [`RwLock`].
+//!
+//! A key's hash picks its shard, so threads working with different shards don't contend and
+//! readers of one shard share its lock. [`Ref`], [`RefMut`] and [`Entry`] hold the shard's lock
+//! until they are dropped, so holding one while calling into the map again can deadlock.
+
+use super::ConcurrentMap;
+use crate::ring_buffer::CachePadded;
+use crate::rwlock::{ReadGuard, RwLock, WriteGuard};
+use std::borrow::Borrow;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hash};
+use std::ops::{Deref, DerefMut};
+use std::thread;
+
+type Shard<K, V, S> = HashMap<K, V, S>;
+type Shards<K, V, S> = Box<[CachePadded<RwLock<Shard<K, V, S>>>]>;
+
+pub struct ConcurrentHashMap<K, V, S = RandomState> {
+    shards: Shards<K, V, S>,
+    /// Shifts a hash right to leave the bits that pick the shard
+    shift: u32,
+    hasher: S,
+}
+
+/// A value in the map, read-locking its shard.
+pub struct Ref<'a, K, V, S> {
+    _shard: ReadGuard<'a, Shard<K, V, S>>,
+    value: *const V,
+}
+
+/// A value in the map, write-locking its shard.
+pub struct RefMut<'a, K, V, S> {
+    _shard: WriteGuard<'a, Shard<K, V, S>>,
+    value: *mut V,
+}
+
+/// A key and its shard, write-locked, whether or not the key is in the map.
+pub struct Entry<'a, K, V, S> {
+    shard: WriteGuard<'a, Shard<K, V, S>>,
+    key: K,
+}
+
+/// Copies the entries out one shard at a time.
+pub struct Iter<'a, K, V, S> {
+    map: &'a ConcurrentHashMap<K, V, S>,
+    next_shard: usize,
+    entries: std::vec::IntoIter<(K, V)>,
+}
+
+impl<K: Hash + Eq, V> ConcurrentHashMap<K, V> {
+    pub fn new() -> Self {
+        // Enough shards that threads rarely meet, like `DashMap`
+        let threads = thread::available_parallelism().map_or(1, usize::from);
+        Self::with_shards(threads * 4)
+    }
+
+    pub fn with_shards(shards: usize) -> Self {
+        Self::with_shards_and_hasher(shards, RandomState::new())
+    }
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher + Clone> ConcurrentHashMap<K, V, S> {
+    /// Creates a map with `shards` shards, rounded up to a power of two.
+    pub fn with_shards_and_hasher(shards: usize, hasher: S) -> Self {
+        let shards = shards.next_power_of_two();
+        Self {
+            shards: (0..shards)
+                .map(|_| CachePadded(RwLock::new(HashMap::with_hasher(hasher.clone()))))
+                .collect(),
+            shift: u64::BITS - shards.trailing_zeros(),
+            hasher,
+        }
+    }
+
+    fn shard<Q: Hash + ?Sized>(&self, key: &Q) -> &RwLock<Shard<K, V, S>> {
+        // Skips the top 7 bits, which the shard's own table uses to tell keys in a bucket apart
+        let hash = self.hasher.hash_one(key) << 7;
+        &self.shards[hash.checked_shr(self.shift).unwrap_or(0) as usize]
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Option<Ref<'_, K, V, S>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let shard = self.shard(key).read();
+        let value: *const V = shard.get(key)?;
+        Some(Ref {
+            _shard: shard,
+            value,
+        })
+    }
+
+    pub fn get_mut<Q>(&self, key: &Q) -> Option<RefMut<'_, K, V, S>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let mut shard = self.shard(key).write();
+        let value: *mut V = shard.get_mut(key)?;
+        Some(RefMut {
+            _shard: shard,
+            value,
+        })
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.shard(key).read().contains_key(key)
+    }
+
+    /// Inserts a value, returning the one it replaces.
+    pub fn insert(&self, key: K, value: V) -> Option<V> {
+        self.shard(&key).write().insert(key, value)
+    }
+
+    pub fn remove<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.shard(key).write().remove(key)
+    }
+
+    pub fn entry(&self, key: K) -> Entry<'_, K, V, S> {
+        Entry {
+            shard: self.shard(&key).write(),
+            key,
+        }
+    }
+
+    /// Removes the entries for which `f` returns `false`, locking one shard at a time.
+    pub fn retain(&self, mut f: impl FnMut(&K, &mut V) -> bool) {
+        for shard in self.shards.iter() {
+            shard.write().retain(&mut f);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|shard| shard.read().len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shards.iter().all(|shard| shard.read().is_empty())
+    }
+
+    /// Iterates over copies of the entries. Each shard is copied at once, but changes made to other
+    /// shards during the iteration may or may not be seen.
+    pub fn iter(&self) -> Iter<'_, K, V, S>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        Iter {
+            map: self,
+            next_shard: 0,
+            entries: Vec::new().into_iter(),
+        }
+    }
+}
+
+impl<K: Hash + Eq, V> Default for ConcurrentHashMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V, S> Deref for Ref<'_, K, V, S> {
+    type Target = V;
+
+    fn deref(&self) -> &V {
+        // The read lock keeps the shard from changing
+        unsafe { &*self.value }
+    }
+}
+
+impl<K, V, S> Deref for RefMut<'_, K, V, S> {
+    type Target = V;
+
+    fn deref(&self) -> &V {
+        unsafe { &*self.value }
+    }
+}
+
+impl<K, V, S> DerefMut for RefMut<'_, K, V, S> {
+    fn deref_mut(&mut self) -> &mut V {
+        unsafe { &mut *self.value }
+    }
+}
+
+impl<'a, K: Hash + Eq, V, S: BuildHasher> Entry<'a, K, V, S> {
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// Calls `f` on the value if there is one.
+    pub fn and_modify(mut self, f: impl FnOnce(&mut V)) -> Self {
+        if let Some(value) = self.shard.get_mut(&self.key) {
+            f(value);
+        }
+        self
+    }
+
+    pub fn or_insert(self, value: V) -> RefMut<'a, K, V, S> {
+        self.or_insert_with(|| value)
+    }
+
+    pub fn or_insert_with(self, f: impl FnOnce() -> V) -> RefMut<'a, K, V, S> {
+        let Entry { mut shard, key } = self;
+        let value: *mut V = shard.entry(key).or_insert_with(f);
+        RefMut {
+            _shard: shard,
+            value,
+        }
+    }
+
+    pub fn or_default(self) -> RefMut<'a, K, V, S>
+    where
+        V: Default,
+    {
+        self.or_insert_with(V::default)
+    }
+
+    /// Sets the value, returning the one it replaces.
+    pub fn insert(mut self, value: V) -> Option<V> {
+        self.shard.insert(self.key, value)
+    }
+}
+
+impl<K: Clone, V: Clone, S> Iterator for Iter<'_, K, V, S> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<(K, V)> {
+        loop {
+            if let Some(entry) = self.entries.next() {
+                return Some(entry);
+            }
+            let shard = self.map.shards.get(self.next_shard)?;
+            self.next_shard += 1;
+            self.entries = shard
+                .read()
+                .iter()
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect::<Vec<_>>()
+                .into_iter();
+        }
+    }
+}
+
+impl<K, V> ConcurrentMap<K, V> for ConcurrentHashMap<K, V>
+where
+    K: Hash + Eq + Clone + Send + Sync,
+    V: Clone + Send + Sync,
+{
+    fn get(&self, key: &K) -> Option<V> {
+        ConcurrentHashMap::get(self, key).map(|v| v.clone())
+    }
+
+    fn insert(&self, key: K, value: V) -> Option<V> {
+        ConcurrentHashMap::insert(self, key, value)
+    }
+
+    fn remove(&self, key: &K) -> Option<V> {
+        ConcurrentHashMap::remove(self, key)
+    }
+
+    fn upsert(&self, key: K, value: V, f: impl FnOnce(&V) -> V) {
+        self.entry(key).and_modify(|v| *v = f(v)).or_insert(value);
+    }
+
+    fn retain(&self, mut f: impl FnMut(&K, &V) -> bool) {
+        ConcurrentHashMap::retain(self, |k, v| f(k, v))
+    }
+
+    fn len(&self) -> usize {
+        ConcurrentHashMap::len(self)
+    }
+
+    fn entries(&self) -> Vec<(K, V)> {
+        self.iter().collect()
+    }
+}
+
+#[cfg(all(test, not(loom), not(pct)))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entry_api() {
+        let map = ConcurrentHashMap::with_shards(4);
+        *map.entry("a").or_insert(1) += 10;
+        map.entry("a").and_modify(|v| *v *= 2).or_insert(0);
+        map.entry("b").and_modify(|v| *v *= 2).or_default();
+        assert_eq!(*map.get("a").unwrap(), 22);
+        assert_eq!(*map.get("b").unwrap(), 0);
+        assert_eq!(map.entry("b").insert(5), Some(0));
+        assert_eq!(map.entry("b").key(), &"b");
+    }
+
+    #[test]
+    fn get_mut_changes_in_place() {
+        let map = ConcurrentHashMap::with_shards(1);
+        map.insert(1, vec![1]);
+        map.get_mut(&1).unwrap().push(2);
+        assert_eq!(*map.get(&1).unwrap(), [1, 2]);
+        assert!(map.get_mut(&2).is_none());
+        assert!(map.contains_key(&1));
+    }
+
+    #[test]
+    fn iter_visits_every_shard() {
+        let map = ConcurrentHashMap::with_shards(8);
+        for k in 0..100 {
+            map.insert(k, k);
+        }
+        let mut entries: Vec<_> = map.iter().collect();
+        entries.sort();
+        assert_eq!(entries, (0..100).map(|k| (k, k)).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,95 @@
+//! A count-down latch: threads wait until the count reaches zero.
+//!
+//! The count is an `AtomicU32`, so waiting threads sleep on [`crate::futex`] and the thread that
+//! brings it to zero wakes them all. Everything a thread does before `count_down` happens before
+//! `wait` returns. `CountDownLatch::new(1)` is a one-off latch that a single thread opens.
+
+use crate::futex::{atomic_wait, atomic_wait_timeout, wake_all};
+use crate::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Duration, Instant};
+
+pub struct CountDownLatch {
+    count: AtomicU32,
+}
+
+impl CountDownLatch {
+    pub fn new(count: u32) -> Self {
+        Self {
+            count: AtomicU32::new(count),
+        }
+    }
+
+    /// Decrements the count, waking the waiters when it reaches zero. Does nothing if the count is
+    /// already zero.
+    pub fn count_down(&self) {
+        let decremented = self
+            .count
+            .fetch_update(Ordering::Release, Ordering::Relaxed, |c| c.checked_sub(1));
+        if decremented == Ok(1) {
+            wake_all(&self.count);
+        }
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    /// Waits until the count reaches zero.
+    pub fn wait(&self) {
+        loop {
+            let count = self.count.load(Ordering::Acquire);
+            if count == 0 {
+                return;
+            }
+            atomic_wait(&self.count, count);
+        }
+    }
+
+    /// Waits until the count reaches zero, for at most `timeout`. Returns `false` on timeout.
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let count = self.count.load(Ordering::Acquire);
+            if count == 0 {
+                return true;
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            atomic_wait_timeout(&self.count, count, deadline - now);
+        }
+    }
+}
+
+#[cfg(all(test, not(loom), not(pct)))]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn wait_returns_after_all_count_downs() {
+        let latch = CountDownLatch::new(4);
+        let done = std::sync::atomic::AtomicU32::new(0);
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    done.fetch_add(1, Ordering::Relaxed);
+                    latch.count_down();
+                });
+            }
+            latch.wait();
+            assert_eq!(done.load(Ordering::Relaxed), 4);
+        });
+    }
+
+    #[test]
+    fn wait_timeout_gives_up() {
+        let latch = CountDownLatch::new(1);
+        assert!(!latch.wait_timeout(Duration::from_millis(10)));
+        latch.count_down();
+        latch.count_down();
+        assert_eq!(latch.count(), 0);
+        assert!(latch.wait_timeout(Duration::from_millis(10)));
+    }
+}
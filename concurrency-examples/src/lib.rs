@@ -1,23 +1,89 @@
-#![feature(portable_simd)]
+#![cfg_attr(not(feature = "std"), no_std)]
+#![cfg_attr(feature = "std", feature(portable_simd))]
 
+#[cfg(all(feature = "std", not(loom)))]
 mod actors;
+#[cfg(feature = "std")]
+pub mod atomic_arc;
+#[cfg(feature = "std")]
+pub mod barrier;
+#[cfg(feature = "std")]
+pub mod channel;
+#[cfg(feature = "std")]
+pub mod condvar;
+#[cfg(feature = "std")]
+pub mod contention;
+// Its thread-local lock stacks would be shared by loom's threads, which all run on one OS thread
+#[cfg(all(feature = "deadlock-detection", not(loom)))]
+pub mod deadlock;
+#[cfg(feature = "std")]
+pub mod futex;
+#[cfg(feature = "std")]
+pub mod hash_map;
+#[cfg(feature = "std")]
+pub mod latch;
+#[cfg(feature = "std")]
+pub mod linearizability;
+#[cfg(feature = "std")]
 mod loom;
+#[cfg(feature = "std")]
 mod memory_ordering;
-
+#[cfg(feature = "std")]
+pub mod mutex;
+#[cfg(feature = "std")]
+pub mod mutual_exclusion;
+pub mod once;
+#[cfg(feature = "std")]
+pub mod oneshot;
+#[cfg(all(feature = "std", pct))]
+pub mod pct;
+#[cfg(feature = "std")]
+pub mod rcu;
+#[cfg(feature = "std")]
+pub mod reclaim;
+#[cfg(feature = "std")]
+pub mod ring_buffer;
+#[cfg(feature = "std")]
+pub mod rwlock;
+#[cfg(feature = "std")]
+pub mod semaphore;
+#[cfg(feature = "std")]
+pub mod seqlock;
+#[cfg(feature = "std")]
+pub mod stress;
+mod sync;
+// Its statics need const atomics, which loom doesn't have
+#[cfg(all(feature = "std", not(loom)))]
+pub mod thread_log;
+#[cfg(feature = "std")]
+pub mod trace;
+#[cfg(feature = "std")]
+pub mod treiber;
+#[cfg(all(feature = "std", tsan))]
+pub mod tsan;
+
+#[cfg(feature = "std")]
 use dashmap::DashMap;
+#[cfg(feature = "std")]
 use rayon::prelude::*;
+#[cfg(feature = "std")]
 use std::simd::{f32x8, Simd};
-use std::sync::{Arc, Mutex, MutexGuard};
+#[cfg(feature = "std")]
+use std::sync::{mpsc, Arc};
+#[cfg(feature = "std")]
 use std::thread;
 
+#[cfg(feature = "std")]
 fn shared_mem_mutex() -> usize {
-    let count = Arc::new(Mutex::new(0));
+    // Profiled, so that `contention::report` shows how long the threads waited for each other,
+    // and traced underneath, so that `trace::record` can show the order they took the lock in
+    let count = Arc::new(contention::Mutex::named("shared_mem_mutex", 0));
 
     let mut handles = vec![];
 
     for _ in 0..10 {
         let count = Arc::clone(&count);
-        let handle = thread::spawn(move || {
+        let handle = trace::thread::spawn(move || {
             let mut num = count.lock().unwrap();
             *num += 1;
         });
@@ -28,10 +94,11 @@ fn shared_mem_mutex() -> usize {
         handle.join().unwrap();
     }
 
-    let result: MutexGuard<usize> = count.lock().unwrap();
+    let result: contention::MutexGuard<usize> = count.lock().unwrap();
     *result // Deref implementation gets the lock's data
 }
 
+#[cfg(feature = "std")]
 fn shared_mem_dashmap() -> usize {
     let count = Arc::new(DashMap::new());
     count.insert("value", 0);
@@ -55,6 +122,31 @@ fn shared_mem_dashmap() -> usize {
     *result
 }
 
+// Its threads and the striped map's locks come from `crate::sync`, so that `cargo pct` can check it
+#[cfg(feature = "std")]
+fn shared_mem_hash_map() -> usize {
+    let count = sync::Arc::new(hash_map::striped::ConcurrentHashMap::new());
+    count.insert("value", 0);
+
+    let mut handles = vec![];
+
+    for _ in 0..10 {
+        let count = sync::Arc::clone(&count);
+        let handle = sync::thread::spawn(move || {
+            let mut value = count.get_mut("value").unwrap();
+            *value += 1;
+        });
+        handles.push(handle);
+    }
+
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    let result = count.get("value").unwrap();
+    *result
+}
+
 /// Multiplies two matrices.
 ///
 /// # Arguments
@@ -68,6 +160,7 @@ fn shared_mem_dashmap() -> usize {
 /// # Returns
 ///
 /// The resultant matrix after multiplication.
+#[cfg(feature = "std")]
 pub fn matrix_multiply(a: &[f32], b: &[f32], m: usize, n: usize, p: usize) -> Vec<f32> {
     let mut result = vec![0.0; m * p];
 
@@ -90,6 +183,7 @@ pub fn matrix_multiply(a: &[f32], b: &[f32], m: usize, n: usize, p: usize) -> Ve
     result
 }
 
+#[cfg(feature = "std")]
 pub fn matrix_multiply_rayon(a: &[f32], b: &[f32], m: usize, n: usize, p: usize) -> Vec<f32> {
     let mut result = vec![0.0; m * p];
 
@@ -110,6 +204,7 @@ pub fn matrix_multiply_rayon(a: &[f32], b: &[f32], m: usize, n: usize, p: usize)
 }
 
 /// Multiplies two matrices using AVX instructions.
+#[cfg(feature = "std")]
 pub fn matrix_multiply_avx(a: &[f32], b: &[f32], m: usize, n: usize, p: usize) -> Vec<f32> {
     let mut result = vec![0.0; m * p];
 
@@ -150,6 +245,7 @@ pub fn matrix_multiply_avx(a: &[f32], b: &[f32], m: usize, n: usize, p: usize) -
 
 /// Multiplies two matrices using AVX instructions. Uses a worker pool to parallelise the outer
 /// loop.
+#[cfg(feature = "std")]
 pub fn matrix_multiply_avx_rayon(a: &[f32], b: &[f32], m: usize, n: usize, p: usize) -> Vec<f32> {
     let mut result = vec![0.0; m * p];
 
@@ -190,27 +286,100 @@ pub fn matrix_multiply_avx_rayon(a: &[f32], b: &[f32], m: usize, n: usize, p: us
     result
 }
 
-#[cfg(test)]
+/// A matrix multiplication `(a, b, m, n, p)` with the arguments of [`matrix_multiply`].
+#[cfg(feature = "std")]
+pub type MatrixJob<'a> = (&'a [f32], &'a [f32], usize, usize, usize);
+
+/// Multiplies several pairs of matrices in parallel, one thread per pair, while keeping the
+/// results still being computed within `budget` elements.
+///
+/// A thread takes `m * p` permits from a [`semaphore::Semaphore`] before it allocates its result
+/// and returns them once it has handed the result back, so jobs beyond the budget wait for earlier
+/// ones to finish. Results that have been handed back no longer count, so the budget limits the
+/// multiplies in progress, not the memory of the returned results.
+///
+/// # Panics
+///
+/// If a single result is larger than `budget`.
+#[cfg(feature = "std")]
+pub fn matrix_multiply_throttled(jobs: &[MatrixJob], budget: u32) -> Vec<Vec<f32>> {
+    let memory = semaphore::Semaphore::new(budget);
+
+    let (sender, receiver) = mpsc::channel();
+    thread::scope(|s| {
+        for (i, &(a, b, m, n, p)) in jobs.iter().enumerate() {
+            let size = u32::try_from(m * p).expect("result too large");
+            assert!(size <= budget, "result of {size} elements exceeds budget");
+            let (memory, sender) = (&memory, sender.clone());
+            s.spawn(move || {
+                let permit = memory.acquire(size);
+                // Results arrive in the order they finish, as joining in job order would have an
+                // earlier job wait for the permits of a later one that is itself waiting to be
+                // joined
+                sender.send((i, matrix_multiply(a, b, m, n, p))).unwrap();
+                drop(permit);
+            });
+        }
+        drop(sender);
+
+        let mut results = vec![Vec::new(); jobs.len()];
+        for (i, result) in receiver {
+            results[i] = result;
+        }
+        results
+    })
+}
+
+#[cfg(all(test, feature = "std", not(loom), not(pct)))]
 mod tests {
     use super::*;
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+
+    /// A pair of matrices `(a, b, m, n, p)` to multiply. Their sizes are often not multiples of 8,
+    /// so the SIMD kernels' partial chunks are covered, and the entries are small integers, so
+    /// every sum is exact whatever order the kernels add in.
+    fn matrices() -> impl Strategy<Value = (Vec<f32>, Vec<f32>, usize, usize, usize)> {
+        (1..=24usize, 1..=24usize, 1..=24usize).prop_flat_map(|(m, n, p)| {
+            let entry = (-8i8..=8).prop_map(f32::from);
+            (
+                vec(entry.clone(), m * n),
+                vec(entry, n * p),
+                Just(m),
+                Just(n),
+                Just(p),
+            )
+        })
+    }
 
     #[test]
     fn shared_mem_mutex_correct() {
         assert_eq!(shared_mem_mutex(), 10);
     }
 
+    #[test]
+    fn shared_mem_mutex_contention() {
+        shared_mem_mutex();
+        let report = contention::report();
+        let stats = report
+            .locks
+            .iter()
+            .find(|s| s.key == "shared_mem_mutex")
+            .unwrap();
+        // Other tests may be running it too, but each run takes the lock 11 times
+        assert!(stats.acquisitions >= 11);
+        assert!(stats.contended < stats.acquisitions);
+        assert!(stats.max_wait <= stats.wait);
+    }
+
     #[test]
     fn shared_mem_dashmap_correct() {
         assert_eq!(shared_mem_dashmap(), 10);
     }
 
     #[test]
-    fn worker_pool_matrix_multiply_correct() {
-        let a = vec![vec![1.0, 2.0], vec![3.0, 4.0]];
-        let b = vec![vec![5.0, 6.0], vec![7.0, 8.0]];
-
-        let result = worker_pool_matrix_multiply(&a, &b);
-        assert_eq!(result, vec![vec![19.0, 22.0], vec![43.0, 50.0]]);
+    fn shared_mem_hash_map_correct() {
+        assert_eq!(shared_mem_hash_map(), 10);
     }
 
     #[test]
@@ -236,4 +405,38 @@ mod tests {
         let result_avx_rayon = matrix_multiply_avx(&a, &b, m, n, p);
         assert_eq!(result_avx_rayon, expected_result);
     }
+
+    #[test]
+    fn matrix_multiply_throttled_correct() {
+        let a = vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0];
+        let b = vec![7.0, 8.0, 9.0, 10.0, 11.0, 12.0];
+
+        // A budget of 4 elements fits one 2x2 result at a time
+        let jobs = [(&a[..], &b[..], 2, 3, 2), (&b[..], &a[..], 2, 3, 2)];
+        let results = matrix_multiply_throttled(&jobs, 4);
+        assert_eq!(results[0], matrix_multiply(&a, &b, 2, 3, 2));
+        assert_eq!(results[1], matrix_multiply(&b, &a, 2, 3, 2));
+    }
+
+    proptest! {
+        #[test]
+        fn matrix_kernels_agree((a, b, m, n, p) in matrices()) {
+            let expected = matrix_multiply(&a, &b, m, n, p);
+            prop_assert_eq!(matrix_multiply_rayon(&a, &b, m, n, p), expected.clone());
+            prop_assert_eq!(matrix_multiply_avx(&a, &b, m, n, p), expected.clone());
+            prop_assert_eq!(matrix_multiply_avx_rayon(&a, &b, m, n, p), expected);
+        }
+
+        #[test]
+        fn matrix_multiply_throttled_agrees(jobs in vec(matrices(), 1..=4)) {
+            let jobs: Vec<MatrixJob> =
+                jobs.iter().map(|(a, b, m, n, p)| (&a[..], &b[..], *m, *n, *p)).collect();
+            // Only room for the largest result, so that the jobs take turns
+            let budget = jobs.iter().map(|&(_, _, m, _, p)| m * p).max().unwrap() as u32;
+            let results = matrix_multiply_throttled(&jobs, budget);
+            for (&(a, b, m, n, p), result) in jobs.iter().zip(results) {
+                prop_assert_eq!(result, matrix_multiply(a, b, m, n, p));
+            }
+        }
+    }
 }
@@ -0,0 +1,840 @@
+//! A linearizability checker, in the style of Wing & Gong with Lowe's memoisation.
+//!
+//! A stress test that only checks the final value misses results that no sequential order could
+//! have produced, such as two increments that both return 0. [`record`] runs threads that call
+//! into a shared object and logs each call's invocation and return with a logical timestamp.
+//! [`check`] then searches for a linearization: an order of the calls that respects real time,
+//! i.e. puts a call before every call invoked after it returned, and in which each call returns
+//! what the sequential [`Spec`] returns. The search takes the next call of some thread, applies it
+//! to the spec and backtracks when the results differ, skipping states (calls taken so far and the
+//! spec's state) it has been in before.
+//!
+//! An invocation with no return is pending: the call may have taken effect at any point after it
+//! was invoked, with any result, or not at all.
+//!
+//! When there is no linearization, [`check`] shrinks the history to a small part that has none.
+//! It takes the shortest prefix that has none, then makes each call pending in turn, keeping the
+//! change if the history still has no linearization. The calls that are left are needed for the
+//! failure even if the others took effect in any order or not at all, so the report doesn't blame
+//! a call for results that one of the dropped calls explains.
+
+use crate::barrier::Barrier;
+#[cfg(all(test, not(loom), not(pct)))]
+use proptest::{
+    arbitrary::{any, Arbitrary},
+    collection::vec,
+    prop_oneof,
+    strategy::Strategy,
+    test_runner::TestCaseError,
+};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+/// Most ops in a thread of a property test
+#[cfg(all(test, not(loom), not(pct)))]
+const CALLS: usize = 32;
+
+/// Runs of each case of a property test, any of which may fail, so that shrinking doesn't stop at a
+/// smaller program just because it happened to pass
+#[cfg(all(test, not(loom), not(pct)))]
+pub(crate) const RUNS: usize = 5;
+
+/// The sequential specification of an object. `Default` is its initial state.
+pub trait Spec: Clone + Default + Eq + Hash {
+    type Op: Clone + fmt::Debug + Send + Sync;
+    type Ret: Clone + PartialEq + fmt::Debug + Send;
+
+    fn apply(&mut self, op: &Self::Op) -> Self::Ret;
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event<Op, Ret> {
+    Invoke {
+        time: usize,
+        thread: usize,
+        op: Op,
+    },
+    Return {
+        time: usize,
+        thread: usize,
+        ret: Ret,
+    },
+}
+
+/// Invocations and returns of calls, in the order they happened. Each thread's calls follow each
+/// other, so a thread's return belongs to its last invocation.
+#[derive(Clone, Debug, PartialEq)]
+pub struct History<Op, Ret> {
+    pub events: Vec<Event<Op, Ret>>,
+}
+
+/// A history with no linearization, with the calls whose results alone already rule one out.
+#[derive(Debug)]
+pub struct NotLinearizable<Op, Ret> {
+    pub history: History<Op, Ret>,
+}
+
+/// Logs the calls of one thread of [`record`].
+pub struct Recorder<'a, S: Spec> {
+    clock: &'a AtomicUsize,
+    thread: usize,
+    events: Vec<Event<S::Op, S::Ret>>,
+}
+
+/// A call, with its return or `None` when it's pending. A pending call returns at `usize::MAX`.
+struct Call<'a, S: Spec> {
+    thread: usize,
+    op: &'a S::Op,
+    ret: Option<&'a S::Ret>,
+    invoke: usize,
+    response: usize,
+}
+
+impl<Op, Ret> Event<Op, Ret> {
+    pub fn time(&self) -> usize {
+        match *self {
+            Event::Invoke { time, .. } | Event::Return { time, .. } => time,
+        }
+    }
+}
+
+impl<Op: fmt::Debug, Ret: fmt::Debug> fmt::Display for Event<Op, Ret> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Event::Invoke { time, thread, op } => write!(f, "{time:>6} thread {thread}: {op:?}"),
+            Event::Return { time, thread, ret } => {
+                write!(f, "{time:>6} thread {thread}: returned {ret:?}")
+            }
+        }
+    }
+}
+
+impl<Op: fmt::Debug, Ret: fmt::Debug> fmt::Display for History<Op, Ret> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for event in &self.events {
+            writeln!(f, "{event}")?;
+        }
+        Ok(())
+    }
+}
+
+impl<Op: fmt::Debug, Ret: fmt::Debug> fmt::Display for NotLinearizable<Op, Ret> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "history is not linearizable, even counting only the results of these calls:\n{}",
+            self.history
+        )
+    }
+}
+
+impl<Op: fmt::Debug, Ret: fmt::Debug> std::error::Error for NotLinearizable<Op, Ret> {}
+
+impl<S: Spec> Recorder<'_, S> {
+    /// The thread's index, from 0 to one less than the number of threads.
+    pub fn thread(&self) -> usize {
+        self.thread
+    }
+
+    /// Runs `f`, which performs `op` on the object under test, and logs its invocation and return.
+    pub fn call(&mut self, op: S::Op, f: impl FnOnce(&S::Op) -> S::Ret) -> S::Ret {
+        let invoke = self.clock.fetch_add(1, Ordering::SeqCst);
+        let ret = f(&op);
+        let response = self.clock.fetch_add(1, Ordering::SeqCst);
+        self.events.push(Event::Invoke {
+            time: invoke,
+            thread: self.thread,
+            op,
+        });
+        self.events.push(Event::Return {
+            time: response,
+            thread: self.thread,
+            ret: ret.clone(),
+        });
+        ret
+    }
+}
+
+impl<S: Spec> Clone for Call<'_, S> {
+    fn clone(&self) -> Self {
+        Self { ..*self }
+    }
+}
+
+/// Runs `f` on `threads` threads at once and returns the calls they logged.
+pub fn record<S: Spec>(
+    threads: usize,
+    f: impl Fn(&mut Recorder<'_, S>) + Sync,
+) -> History<S::Op, S::Ret> {
+    let clock = AtomicUsize::new(0);
+    let barrier = Barrier::new(threads as u32);
+    let logs: Vec<_> = thread::scope(|s| {
+        let handles: Vec<_> = (0..threads)
+            .map(|thread| {
+                let (clock, barrier, f) = (&clock, &barrier, &f);
+                s.spawn(move || {
+                    let mut recorder = Recorder {
+                        clock,
+                        thread,
+                        events: Vec::new(),
+                    };
+                    barrier.wait();
+                    f(&mut recorder);
+                    recorder.events
+                })
+            })
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    });
+    let mut events: Vec<_> = logs.into_iter().flatten().collect();
+    events.sort_by_key(Event::time);
+    History { events }
+}
+
+/// Runs each program on a thread of its own, calling `f` to perform each of its ops, and returns
+/// the calls.
+pub fn run<S: Spec>(
+    programs: &[Vec<S::Op>],
+    f: impl Fn(&S::Op) -> S::Ret + Sync,
+) -> History<S::Op, S::Ret> {
+    record::<S>(programs.len(), |r| {
+        for op in &programs[r.thread()] {
+            r.call(op.clone(), &f);
+        }
+    })
+}
+
+/// Checks that `history` has a linearization under `S`, and if not, finds a small part of it that
+/// has none.
+pub fn check<S: Spec>(
+    history: &History<S::Op, S::Ret>,
+) -> Result<(), NotLinearizable<S::Op, S::Ret>> {
+    let mut calls = calls::<S>(history);
+    if linearizable(&calls) {
+        return Ok(());
+    }
+
+    // Linearizability is prefix-closed, so the prefixes that fail are those past some point
+    let mut times: Vec<_> = calls.iter().flat_map(|c| [c.invoke, c.response]).collect();
+    times.retain(|&time| time != usize::MAX);
+    times.sort_unstable();
+    let end = times.partition_point(|&time| linearizable(&prefix(&calls, time)));
+    calls = prefix(&calls, times[end]);
+
+    // Whole threads first, as most of them usually have nothing to do with the failure
+    let mut threads: Vec<_> = calls.iter().map(|c| c.thread).collect();
+    threads.sort_unstable();
+    threads.dedup();
+    for thread in threads {
+        weaken(&mut calls, |c| c.thread == thread);
+    }
+    for invoke in calls.iter().map(|c| c.invoke).collect::<Vec<_>>() {
+        weaken(&mut calls, |c| c.invoke == invoke);
+    }
+
+    // Leaving out the pending calls forces them not to take effect, which can't make a
+    // linearization possible
+    let mut events = Vec::new();
+    for call in calls {
+        if let Some(ret) = call.ret {
+            events.push(Event::Invoke {
+                time: call.invoke,
+                thread: call.thread,
+                op: call.op.clone(),
+            });
+            events.push(Event::Return {
+                time: call.response,
+                thread: call.thread,
+                ret: ret.clone(),
+            });
+        }
+    }
+    events.sort_by_key(Event::time);
+    Err(NotLinearizable {
+        history: History { events },
+    })
+}
+
+/// Pairs up the invocations and returns of `history`, in the order of their invocations.
+fn calls<S: Spec>(history: &History<S::Op, S::Ret>) -> Vec<Call<'_, S>> {
+    let mut calls: Vec<Call<'_, S>> = Vec::new();
+    // Each thread's last call
+    let mut last = HashMap::new();
+    for event in &history.events {
+        match event {
+            Event::Invoke { time, thread, op } => {
+                last.insert(*thread, calls.len());
+                calls.push(Call {
+                    thread: *thread,
+                    op,
+                    ret: None,
+                    invoke: *time,
+                    response: usize::MAX,
+                });
+            }
+            Event::Return { time, thread, ret } => {
+                let call = last
+                    .remove(thread)
+                    .unwrap_or_else(|| panic!("thread {thread} returned at {time} without a call"));
+                calls[call].ret = Some(ret);
+                calls[call].response = *time;
+            }
+        }
+    }
+    calls
+}
+
+/// The calls invoked by `time`, with those that hadn't returned by then pending.
+fn prefix<'a, S: Spec>(calls: &[Call<'a, S>], time: usize) -> Vec<Call<'a, S>> {
+    calls
+        .iter()
+        .filter(|c| c.invoke <= time)
+        .map(|c| {
+            if c.response > time {
+                Call {
+                    ret: None,
+                    response: usize::MAX,
+                    ..c.clone()
+                }
+            } else {
+                c.clone()
+            }
+        })
+        .collect()
+}
+
+/// Makes the calls that match `which` pending, unless that gives `calls` a linearization.
+fn weaken<S: Spec>(calls: &mut Vec<Call<'_, S>>, which: impl Fn(&Call<'_, S>) -> bool) {
+    let weaker: Vec<_> = calls
+        .iter()
+        .map(|c| {
+            if which(c) {
+                Call {
+                    ret: None,
+                    ..c.clone()
+                }
+            } else {
+                c.clone()
+            }
+        })
+        .collect();
+    if !linearizable(&weaker) {
+        *calls = weaker;
+    }
+}
+
+fn linearizable<S: Spec>(calls: &[Call<'_, S>]) -> bool {
+    let mut threads: BTreeMap<usize, Vec<&Call<'_, S>>> = BTreeMap::new();
+    for call in calls {
+        threads.entry(call.thread).or_default().push(call);
+    }
+    let threads: Vec<_> = threads.into_values().collect();
+
+    // A state is how many calls of each thread are linearized, and the spec after them
+    let start = (vec![0; threads.len()], S::default());
+    let mut seen = HashSet::from([start.clone()]);
+    let mut stack = vec![start];
+    while let Some((taken, spec)) = stack.pop() {
+        let next = threads.iter().zip(&taken).filter_map(|(t, &i)| t.get(i));
+        // Every call that returned already has to come before any call invoked after it
+        let Some(deadline) = next.map(|c| c.response).min() else {
+            return true;
+        };
+        for (t, calls) in threads.iter().enumerate() {
+            let Some(call) = calls.get(taken[t]) else {
+                continue;
+            };
+            if call.invoke > deadline {
+                continue;
+            }
+            let mut after = taken.clone();
+            after[t] += 1;
+            let mut applied = spec.clone();
+            let ret = applied.apply(call.op);
+            let mut states = vec![];
+            if call.ret.is_none_or(|r| *r == ret) {
+                states.push((after.clone(), applied));
+            }
+            // A pending call may also not have taken effect
+            if call.ret.is_none() {
+                states.push((after, spec.clone()));
+            }
+            for state in states {
+                if seen.insert(state.clone()) {
+                    stack.push(state);
+                }
+            }
+        }
+    }
+    false
+}
+
+/// Sequential models of common objects.
+pub mod models {
+    use super::Spec;
+    #[cfg(all(test, not(loom), not(pct)))]
+    use proptest::prelude::*;
+    use std::collections::{BTreeMap, VecDeque};
+    use std::fmt;
+    use std::hash::Hash;
+
+    /// A counter that starts at 0.
+    #[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+    pub struct Counter(pub u64);
+
+    #[derive(Clone, Debug, PartialEq)]
+    pub enum CounterOp {
+        /// Returns the value before the increment.
+        Increment,
+        Get,
+    }
+
+    /// A single value, such as the one behind a lock.
+    #[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+    pub struct Register<T>(pub T);
+
+    /// A write returns `None` and a read the value.
+    #[derive(Clone, Debug, PartialEq)]
+    pub enum RegisterOp<T> {
+        Write(T),
+        Read,
+    }
+
+    #[derive(Clone, Debug, PartialEq, Eq, Hash)]
+    pub struct Map<K, V>(pub BTreeMap<K, V>);
+
+    /// Each returns the value that was there before.
+    #[derive(Clone, Debug, PartialEq)]
+    pub enum MapOp<K, V> {
+        Insert(K, V),
+        Get(K),
+        Remove(K),
+    }
+
+    /// A first-in, first-out queue.
+    #[derive(Clone, Debug, PartialEq, Eq, Hash)]
+    pub struct Queue<T>(pub VecDeque<T>);
+
+    /// A last-in, first-out stack.
+    #[derive(Clone, Debug, PartialEq, Eq, Hash)]
+    pub struct Stack<T>(pub Vec<T>);
+
+    /// The ops of a [`Queue`] or a [`Stack`]. A push returns `None`, and so does a pop of an empty
+    /// one.
+    #[derive(Clone, Debug, PartialEq)]
+    pub enum PushPop<T> {
+        Push(T),
+        Pop,
+    }
+
+    // Derived, these would need `K`, `V` and `T` to be `Default`
+    impl<K, V> Default for Map<K, V> {
+        fn default() -> Self {
+            Self(BTreeMap::new())
+        }
+    }
+
+    impl<T> Default for Queue<T> {
+        fn default() -> Self {
+            Self(VecDeque::new())
+        }
+    }
+
+    impl<T> Default for Stack<T> {
+        fn default() -> Self {
+            Self(Vec::new())
+        }
+    }
+
+    impl Spec for Counter {
+        type Op = CounterOp;
+        type Ret = u64;
+
+        fn apply(&mut self, op: &CounterOp) -> u64 {
+            let value = self.0;
+            if let CounterOp::Increment = op {
+                self.0 += 1;
+            }
+            value
+        }
+    }
+
+    impl<T> Spec for Register<T>
+    where
+        T: Clone + fmt::Debug + Default + Eq + Hash + Send + Sync,
+    {
+        type Op = RegisterOp<T>;
+        type Ret = Option<T>;
+
+        fn apply(&mut self, op: &RegisterOp<T>) -> Option<T> {
+            match op {
+                RegisterOp::Write(value) => {
+                    self.0 = value.clone();
+                    None
+                }
+                RegisterOp::Read => Some(self.0.clone()),
+            }
+        }
+    }
+
+    impl<K, V> Spec for Map<K, V>
+    where
+        K: Clone + fmt::Debug + Ord + Hash + Send + Sync,
+        V: Clone + fmt::Debug + Eq + Hash + Send + Sync,
+    {
+        type Op = MapOp<K, V>;
+        type Ret = Option<V>;
+
+        fn apply(&mut self, op: &MapOp<K, V>) -> Option<V> {
+            match op {
+                MapOp::Insert(key, value) => self.0.insert(key.clone(), value.clone()),
+                MapOp::Get(key) => self.0.get(key).cloned(),
+                MapOp::Remove(key) => self.0.remove(key),
+            }
+        }
+    }
+
+    impl<T: Clone + fmt::Debug + Eq + Hash + Send + Sync> Spec for Queue<T> {
+        type Op = PushPop<T>;
+        type Ret = Option<T>;
+
+        fn apply(&mut self, op: &PushPop<T>) -> Option<T> {
+            match op {
+                PushPop::Push(value) => {
+                    self.0.push_back(value.clone());
+                    None
+                }
+                PushPop::Pop => self.0.pop_front(),
+            }
+        }
+    }
+
+    impl<T: Clone + fmt::Debug + Eq + Hash + Send + Sync> Spec for Stack<T> {
+        type Op = PushPop<T>;
+        type Ret = Option<T>;
+
+        fn apply(&mut self, op: &PushPop<T>) -> Option<T> {
+            match op {
+                PushPop::Push(value) => {
+                    self.0.push(value.clone());
+                    None
+                }
+                PushPop::Pop => self.0.pop(),
+            }
+        }
+    }
+
+    #[cfg(all(test, not(loom), not(pct)))]
+    impl Arbitrary for CounterOp {
+        type Parameters = ();
+        type Strategy = BoxedStrategy<Self>;
+
+        fn arbitrary_with((): ()) -> Self::Strategy {
+            prop_oneof![Just(CounterOp::Increment), Just(CounterOp::Get)].boxed()
+        }
+    }
+
+    #[cfg(all(test, not(loom), not(pct)))]
+    impl<T: Arbitrary + Clone + 'static> Arbitrary for RegisterOp<T> {
+        type Parameters = ();
+        type Strategy = BoxedStrategy<Self>;
+
+        fn arbitrary_with((): ()) -> Self::Strategy {
+            prop_oneof![
+                any::<T>().prop_map(RegisterOp::Write),
+                Just(RegisterOp::Read)
+            ]
+            .boxed()
+        }
+    }
+
+    #[cfg(all(test, not(loom), not(pct)))]
+    impl<T: Arbitrary + Clone + 'static> Arbitrary for PushPop<T> {
+        type Parameters = ();
+        type Strategy = BoxedStrategy<Self>;
+
+        fn arbitrary_with((): ()) -> Self::Strategy {
+            prop_oneof![any::<T>().prop_map(PushPop::Push), Just(PushPop::Pop)].boxed()
+        }
+    }
+}
+
+/// Up to `threads` threads of up to `CALLS` ops each, for property tests.
+#[cfg(all(test, not(loom), not(pct)))]
+pub(crate) fn programs<T: fmt::Debug>(
+    threads: usize,
+    op: impl Strategy<Value = T>,
+) -> impl Strategy<Value = Vec<Vec<T>>> {
+    vec(vec(op, 0..=CALLS), 1..=threads)
+}
+
+/// Map operations on a few keys, so that the threads' calls touch the same entries.
+#[cfg(all(test, not(loom), not(pct)))]
+pub(crate) fn map_op() -> impl Strategy<Value = models::MapOp<usize, usize>> {
+    use models::MapOp;
+
+    prop_oneof![
+        (0..4usize, any::<usize>()).prop_map(|(key, value)| MapOp::Insert(key, value)),
+        (0..4usize).prop_map(MapOp::Get),
+        (0..4usize).prop_map(MapOp::Remove),
+    ]
+}
+
+/// Programs for up to `consumers` threads that only pop, followed by up to `producers` threads that
+/// only push, with no more pops than pushes. Pops that block until there is a value then always
+/// finish.
+#[cfg(all(test, not(loom), not(pct)))]
+pub(crate) fn producers_and_consumers<T: Arbitrary + Clone>(
+    producers: usize,
+    consumers: usize,
+) -> impl Strategy<Value = Vec<Vec<models::PushPop<T>>>> {
+    use models::PushPop;
+
+    let pushes = vec(vec(any::<T>(), 0..=CALLS), 1..=producers);
+    let pops = vec(0..=CALLS, 1..=consumers);
+    (pushes, pops).prop_map(|(pushes, pops)| {
+        let mut left: usize = pushes.iter().map(Vec::len).sum();
+        let consumers = pops.into_iter().map(|pops: usize| {
+            let pops = pops.min(left);
+            left -= pops;
+            vec![PushPop::Pop; pops]
+        });
+        let producers = pushes
+            .into_iter()
+            .map(|values| values.into_iter().map(PushPop::Push).collect());
+        consumers.chain(producers).collect()
+    })
+}
+
+/// Runs each program on a thread of its own against the object `new` returns, calling `f` to
+/// perform each op like [`run`], and fails the test case if the calls have no linearization under
+/// `S`. Tries a few runs, each with a new object.
+#[cfg(all(test, not(loom), not(pct)))]
+pub(crate) fn assert_linearizable<S: Spec, T: Sync>(
+    programs: &[Vec<S::Op>],
+    new: impl Fn() -> T,
+    f: impl Fn(&T, &S::Op) -> S::Ret + Sync,
+) -> Result<(), TestCaseError> {
+    for _ in 0..RUNS {
+        let object = new();
+        let history = run::<S>(programs, |op| f(&object, op));
+        check::<S>(&history).map_err(|e| TestCaseError::fail(e.to_string()))?;
+    }
+    Ok(())
+}
+
+#[cfg(all(test, not(loom), not(pct)))]
+mod tests {
+    use super::models::*;
+    use super::*;
+    use dashmap::DashMap;
+    use proptest::prelude::any;
+    use proptest::test_runner::{Config, TestError, TestRunner};
+    use std::sync::atomic::AtomicU64;
+    use std::sync::{mpsc, Mutex};
+
+    const THREADS: usize = 4;
+    // Calls per thread in the histories recorded here, more than `programs` generates
+    const RECORDED_CALLS: usize = 200;
+
+    fn invoke<Ret>(time: usize, thread: usize, op: CounterOp) -> Event<CounterOp, Ret> {
+        Event::Invoke { time, thread, op }
+    }
+
+    fn ret<Op>(time: usize, thread: usize, ret: u64) -> Event<Op, u64> {
+        Event::Return { time, thread, ret }
+    }
+
+    /// A unique value for each call of each thread.
+    fn value(r: &Recorder<'_, impl Spec>, i: usize) -> u64 {
+        (r.thread() * RECORDED_CALLS + i) as u64
+    }
+
+    #[test]
+    fn orders_overlapping_calls_either_way() {
+        // Thread 1's increment runs within thread 0's, so it can take effect first
+        let history = History {
+            events: vec![
+                invoke(0, 0, CounterOp::Increment),
+                invoke(1, 1, CounterOp::Increment),
+                ret(2, 1, 0),
+                ret(3, 0, 1),
+                invoke(4, 1, CounterOp::Get),
+                ret(5, 1, 2),
+            ],
+        };
+        check::<Counter>(&history).unwrap();
+    }
+
+    #[test]
+    fn pending_calls_may_or_may_not_take_effect() {
+        let history = |get| History {
+            events: vec![
+                invoke(0, 0, CounterOp::Increment),
+                invoke(1, 1, CounterOp::Get),
+                ret(2, 1, get),
+            ],
+        };
+        check::<Counter>(&history(0)).unwrap();
+        check::<Counter>(&history(1)).unwrap();
+        check::<Counter>(&history(2)).unwrap_err();
+    }
+
+    #[test]
+    fn reports_smallest_failing_part() {
+        // Thread 1 reads 1 after the increment that returned 1; the other calls don't matter
+        let history = History {
+            events: vec![
+                invoke(0, 0, CounterOp::Increment),
+                invoke(1, 2, CounterOp::Get),
+                ret(2, 0, 0),
+                ret(3, 2, 0),
+                invoke(4, 0, CounterOp::Increment),
+                invoke(5, 3, CounterOp::Get),
+                ret(6, 0, 1),
+                ret(7, 3, 1),
+                invoke(8, 1, CounterOp::Get),
+                invoke(9, 2, CounterOp::Get),
+                ret(10, 1, 1),
+                ret(11, 2, 2),
+            ],
+        };
+        let failure = check::<Counter>(&history).unwrap_err();
+        let expected = [4, 6, 8, 10].map(|time| history.events[time].clone());
+        assert_eq!(failure.history.events, expected);
+    }
+
+    #[test]
+    fn mutex_counter_is_linearizable() {
+        let counter = Mutex::new(0);
+        let history = record::<Counter>(THREADS, |r| {
+            for i in 0..RECORDED_CALLS {
+                let op = if i % 4 == 0 {
+                    CounterOp::Get
+                } else {
+                    CounterOp::Increment
+                };
+                r.call(op, |op| {
+                    let mut value = counter.lock().unwrap();
+                    let before = *value;
+                    if let CounterOp::Increment = op {
+                        *value += 1;
+                    }
+                    before
+                });
+            }
+        });
+        check::<Counter>(&history).unwrap_or_else(|e| panic!("{e}"));
+    }
+
+    #[test]
+    fn reports_lost_updates() {
+        // Increments by a load and a separate store, with room for another thread in between
+        let counter = AtomicU64::new(0);
+        let history = record::<Counter>(THREADS, |r| {
+            for _ in 0..RECORDED_CALLS {
+                r.call(CounterOp::Increment, |_| {
+                    let value = counter.load(Ordering::SeqCst);
+                    thread::yield_now();
+                    counter.store(value + 1, Ordering::SeqCst);
+                    value
+                });
+            }
+        });
+        let failure = check::<Counter>(&history).unwrap_err();
+        // Two increments that can't both have returned what they did
+        assert_eq!(failure.history.events.len(), 4, "{failure}");
+        check::<Counter>(&failure.history).unwrap_err();
+    }
+
+    #[test]
+    fn shrinks_to_smallest_failing_program() {
+        let config = Config {
+            failure_persistence: None,
+            ..Config::default()
+        };
+        let result = TestRunner::new(config).run(&programs(4, any::<CounterOp>()), |programs| {
+            let new = || AtomicU64::new(0);
+            assert_linearizable::<Counter, _>(&programs, new, |counter, op| {
+                let value = counter.load(Ordering::SeqCst);
+                if let CounterOp::Increment = op {
+                    thread::yield_now();
+                    counter.store(value + 1, Ordering::SeqCst);
+                }
+                value
+            })
+        });
+        let Err(TestError::Fail(_, programs)) = result else {
+            panic!("lost updates went unnoticed: {result:?}");
+        };
+        let increment = vec![CounterOp::Increment];
+        assert_eq!(programs, [increment.clone(), increment]);
+    }
+
+    #[test]
+    fn dashmap_is_linearizable() {
+        let map = DashMap::new();
+        let history = record::<Map<u8, u64>>(THREADS, |r| {
+            for i in 0..RECORDED_CALLS {
+                let key = (i % 3) as u8;
+                let op = match i % 5 {
+                    0 | 1 => MapOp::Insert(key, value(r, i)),
+                    2 | 3 => MapOp::Get(key),
+                    _ => MapOp::Remove(key),
+                };
+                r.call(op, |op| match *op {
+                    MapOp::Insert(key, value) => map.insert(key, value),
+                    MapOp::Get(key) => map.get(&key).map(|v| *v),
+                    MapOp::Remove(key) => map.remove(&key).map(|(_, v)| v),
+                });
+            }
+        });
+        check::<Map<u8, u64>>(&history).unwrap_or_else(|e| panic!("{e}"));
+    }
+
+    #[test]
+    fn mpsc_is_linearizable() {
+        let (sender, receiver) = mpsc::channel();
+        // Only thread 0 receives, but the closure is shared by all of them
+        let receiver = Mutex::new(receiver);
+        let history = record::<Queue<u64>>(THREADS, |r| {
+            for i in 0..RECORDED_CALLS {
+                if r.thread() == 0 {
+                    r.call(PushPop::Pop, |_| receiver.lock().unwrap().try_recv().ok());
+                } else {
+                    let value = value(r, i);
+                    r.call(PushPop::Push(value), |_| {
+                        sender.send(value).unwrap();
+                        None
+                    });
+                }
+            }
+        });
+        check::<Queue<u64>>(&history).unwrap_or_else(|e| panic!("{e}"));
+    }
+
+    #[test]
+    fn mutex_vec_is_linearizable() {
+        let stack = Mutex::new(Vec::new());
+        let history = record::<Stack<u64>>(THREADS, |r| {
+            for i in 0..RECORDED_CALLS {
+                let op = if i % 2 == 0 {
+                    PushPop::Push(value(r, i))
+                } else {
+                    PushPop::Pop
+                };
+                r.call(op, |op| match *op {
+                    PushPop::Push(value) => {
+                        stack.lock().unwrap().push(value);
+                        None
+                    }
+                    PushPop::Pop => stack.lock().unwrap().pop(),
+                });
+            }
+        });
+        check::<Stack<u64>>(&history).unwrap_or_else(|e| panic!("{e}"));
+    }
+}
@@ -1,3 +1,10 @@
+//! Loom models of the primitives. The modules below only compile with `--cfg loom`, where
+//! [`crate::sync`] re-exports loom's types; run them with `cargo loom`. `buggy_concurrent_inc` uses
+//! loom's types directly, so it runs under a plain `cargo test` too.
+//!
+//! The same models also run under [`crate::pct`]'s randomised scheduler with `cargo pct`, except
+//! those that rely on loom's weak memory model.
+
 use loom::sync::atomic::AtomicUsize;
 use loom::sync::atomic::Ordering::{Acquire, Relaxed, Release};
 use loom::sync::Arc;
@@ -26,3 +33,939 @@ fn buggy_concurrent_inc() {
         assert_eq!(2, num.load(Relaxed));
     });
 }
+
+#[cfg(all(test, any(loom, pct)))]
+mod mutual_exclusion {
+    use crate::mutual_exclusion::{Dekker, Peterson};
+    use crate::sync::atomic::Ordering;
+    use crate::sync::model;
+    use crate::sync::thread;
+    use crate::sync::Arc;
+    use crate::sync::UnsafeCell;
+
+    /// Two threads increment an unsynchronised counter under the lock. Loom panics on the
+    /// concurrent access to `UnsafeCell` if mutual exclusion is violated.
+    fn check<L: Send + Sync + 'static>(lock: L, with_lock: fn(&L, usize, &dyn Fn())) {
+        let lock = Arc::new(lock);
+        let count = Arc::new(UnsafeCell::new(0));
+
+        let th = {
+            let lock = lock.clone();
+            let count = count.clone();
+            thread::spawn(move || with_lock(&lock, 1, &|| count.with_mut(|c| unsafe { *c += 1 })))
+        };
+        with_lock(&lock, 0, &|| count.with_mut(|c| unsafe { *c += 1 }));
+        th.join().unwrap();
+
+        assert_eq!(2, count.with(|c| unsafe { *c }));
+    }
+
+    fn peterson(ordering: Ordering) {
+        check(Peterson::new(ordering), |l, me, f| l.with_lock(me, f));
+    }
+
+    fn dekker(ordering: Ordering) {
+        check(Dekker::new(ordering), |l, me, f| l.with_lock(me, f));
+    }
+
+    #[test]
+    fn peterson_seqcst() {
+        model(|| peterson(Ordering::SeqCst));
+    }
+
+    #[test]
+    #[cfg(loom)]
+    #[should_panic(expected = "Causality violation")]
+    fn peterson_acqrel() {
+        model(|| peterson(Ordering::AcqRel));
+    }
+
+    #[test]
+    fn dekker_seqcst() {
+        model(|| dekker(Ordering::SeqCst));
+    }
+
+    #[test]
+    #[cfg(loom)]
+    #[should_panic(expected = "Causality violation")]
+    fn dekker_acqrel() {
+        model(|| dekker(Ordering::AcqRel));
+    }
+}
+
+#[cfg(all(test, any(loom, pct)))]
+mod blocking {
+    use crate::condvar::Condvar;
+    use crate::mutex::Mutex;
+    use crate::sync::model;
+    use crate::sync::thread;
+    use crate::sync::Arc;
+    use crate::sync::UnsafeCell;
+
+    #[test]
+    fn mutex_excludes() {
+        model(|| {
+            let mutex = Arc::new(Mutex::new(()));
+            let count = Arc::new(UnsafeCell::new(0));
+
+            let th = {
+                let mutex = mutex.clone();
+                let count = count.clone();
+                thread::spawn(move || {
+                    let _guard = mutex.lock();
+                    count.with_mut(|c| unsafe { *c += 1 });
+                })
+            };
+            {
+                let _guard = mutex.lock();
+                count.with_mut(|c| unsafe { *c += 1 });
+            }
+            th.join().unwrap();
+
+            assert_eq!(2, count.with(|c| unsafe { *c }));
+        });
+    }
+
+    #[test]
+    fn condvar_notifies() {
+        // A futex wait is a yield under loom, so the waiter spins. Bounding preemptions keeps the
+        // number of explored spin iterations finite.
+        let mut builder = model::Builder::new();
+        builder.preemption_bound = Some(3);
+        builder.check(|| {
+            let pair = Arc::new((Mutex::new(false), Condvar::new()));
+
+            let th = {
+                let pair = pair.clone();
+                thread::spawn(move || {
+                    let (ready, condvar) = &*pair;
+                    *ready.lock() = true;
+                    condvar.notify_one();
+                })
+            };
+
+            let (ready, condvar) = &*pair;
+            let guard = condvar.wait_while(ready.lock(), |ready| !*ready);
+            assert!(*guard);
+            drop(guard);
+
+            th.join().unwrap();
+        });
+    }
+}
+
+#[cfg(all(test, any(loom, pct)))]
+mod seqlock {
+    use crate::seqlock::SeqLock;
+    use crate::sync::model;
+    use crate::sync::thread;
+    use crate::sync::Arc;
+
+    #[test]
+    fn reader_sees_whole_write() {
+        model(|| {
+            let lock = Arc::new(SeqLock::new([0usize, 0]));
+
+            let writer = {
+                let lock = lock.clone();
+                thread::spawn(move || {
+                    lock.write([1, 1]);
+                    lock.write([2, 2]);
+                })
+            };
+
+            let [a, b] = lock.read();
+            assert_eq!(a, b);
+            writer.join().unwrap();
+            assert_eq!(lock.read(), [2, 2]);
+        });
+    }
+}
+
+#[cfg(all(test, any(loom, pct)))]
+mod rwlock {
+    use crate::rwlock::{RwLock, RwSpinLock, SpinWriteGuard, WriteGuard};
+    use crate::sync::thread;
+    use crate::sync::Arc;
+    use crate::sync::UnsafeCell;
+
+    /// Runs `f` in a second thread and `g` in the main thread, both with access to `value`. Loom
+    /// panics if a read overlaps a write of `value`.
+    fn race<L: Send + Sync + 'static>(
+        lock: L,
+        f: fn(&L, &UnsafeCell<usize>),
+        g: fn(&L, &UnsafeCell<usize>),
+    ) {
+        let lock = Arc::new(lock);
+        let value = Arc::new(UnsafeCell::new(0));
+
+        let th = {
+            let lock = lock.clone();
+            let value = value.clone();
+            thread::spawn(move || f(&lock, &value))
+        };
+        g(&lock, &value);
+        th.join().unwrap();
+    }
+
+    fn model(f: impl Fn() + Sync + Send + 'static) {
+        let mut builder = crate::sync::model::Builder::new();
+        builder.preemption_bound = Some(2);
+        builder.check(f);
+    }
+
+    fn increment(value: &UnsafeCell<usize>) {
+        value.with_mut(|v| unsafe { *v += 1 });
+    }
+
+    fn get(value: &UnsafeCell<usize>) -> usize {
+        value.with(|v| unsafe { *v })
+    }
+
+    #[test]
+    fn spin_write_excludes_read() {
+        model(|| {
+            race(
+                RwSpinLock::new(()),
+                |l, v| {
+                    let _guard = l.write();
+                    increment(v);
+                },
+                |l, v| {
+                    let _guard = l.read();
+                    get(v);
+                },
+            )
+        });
+    }
+
+    #[test]
+    fn spin_downgrade_excludes_write() {
+        model(|| {
+            race(
+                RwSpinLock::new(()),
+                |l, v| {
+                    let _guard = l.write();
+                    increment(v);
+                },
+                |l, v| {
+                    let guard = l.write();
+                    increment(v);
+                    let _guard = SpinWriteGuard::downgrade(guard);
+                    assert!(get(v) >= 1);
+                },
+            )
+        });
+    }
+
+    #[test]
+    fn blocking_write_excludes_read() {
+        model(|| {
+            race(
+                RwLock::new(()),
+                |l, v| {
+                    let _guard = l.write();
+                    increment(v);
+                },
+                |l, v| {
+                    let _guard = l.read();
+                    get(v);
+                },
+            )
+        });
+    }
+
+    #[test]
+    fn blocking_downgrade_excludes_write() {
+        model(|| {
+            race(
+                RwLock::new(()),
+                |l, v| {
+                    let _guard = l.write();
+                    increment(v);
+                },
+                |l, v| {
+                    let guard = l.write();
+                    increment(v);
+                    let _guard = WriteGuard::downgrade(guard);
+                    assert!(get(v) >= 1);
+                },
+            )
+        });
+    }
+}
+
+#[cfg(all(test, any(loom, pct)))]
+mod treiber {
+    use crate::reclaim::{Epoch, HazardPointers, Reclaim};
+    use crate::sync::model;
+    use crate::sync::thread;
+    use crate::sync::Arc;
+    use crate::treiber::TreiberStack;
+
+    /// Two threads pop concurrently. Without reclamation the thread that loses the race reads
+    /// `next` from a node the winner has already freed, and loom reports the race.
+    fn concurrent_pops<R: Reclaim + 'static>() {
+        let mut builder = model::Builder::new();
+        builder.preemption_bound = Some(3);
+        builder.check(|| {
+            let stack = Arc::new(TreiberStack::<usize, R>::new());
+            stack.push(1);
+            stack.push(2);
+
+            let th = {
+                let stack = stack.clone();
+                thread::spawn(move || stack.pop())
+            };
+            let a = stack.pop();
+            let b = th.join().unwrap();
+
+            let mut popped = [a.unwrap(), b.unwrap()];
+            popped.sort();
+            assert_eq!(popped, [1, 2]);
+        });
+    }
+
+    #[test]
+    #[cfg(loom)]
+    #[should_panic(expected = "Causality violation")]
+    fn immediate_pops() {
+        concurrent_pops::<crate::reclaim::Immediate>();
+    }
+
+    #[test]
+    fn hazard_pointers_pops() {
+        concurrent_pops::<HazardPointers>();
+    }
+
+    #[test]
+    fn epoch_pops() {
+        concurrent_pops::<Epoch>();
+    }
+}
+
+#[cfg(all(test, any(loom, pct)))]
+mod ring_buffer {
+    use crate::ring_buffer::{spsc, MpmcQueue};
+    use crate::sync::thread;
+    use crate::sync::Arc;
+
+    fn model(f: impl Fn() + Sync + Send + 'static) {
+        let mut builder = crate::sync::model::Builder::new();
+        builder.preemption_bound = Some(2);
+        builder.check(f);
+    }
+
+    #[test]
+    fn spsc_wraps_around() {
+        model(|| {
+            let (mut tx, mut rx) = spsc(1);
+            let th = thread::spawn(move || {
+                tx.send(1);
+                tx.send(2);
+            });
+            assert_eq!(rx.recv(), 1);
+            assert_eq!(rx.recv(), 2);
+            th.join().unwrap();
+        });
+    }
+
+    #[test]
+    fn mpmc_wraps_around() {
+        model(|| {
+            let queue = Arc::new(MpmcQueue::new(2));
+            let th = {
+                let queue = queue.clone();
+                thread::spawn(move || {
+                    for i in 0..3 {
+                        queue.send(i);
+                    }
+                })
+            };
+            for i in 0..3 {
+                assert_eq!(queue.recv(), i);
+            }
+            th.join().unwrap();
+        });
+    }
+
+    #[test]
+    fn mpmc_concurrent_sends() {
+        model(|| {
+            let queue = Arc::new(MpmcQueue::new(2));
+            let th = {
+                let queue = queue.clone();
+                thread::spawn(move || queue.try_send(1).unwrap())
+            };
+            queue.try_send(2).unwrap();
+            th.join().unwrap();
+
+            let mut received = [queue.try_recv().unwrap(), queue.try_recv().unwrap()];
+            received.sort();
+            assert_eq!(received, [1, 2]);
+        });
+    }
+
+    #[test]
+    fn mpmc_concurrent_recvs() {
+        model(|| {
+            let queue = Arc::new(MpmcQueue::new(2));
+            queue.send(1);
+            queue.send(2);
+            let th = {
+                let queue = queue.clone();
+                thread::spawn(move || queue.try_recv().unwrap())
+            };
+            let mut received = [queue.try_recv().unwrap(), th.join().unwrap()];
+            received.sort();
+            assert_eq!(received, [1, 2]);
+        });
+    }
+}
+
+#[cfg(all(test, any(loom, pct)))]
+mod channel {
+    use crate::channel::{lock_free, locked, Receive, RecvError};
+    use crate::sync::thread;
+
+    fn model(f: impl Fn() + Sync + Send + 'static) {
+        let mut builder = crate::sync::model::Builder::new();
+        builder.preemption_bound = Some(2);
+        builder.check(f);
+    }
+
+    #[test]
+    fn locked_recv_sees_value_then_disconnect() {
+        model(|| {
+            let (tx, rx) = locked::channel();
+            let th = thread::spawn(move || tx.send(1).unwrap());
+            assert_eq!(rx.recv(), Ok(1));
+            assert_eq!(rx.recv(), Err(RecvError));
+            th.join().unwrap();
+        });
+    }
+
+    #[test]
+    fn lock_free_recv_sees_value_then_disconnect() {
+        model(|| {
+            let (tx, rx) = lock_free::channel();
+            let th = thread::spawn(move || tx.send(1).unwrap());
+            assert_eq!(rx.recv(), Ok(1));
+            assert_eq!(rx.recv(), Err(RecvError));
+            th.join().unwrap();
+        });
+    }
+
+    #[test]
+    fn lock_free_concurrent_sends() {
+        model(|| {
+            let (tx, rx) = lock_free::channel();
+            let tx2 = tx.clone();
+            let th = thread::spawn(move || tx2.send(1).unwrap());
+            tx.send(2).unwrap();
+            drop(tx);
+            th.join().unwrap();
+
+            let mut received: Vec<_> = rx.iter().collect();
+            received.sort();
+            assert_eq!(received, [1, 2]);
+        });
+    }
+}
+
+#[cfg(all(test, any(loom, pct)))]
+mod oneshot {
+    use crate::channel::RecvError;
+    use crate::oneshot::Channel;
+    use crate::sync::model;
+    use crate::sync::thread;
+
+    #[test]
+    fn delivers_value() {
+        model(|| {
+            // Loom threads can't borrow, so the channel lives for the rest of the test run
+            let channel = Box::leak(Box::new(Channel::new()));
+            let (tx, rx) = channel.split();
+            let th = thread::spawn(move || tx.send(1));
+            assert_eq!(rx.recv(), Ok(1));
+            th.join().unwrap();
+        });
+    }
+
+    #[test]
+    fn dropped_sender_closes() {
+        model(|| {
+            let channel = Box::leak(Box::new(Channel::<()>::new()));
+            let (tx, rx) = channel.split();
+            let th = thread::spawn(move || drop(tx));
+            assert_eq!(rx.recv(), Err(RecvError));
+            th.join().unwrap();
+        });
+    }
+}
+
+#[cfg(all(test, any(loom, pct)))]
+mod latch {
+    use crate::latch::CountDownLatch;
+    use crate::sync::atomic::{AtomicBool, Ordering};
+    use crate::sync::model;
+    use crate::sync::thread;
+    use crate::sync::Arc;
+
+    #[test]
+    fn wait_sees_writes_before_count_down() {
+        model(|| {
+            let latch = Arc::new(CountDownLatch::new(2));
+            let flags = Arc::new([AtomicBool::new(false), AtomicBool::new(false)]);
+
+            let handles: Vec<_> = (0..2)
+                .map(|i| {
+                    let latch = latch.clone();
+                    let flags = flags.clone();
+                    thread::spawn(move || {
+                        flags[i].store(true, Ordering::Relaxed);
+                        latch.count_down();
+                    })
+                })
+                .collect();
+
+            latch.wait();
+            assert!(flags.iter().all(|f| f.load(Ordering::Relaxed)));
+
+            for handle in handles {
+                handle.join().unwrap();
+            }
+        });
+    }
+}
+
+#[cfg(all(test, any(loom, pct)))]
+mod barrier {
+    use crate::barrier::{Barrier, Phaser, SpinBarrier};
+    use crate::sync::atomic::{AtomicBool, Ordering};
+    use crate::sync::model;
+    use crate::sync::thread;
+    use crate::sync::Arc;
+
+    /// Two threads set a flag, meet at the barrier and check the other's flag, twice over. Exactly
+    /// one of them leads each phase.
+    fn meet<B: Send + Sync + 'static>(new: fn() -> B, wait: fn(&B) -> bool) {
+        let mut builder = model::Builder::new();
+        builder.preemption_bound = Some(2);
+        builder.check(move || {
+            let barrier = Arc::new(new());
+            let flags = Arc::new([[false, false], [false, false]].map(|p| p.map(AtomicBool::new)));
+
+            let run = move |me: usize, barrier: &B, flags: &[[AtomicBool; 2]; 2]| {
+                let mut leads = 0;
+                for phase in flags {
+                    phase[me].store(true, Ordering::Relaxed);
+                    leads += wait(barrier) as usize;
+                    assert!(phase[1 - me].load(Ordering::Relaxed));
+                }
+                leads
+            };
+
+            let th = {
+                let barrier = barrier.clone();
+                let flags = flags.clone();
+                thread::spawn(move || run(1, &barrier, &flags))
+            };
+            let leads = run(0, &barrier, &flags) + th.join().unwrap();
+            assert_eq!(leads, 2);
+        });
+    }
+
+    #[test]
+    fn spin_barrier_meets() {
+        meet(|| SpinBarrier::new(2), SpinBarrier::wait);
+    }
+
+    #[test]
+    fn barrier_meets() {
+        meet(|| Barrier::new(2), Barrier::wait);
+    }
+
+    #[test]
+    fn phaser_deregistration_advances() {
+        let mut builder = model::Builder::new();
+        builder.preemption_bound = Some(2);
+        builder.check(|| {
+            let phaser = Arc::new(Phaser::new(2));
+            let flag = Arc::new(AtomicBool::new(false));
+
+            let th = {
+                let phaser = phaser.clone();
+                let flag = flag.clone();
+                thread::spawn(move || {
+                    flag.store(true, Ordering::Relaxed);
+                    phaser.arrive_and_deregister();
+                })
+            };
+            assert_eq!(phaser.arrive_and_await_advance(), 1);
+            assert!(flag.load(Ordering::Relaxed));
+            // Only this party is left, so the next phase doesn't wait
+            assert_eq!(phaser.arrive_and_await_advance(), 2);
+            th.join().unwrap();
+        });
+    }
+}
+
+#[cfg(all(test, any(loom, pct)))]
+mod semaphore {
+    use crate::semaphore::Semaphore;
+    use crate::sync::model;
+    use crate::sync::thread;
+    use crate::sync::Arc;
+    use crate::sync::UnsafeCell;
+
+    #[test]
+    fn permits_exclude() {
+        let mut builder = model::Builder::new();
+        builder.preemption_bound = Some(3);
+        builder.check(|| {
+            let shared = Arc::new((Semaphore::new(2), UnsafeCell::new(0)));
+
+            let th = {
+                let shared = shared.clone();
+                thread::spawn(move || {
+                    let _permit = shared.0.acquire(2);
+                    shared.1.with_mut(|v| unsafe { *v += 1 });
+                })
+            };
+            {
+                // Two single permits must not overlap with the thread's pair
+                let _first = shared.0.acquire(1);
+                let _second = shared.0.acquire(1);
+                shared.1.with_mut(|v| unsafe { *v += 1 });
+            }
+            th.join().unwrap();
+
+            assert_eq!(shared.1.with(|v| unsafe { *v }), 2);
+            assert_eq!(shared.0.available_permits(), 2);
+        });
+    }
+}
+
+#[cfg(all(test, any(loom, pct)))]
+mod once {
+    use crate::once::OnceCell;
+    use crate::sync::atomic::{AtomicUsize, Ordering};
+    use crate::sync::model;
+    use crate::sync::thread;
+    use crate::sync::Arc;
+
+    #[test]
+    fn initialiser_runs_once() {
+        let mut builder = model::Builder::new();
+        builder.preemption_bound = Some(3);
+        builder.check(|| {
+            let shared = Arc::new((OnceCell::new(), AtomicUsize::new(0)));
+            let init = |shared: &(OnceCell<usize>, AtomicUsize), value| {
+                *shared.0.get_or_init(|| {
+                    shared.1.fetch_add(1, Ordering::Relaxed);
+                    value
+                })
+            };
+
+            let th = {
+                let shared = shared.clone();
+                thread::spawn(move || init(&shared, 1))
+            };
+            let value = init(&shared, 2);
+            assert_eq!(th.join().unwrap(), value);
+            assert_eq!(shared.1.load(Ordering::Relaxed), 1);
+        });
+    }
+}
+
+#[cfg(all(test, any(loom, pct)))]
+mod atomic_arc {
+    use crate::atomic_arc::AtomicArc;
+    use crate::reclaim::{Epoch, HazardPointers, Reclaim};
+    use crate::sync::model;
+    use crate::sync::thread;
+    use crate::sync::Arc;
+
+    /// A reader loads while the writer replaces the value twice. Loom reports a value dropped while
+    /// the reader still uses it, and one that is never dropped.
+    fn load_while_replacing<R: Reclaim + 'static>() {
+        let mut builder = model::Builder::new();
+        builder.preemption_bound = Some(3);
+        builder.check(|| {
+            let cell = Arc::new(AtomicArc::<usize, R>::new(Arc::new(0)));
+
+            let th = {
+                let cell = cell.clone();
+                thread::spawn(move || *cell.load())
+            };
+            cell.store(Arc::new(1));
+            assert_eq!(*cell.swap(Arc::new(2)), 1);
+            assert!(th.join().unwrap() <= 2);
+            assert_eq!(*cell.load(), 2);
+        });
+    }
+
+    #[test]
+    fn hazard_pointers_load_while_replacing() {
+        load_while_replacing::<HazardPointers>();
+    }
+
+    #[test]
+    fn epoch_load_while_replacing() {
+        load_while_replacing::<Epoch>();
+    }
+}
+
+#[cfg(all(test, any(loom, pct)))]
+mod rcu {
+    use crate::rcu::map::RcuMap;
+    use crate::rcu::RcuDomain;
+    use crate::sync::atomic::{AtomicPtr, Ordering};
+    use crate::sync::model;
+    use crate::sync::thread;
+    use crate::sync::Arc;
+    use crate::sync::UnsafeCell;
+
+    struct Node {
+        value: UnsafeCell<usize>,
+    }
+
+    fn node(value: usize) -> *mut Node {
+        Box::into_raw(Box::new(Node {
+            value: UnsafeCell::new(value),
+        }))
+    }
+
+    /// Stands in for freeing: loom reports it if a reader still uses the node, and a reader that
+    /// comes later reads 0.
+    unsafe fn poison(node: *mut Node) {
+        (*node).value.with_mut(|v| *v = 0);
+    }
+
+    /// A reader reads the shared node while the writer replaces it and, with `reclaim`, frees the
+    /// old one.
+    fn read_while_replacing(reclaim: fn(&RcuDomain, *mut Node)) {
+        let mut builder = model::Builder::new();
+        builder.preemption_bound = Some(3);
+        builder.check(move || {
+            let first = node(1);
+            let shared = Arc::new((RcuDomain::new(), AtomicPtr::new(first)));
+
+            let th = {
+                let shared = shared.clone();
+                thread::spawn(move || {
+                    let _guard = shared.0.read_lock();
+                    let node = shared.1.load(Ordering::Acquire);
+                    let value = unsafe { (*node).value.with(|v| *v) };
+                    assert_ne!(value, 0);
+                })
+            };
+            let second = node(2);
+            shared.1.store(second, Ordering::Release);
+            reclaim(&shared.0, first);
+            th.join().unwrap();
+
+            drop(shared);
+            unsafe {
+                drop(Box::from_raw(first));
+                drop(Box::from_raw(second));
+            }
+        });
+    }
+
+    #[test]
+    fn synchronize_waits_for_readers() {
+        read_while_replacing(|rcu, old| {
+            rcu.synchronize();
+            unsafe { poison(old) };
+        });
+    }
+
+    #[test]
+    fn call_rcu_waits_for_readers() {
+        read_while_replacing(|rcu, old| {
+            let old = old as usize;
+            rcu.call_rcu(move || unsafe { poison(old as *mut Node) });
+        });
+    }
+
+    #[test]
+    fn map_lookup_while_writing() {
+        let mut builder = model::Builder::new();
+        builder.preemption_bound = Some(2);
+        builder.check(|| {
+            let map = Arc::new(RcuMap::with_buckets(1));
+            map.insert(1, 1);
+
+            let th = {
+                let map = map.clone();
+                thread::spawn(move || map.get(&1))
+            };
+            map.insert(1, 2);
+            map.insert(2, 2);
+            assert!(matches!(th.join().unwrap(), Some(1 | 2)));
+            assert_eq!(map.get(&2), Some(2));
+        });
+    }
+}
+
+#[cfg(all(test, any(loom, pct)))]
+mod hash_map {
+    use crate::hash_map::lock_free::ConcurrentHashMap;
+    use crate::sync::model;
+    use crate::sync::thread;
+    use crate::sync::Arc;
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::BuildHasherDefault;
+
+    /// A map whose hashes are the same in every execution, as loom needs.
+    fn map() -> Arc<ConcurrentHashMap<usize, usize, BuildHasherDefault<DefaultHasher>>> {
+        Arc::new(ConcurrentHashMap::with_capacity_and_hasher(
+            0,
+            BuildHasherDefault::default(),
+        ))
+    }
+
+    #[test]
+    fn lookup_while_growing() {
+        let mut builder = model::Builder::new();
+        builder.preemption_bound = Some(2);
+        builder.check(|| {
+            // Two buckets under loom, so the third key grows the table
+            let map = map();
+            map.insert(0, 0);
+            map.insert(1, 1);
+
+            let th = {
+                let map = map.clone();
+                thread::spawn(move || map.get(&0).map(|v| *v))
+            };
+            map.insert(2, 2);
+            assert_eq!(map.remove(&1), Some(1));
+            assert_eq!(th.join().unwrap(), Some(0));
+            assert_eq!(map.get(&2).map(|v| *v), Some(2));
+        });
+    }
+
+    #[test]
+    fn replace_while_reading() {
+        let mut builder = model::Builder::new();
+        builder.preemption_bound = Some(3);
+        builder.check(|| {
+            let map = map();
+            map.insert(0, 0);
+
+            let th = {
+                let map = map.clone();
+                thread::spawn(move || *map.entry(0).and_modify(|v| v + 1).or_insert(0))
+            };
+            let seen = *map.get(&0).unwrap();
+            assert!(seen <= 1);
+            assert_eq!(th.join().unwrap(), 1);
+        });
+    }
+}
+
+#[cfg(all(test, loom))]
+mod memory_ordering {
+    //! On real hardware the ordering experiments only show some outcomes now and then. Loom
+    //! explores every execution up to its preemption bound, so each outcome it allows shows up on
+    //! every run. Outcomes are collected across executions with `std` types, which outlive the
+    //! model.
+
+    use crate::memory_ordering::{acqrel_relaxed_ordering, bad_mutex, mutex, relaxed_ordering};
+    use crate::sync::atomic::{AtomicUsize, Ordering};
+    use crate::sync::thread;
+    use crate::sync::Arc;
+    use crate::trace::atomic::AtomicBool;
+    use std::collections::BTreeSet;
+    use std::fmt::Debug;
+
+    const PREEMPTION_BOUND: usize = 2;
+
+    /// Returns the results of `f` in every execution loom explores.
+    fn outcomes<T>(
+        preemption_bound: usize,
+        f: impl Fn() -> T + Send + Sync + 'static,
+    ) -> BTreeSet<T>
+    where
+        T: Ord + Debug + Clone + Send + 'static,
+    {
+        let seen = std::sync::Arc::new(std::sync::Mutex::new(BTreeSet::new()));
+        let mut builder = loom::model::Builder::new();
+        builder.preemption_bound = Some(preemption_bound);
+        builder.check({
+            let seen = seen.clone();
+            move || {
+                let outcome = f();
+                seen.lock().unwrap().insert(outcome);
+            }
+        });
+        let seen = seen.lock().unwrap().clone();
+        println!("outcomes with at most {preemption_bound} preemptions: {seen:?}");
+        seen
+    }
+
+    /// Returns the most threads that were inside the critical section at once, in each execution
+    /// of two threads taking the lock.
+    fn occupancy(with_lock: fn(&AtomicBool, &dyn Fn())) -> BTreeSet<usize> {
+        outcomes(PREEMPTION_BOUND, move || {
+            let lock = Arc::new(AtomicBool::new(false));
+            let inside = Arc::new(AtomicUsize::new(0));
+            let most = Arc::new(AtomicUsize::new(0));
+            let enter = {
+                let (inside, most) = (inside.clone(), most.clone());
+                move |lock: &AtomicBool| {
+                    with_lock(lock, &|| {
+                        let now = inside.fetch_add(1, Ordering::SeqCst) + 1;
+                        most.fetch_max(now, Ordering::SeqCst);
+                        inside.fetch_sub(1, Ordering::SeqCst);
+                    })
+                }
+            };
+            let th = {
+                let (lock, enter) = (lock.clone(), enter.clone());
+                thread::spawn(move || enter(&lock))
+            };
+            enter(&lock);
+            th.join().unwrap();
+            most.load(Ordering::SeqCst)
+        })
+    }
+
+    #[test]
+    fn bad_mutex_lets_both_in() {
+        let seen = occupancy(|lock, f| bad_mutex(lock, f));
+        assert!(seen.contains(&2), "no execution had both threads inside");
+    }
+
+    #[test]
+    fn mutex_lets_one_in() {
+        let seen = occupancy(|lock, f| mutex(lock, f));
+        assert_eq!(seen, BTreeSet::from([1]), "two threads were inside at once");
+        println!(
+            "exclusion was only checked for executions with at most {PREEMPTION_BOUND} preemptions"
+        );
+    }
+
+    #[test]
+    fn relaxed_ordering_has_both_outcomes() {
+        // `y` ends up set either way; `x` copies whatever the first thread read of it
+        let seen = outcomes(PREEMPTION_BOUND, relaxed_ordering);
+        assert_eq!(seen, BTreeSet::from([(false, true), (true, true)]));
+    }
+
+    #[test]
+    fn acqrel_relaxed_ordering_has_every_outcome() {
+        // Five threads waiting on latches are too many for loom to preempt. It still explores which
+        // store each acquire load reads, which is all the outcomes need.
+        let seen = outcomes(0, acqrel_relaxed_ordering);
+        assert_eq!(seen, BTreeSet::from([0, 1, 2]));
+        println!(
+            "0 means the readers disagree on the order of the stores, which acquire/release allows. \
+             SeqCst forbids it, but loom treats SeqCst loads and stores as acquire/release, so it \
+             would report 0 for SeqCst too"
+        );
+    }
+}
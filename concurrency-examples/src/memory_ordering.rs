@@ -1,18 +1,28 @@
-use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
-use std::sync::Arc;
-use std::thread;
-
-static X: AtomicBool = AtomicBool::new(false);
-
-fn relaxed_ordering() -> (bool, bool) {
+use crate::barrier::SpinBarrier;
+use crate::latch::CountDownLatch;
+#[cfg(not(any(loom, pct)))]
+use crate::oneshot;
+use crate::sync::{hint, Arc};
+use crate::trace::{
+    atomic::{AtomicBool, Ordering},
+    thread,
+};
+#[cfg(not(any(loom, pct)))]
+use std::thread::Scope;
+
+pub(crate) fn relaxed_ordering() -> (bool, bool) {
     // Shared flags
     let x = Arc::new(AtomicBool::new(false));
     let y = Arc::new(AtomicBool::new(false));
+    // Releases both threads at once so that their accesses overlap
+    let start = Arc::new(SpinBarrier::new(2));
 
     let t1 = {
         let x = Arc::clone(&x);
         let y = Arc::clone(&y);
+        let start = Arc::clone(&start);
         thread::spawn(move || {
+            start.wait();
             let a = y.load(Ordering::Relaxed);
             x.store(a, Ordering::Relaxed);
         })
@@ -21,7 +31,9 @@ fn relaxed_ordering() -> (bool, bool) {
     let t2 = {
         let x = Arc::clone(&x);
         let y = Arc::clone(&y);
+        let start = Arc::clone(&start);
         thread::spawn(move || {
+            start.wait();
             let _b = x.load(Ordering::Relaxed);
             y.store(true, Ordering::Relaxed);
         })
@@ -36,91 +48,152 @@ fn relaxed_ordering() -> (bool, bool) {
     (final_x, final_y)
 }
 
-fn acqrel_relaxed_ordering() -> i32 {
-    let x = Arc::new(AtomicBool::new(false));
-    let y = Arc::new(AtomicBool::new(false));
-    let z = Arc::new(AtomicI32::new(0));
+struct Flags {
+    x: AtomicBool,
+    y: AtomicBool,
+    x_stored: CountDownLatch,
+    y_stored: CountDownLatch,
+    start: SpinBarrier,
+}
 
-    let t1 = {
-        let x = Arc::clone(&x);
-        thread::spawn(move || {
-            x.store(true, Ordering::Release);
-        })
-    };
+impl Flags {
+    fn new() -> Self {
+        Self {
+            x: AtomicBool::new(false),
+            y: AtomicBool::new(false),
+            x_stored: CountDownLatch::new(1),
+            y_stored: CountDownLatch::new(1),
+            start: SpinBarrier::new(4),
+        }
+    }
+}
 
-    let t2 = {
-        let y = Arc::clone(&y);
-        thread::spawn(move || {
-            y.store(true, Ordering::Release);
-        })
-    };
+#[cfg(not(any(loom, pct)))]
+pub(crate) fn acqrel_relaxed_ordering() -> i32 {
+    /// Runs `f` in a thread of `s` once all four threads have started.
+    fn spawn<'scope>(
+        s: &'scope Scope<'scope, '_>,
+        flags: &'scope Flags,
+        f: impl FnOnce(&Flags) + Send + 'scope,
+    ) {
+        thread::spawn_scoped(s, move || {
+            flags.start.wait();
+            f(flags);
+        });
+    }
 
-    let t3 = {
-        let x = Arc::clone(&x);
-        let y = Arc::clone(&y);
-        let z = Arc::clone(&z);
-        thread::spawn(move || {
-            while !x.load(Ordering::Acquire) {}
-            if y.load(Ordering::Acquire) {
-                z.fetch_add(1, Ordering::Relaxed);
-            }
-        })
-    };
+    let flags = &Flags::new();
+    // Whether each reader saw the other flag set
+    let mut saw_y = oneshot::Channel::new();
+    let mut saw_x = oneshot::Channel::new();
+    let (saw_y_tx, saw_y_rx) = saw_y.split();
+    let (saw_x_tx, saw_x_rx) = saw_x.split();
+
+    std::thread::scope(|s| {
+        spawn(s, flags, |f| {
+            f.x.store(true, Ordering::Release);
+            f.x_stored.count_down();
+        });
+        spawn(s, flags, |f| {
+            f.y.store(true, Ordering::Release);
+            f.y_stored.count_down();
+        });
+        spawn(s, flags, |f| {
+            f.x_stored.wait();
+            saw_y_tx.send(f.y.load(Ordering::Acquire));
+        });
+        spawn(s, flags, |f| {
+            f.y_stored.wait();
+            saw_x_tx.send(f.x.load(Ordering::Acquire));
+        });
+
+        saw_y_rx.recv().unwrap() as i32 + saw_x_rx.recv().unwrap() as i32
+    })
+}
 
-    let t4 = {
-        let x = Arc::clone(&x);
-        let y = Arc::clone(&y);
-        let z = Arc::clone(&z);
+// Loom explores every interleaving anyway, and with four threads spinning in the barrier there are
+// too many for it
+#[cfg(any(loom, pct))]
+const START_TOGETHER: bool = !cfg!(loom);
+
+// Loom and pct have no `thread::scope`, so the readers can't borrow the channels, and return
+// whether they saw the other flag set instead
+#[cfg(any(loom, pct))]
+pub(crate) fn acqrel_relaxed_ordering() -> i32 {
+    let flags = Arc::new(Flags::new());
+
+    let spawn = |f: fn(&Flags) -> bool| {
+        let flags = Arc::clone(&flags);
         thread::spawn(move || {
-            while !y.load(Ordering::Acquire) {}
-            if x.load(Ordering::Acquire) {
-                z.fetch_add(1, Ordering::Relaxed);
+            if START_TOGETHER {
+                flags.start.wait();
             }
+            f(&flags)
         })
     };
-
-    t1.join().unwrap();
-    t2.join().unwrap();
-    t3.join().unwrap();
-    t4.join().unwrap();
-
-    z.load(Ordering::Acquire)
+    // The writers see nothing
+    let threads = [
+        spawn(|f| {
+            f.x.store(true, Ordering::Release);
+            f.x_stored.count_down();
+            false
+        }),
+        spawn(|f| {
+            f.y.store(true, Ordering::Release);
+            f.y_stored.count_down();
+            false
+        }),
+        spawn(|f| {
+            f.x_stored.wait();
+            f.y.load(Ordering::Acquire)
+        }),
+        spawn(|f| {
+            f.y_stored.wait();
+            f.x.load(Ordering::Acquire)
+        }),
+    ];
+
+    threads.into_iter().map(|t| t.join().unwrap() as i32).sum()
 }
 
-static LOCK: AtomicBool = AtomicBool::new(false);
-
-fn bad_mutex(f: impl FnOnce()) {
+// The lock is passed in rather than a `static`, since loom's atomics can't be created in a const
+// context
+pub(crate) fn bad_mutex(lock: &AtomicBool, f: impl FnOnce()) {
     // Wait for the lock to become false
-    while LOCK.load(Ordering::Acquire) {
+    while lock.load(Ordering::Acquire) {
         // Compiler hint to a avoid spinning
-        std::hint::spin_loop();
+        hint::spin_loop();
     }
-    LOCK.store(true, Ordering::Release);
+    lock.store(true, Ordering::Release);
     // Call f while holding the lock
     f();
     // Release the lock
-    LOCK.store(false, Ordering::Release);
+    lock.store(false, Ordering::Release);
 }
 
-fn mutex(f: impl FnOnce()) {
+pub(crate) fn mutex(lock: &AtomicBool, f: impl FnOnce()) {
     // Wait for the lock to become false
     loop {
-        let take = LOCK.compare_exchange(false, true, Ordering::AcqRel, Ordering::Relaxed);
+        let take = lock.compare_exchange(false, true, Ordering::AcqRel, Ordering::Relaxed);
         match take {
             Ok(false) => break,
             Ok(true) | Err(false) => unreachable!(),
-            Err(true) => std::hint::spin_loop(),
+            Err(true) => hint::spin_loop(),
         }
     }
     // Call f while holding the lock
     f();
     // Release the lock
-    LOCK.store(false, Ordering::Release);
+    lock.store(false, Ordering::Release);
 }
 
-#[cfg(test)]
+#[cfg(all(test, not(loom), not(pct)))]
 mod tests {
     use super::*;
+    use crate::linearizability::models::{Counter, CounterOp};
+    use crate::linearizability::{assert_linearizable, programs};
+    use proptest::prelude::*;
+    use std::sync::atomic::AtomicU64;
 
     #[test]
     fn relaxed_ordering_reorders() {
@@ -141,4 +214,22 @@ mod tests {
         assert!(results.contains(&1));
         // assert!(results.contains(&0));
     }
+
+    proptest! {
+        #[test]
+        fn mutex_counter_is_linearizable(programs in programs(4, any::<CounterOp>())) {
+            let new = || (AtomicBool::new(false), AtomicU64::new(0));
+            assert_linearizable::<Counter, _>(&programs, new, |(lock, count), op| {
+                let mut before = 0;
+                // Relaxed, as the lock orders the accesses
+                mutex(lock, || {
+                    before = count.load(Ordering::Relaxed);
+                    if let CounterOp::Increment = op {
+                        count.store(before + 1, Ordering::Relaxed);
+                    }
+                });
+                before
+            })?;
+        }
+    }
 }
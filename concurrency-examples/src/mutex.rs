@@ -0,0 +1,168 @@
+//! A mutex that sleeps on [`crate::futex`] instead of spinning.
+//!
+//! The state is `0` when unlocked, `1` when locked and `2` when locked with possible waiters. Only
+//! an unlock from state `2` has to make the wake syscall.
+
+use crate::futex::{atomic_wait, wake_one};
+use crate::sync::{
+    atomic::{AtomicU32, Ordering},
+    hint, UnsafeCell,
+};
+use std::ops::{Deref, DerefMut};
+
+const UNLOCKED: u32 = 0;
+const LOCKED: u32 = 1;
+const CONTENDED: u32 = 2;
+
+// Spinning a little before sleeping pays off when the lock is held briefly. Loom would explore
+// every spin iteration, so it doesn't spin.
+const SPIN_LIMIT: usize = if cfg!(any(loom, pct)) { 0 } else { 100 };
+
+pub struct Mutex<T> {
+    state: AtomicU32,
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Sync for Mutex<T> {}
+
+pub struct MutexGuard<'a, T> {
+    pub(crate) mutex: &'a Mutex<T>,
+}
+
+unsafe impl<T: Sync> Sync for MutexGuard<'_, T> {}
+
+impl<T> Mutex<T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            state: AtomicU32::new(UNLOCKED),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        if self
+            .state
+            .compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            self.lock_contended();
+        }
+        MutexGuard { mutex: self }
+    }
+
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        self.state
+            .compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| MutexGuard { mutex: self })
+    }
+
+    #[cold]
+    fn lock_contended(&self) {
+        let mut spins = SPIN_LIMIT;
+        while spins > 0 && self.state.load(Ordering::Relaxed) == LOCKED {
+            spins -= 1;
+            hint::spin_loop();
+        }
+
+        if self
+            .state
+            .compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+        {
+            return;
+        }
+
+        // Whoever unlocks after this swap sees `CONTENDED` and wakes a waiter
+        while self.state.swap(CONTENDED, Ordering::Acquire) != UNLOCKED {
+            atomic_wait(&self.state, CONTENDED);
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+impl<T: Default> Default for Mutex<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.mutex.value.with(|value| unsafe { &*value })
+    }
+}
+
+impl<T> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.mutex.value.with_mut(|value| unsafe { &mut *value })
+    }
+}
+
+impl<T> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        if self.mutex.state.swap(UNLOCKED, Ordering::Release) == CONTENDED {
+            wake_one(&self.mutex.state);
+        }
+    }
+}
+
+#[cfg(all(test, not(loom), not(pct)))]
+mod tests {
+    use super::*;
+    use crate::linearizability::models::{Counter, CounterOp};
+    use crate::linearizability::{assert_linearizable, programs};
+    use proptest::prelude::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn mutex_counts() {
+        let count = Arc::new(Mutex::new(0));
+
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let count = Arc::clone(&count);
+                thread::spawn(move || {
+                    for _ in 0..10_000 {
+                        *count.lock() += 1;
+                    }
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert_eq!(*count.lock(), 40_000);
+    }
+
+    #[test]
+    fn try_lock_fails_while_locked() {
+        let mutex = Mutex::new(());
+        let guard = mutex.lock();
+        assert!(mutex.try_lock().is_none());
+        drop(guard);
+        assert!(mutex.try_lock().is_some());
+    }
+
+    proptest! {
+        #[test]
+        fn counter_is_linearizable(programs in programs(4, any::<CounterOp>())) {
+            assert_linearizable::<Counter, _>(&programs, || Mutex::new(0), |count, op| {
+                let mut count = count.lock();
+                let before = *count;
+                if let CounterOp::Increment = op {
+                    *count += 1;
+                }
+                before
+            })?;
+        }
+    }
+}
@@ -0,0 +1,280 @@
+//! Two-thread locks built from plain loads and stores.
+//!
+//! Both locks take the `Ordering` they use as a constructor argument. Loads use the acquiring half
+//! of it and stores the releasing half, so `Acquire`, `Release` and `AcqRel` all give an
+//! acquire/release lock. That is not enough: each thread stores its own flag and then loads the
+//! other thread's flag, and acquire/release allows that load to be served before the store becomes
+//! visible. Both threads can then see the other's flag as `false` and enter the critical section.
+//!
+//! With `SeqCst` every store in `lock` is followed by a `SeqCst` fence. The fence is what forbids
+//! the store-load reordering. It is redundant on top of `SeqCst` accesses on real hardware, but
+//! loom treats `SeqCst` accesses as `AcqRel` and only models `SeqCst` fences fully.
+
+use crate::sync::{
+    atomic::{fence, AtomicBool, AtomicUsize, Ordering},
+    hint,
+};
+
+fn load_ordering(ordering: Ordering) -> Ordering {
+    match ordering {
+        Ordering::Release | Ordering::AcqRel => Ordering::Acquire,
+        o => o,
+    }
+}
+
+fn store_ordering(ordering: Ordering) -> Ordering {
+    match ordering {
+        Ordering::Acquire | Ordering::AcqRel => Ordering::Release,
+        o => o,
+    }
+}
+
+/// Peterson's lock for threads `0` and `1`.
+pub struct Peterson {
+    flag: [AtomicBool; 2],
+    turn: AtomicUsize,
+    ordering: Ordering,
+}
+
+impl Peterson {
+    pub fn new(ordering: Ordering) -> Self {
+        Self {
+            flag: [AtomicBool::new(false), AtomicBool::new(false)],
+            turn: AtomicUsize::new(0),
+            ordering,
+        }
+    }
+
+    fn store_fence(&self) {
+        if self.ordering == Ordering::SeqCst {
+            fence(Ordering::SeqCst);
+        }
+    }
+
+    pub fn lock(&self, me: usize) {
+        assert!(me < 2, "thread {me} is neither 0 nor 1");
+        let other = 1 - me;
+        let (load, store) = (load_ordering(self.ordering), store_ordering(self.ordering));
+
+        // Announce the intent to enter
+        self.flag[me].store(true, store);
+        self.store_fence();
+        // Let the other thread go first if it wants to
+        self.turn.store(other, store);
+        self.store_fence();
+        while self.flag[other].load(load) && self.turn.load(load) == other {
+            hint::spin_loop();
+        }
+    }
+
+    pub fn unlock(&self, me: usize) {
+        assert!(me < 2, "thread {me} is neither 0 nor 1");
+        self.flag[me].store(false, store_ordering(self.ordering));
+    }
+
+    /// Calls `f` while holding the lock on behalf of thread `me`.
+    pub fn with_lock<R>(&self, me: usize, f: impl FnOnce() -> R) -> R {
+        self.lock(me);
+        let r = f();
+        self.unlock(me);
+        r
+    }
+}
+
+/// Dekker's lock for threads `0` and `1`.
+pub struct Dekker {
+    flag: [AtomicBool; 2],
+    turn: AtomicUsize,
+    ordering: Ordering,
+}
+
+impl Dekker {
+    pub fn new(ordering: Ordering) -> Self {
+        Self {
+            flag: [AtomicBool::new(false), AtomicBool::new(false)],
+            turn: AtomicUsize::new(0),
+            ordering,
+        }
+    }
+
+    fn store_fence(&self) {
+        if self.ordering == Ordering::SeqCst {
+            fence(Ordering::SeqCst);
+        }
+    }
+
+    pub fn lock(&self, me: usize) {
+        assert!(me < 2, "thread {me} is neither 0 nor 1");
+        let other = 1 - me;
+        let (load, store) = (load_ordering(self.ordering), store_ordering(self.ordering));
+
+        self.flag[me].store(true, store);
+        self.store_fence();
+        while self.flag[other].load(load) {
+            if self.turn.load(load) != me {
+                // Back off until it is our turn
+                self.flag[me].store(false, store);
+                while self.turn.load(load) != me {
+                    hint::spin_loop();
+                }
+                self.flag[me].store(true, store);
+                self.store_fence();
+            } else {
+                hint::spin_loop();
+            }
+        }
+    }
+
+    pub fn unlock(&self, me: usize) {
+        assert!(me < 2, "thread {me} is neither 0 nor 1");
+        let store = store_ordering(self.ordering);
+        self.turn.store(1 - me, store);
+        self.flag[me].store(false, store);
+    }
+
+    /// Calls `f` while holding the lock on behalf of thread `me`.
+    pub fn with_lock<R>(&self, me: usize, f: impl FnOnce() -> R) -> R {
+        self.lock(me);
+        let r = f();
+        self.unlock(me);
+        r
+    }
+}
+
+#[cfg(all(test, not(loom), not(pct)))]
+mod tests {
+    use super::*;
+    use crate::linearizability::models::{Counter, CounterOp};
+    use crate::linearizability::{check, programs, record, RUNS};
+    use proptest::prelude::*;
+    use proptest::test_runner::TestCaseError;
+    use std::sync::Arc;
+    use std::thread;
+
+    /// Runs two threads through the critical section `iterations` times each and returns the number
+    /// of times a thread found the other one already inside.
+    fn count_violations(
+        iterations: usize,
+        lock: impl Fn(usize, &dyn Fn()) + Send + Sync + 'static,
+    ) -> usize {
+        let lock = Arc::new(lock);
+        let inside = Arc::new(AtomicUsize::new(0));
+        let violations = Arc::new(AtomicUsize::new(0));
+
+        let handles: Vec<_> = (0..2)
+            .map(|me| {
+                let lock = Arc::clone(&lock);
+                let inside = Arc::clone(&inside);
+                let violations = Arc::clone(&violations);
+                thread::spawn(move || {
+                    for _ in 0..iterations {
+                        lock(me, &|| {
+                            if inside.fetch_add(1, Ordering::Relaxed) != 0 {
+                                violations.fetch_add(1, Ordering::Relaxed);
+                            }
+                            inside.fetch_sub(1, Ordering::Relaxed);
+                        });
+                    }
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        violations.load(Ordering::Relaxed)
+    }
+
+    /// Runs each program on one of two threads, which increment or read a counter under the
+    /// lock `new` returns, and fails the test case if the calls have no linearization in any of a
+    /// few runs.
+    fn counter_is_linearizable<L: Sync>(
+        programs: &[Vec<CounterOp>],
+        new: impl Fn() -> L,
+        with_lock: impl Fn(&L, usize, &mut dyn FnMut()) + Sync,
+    ) -> Result<(), TestCaseError> {
+        for _ in 0..RUNS {
+            let (lock, count) = (new(), AtomicUsize::new(0));
+            // Not `linearizability::run`, as the locks need to know which thread is which
+            let history = record::<Counter>(programs.len(), |r| {
+                let me = r.thread();
+                for op in &programs[me] {
+                    r.call(op.clone(), |op| {
+                        let mut before = 0;
+                        with_lock(&lock, me, &mut || {
+                            before = count.load(Ordering::Relaxed);
+                            if let CounterOp::Increment = op {
+                                count.store(before + 1, Ordering::Relaxed);
+                            }
+                        });
+                        before as u64
+                    });
+                }
+            });
+            check::<Counter>(&history).map_err(|e| TestCaseError::fail(e.to_string()))?;
+        }
+        Ok(())
+    }
+
+    fn peterson(ordering: Ordering, iterations: usize) -> usize {
+        let lock = Peterson::new(ordering);
+        count_violations(iterations, move |me, f| lock.with_lock(me, f))
+    }
+
+    fn dekker(ordering: Ordering, iterations: usize) -> usize {
+        let lock = Dekker::new(ordering);
+        count_violations(iterations, move |me, f| lock.with_lock(me, f))
+    }
+
+    #[test]
+    fn peterson_seqcst_excludes() {
+        assert_eq!(peterson(Ordering::SeqCst, 2_000), 0);
+    }
+
+    #[test]
+    fn dekker_seqcst_excludes() {
+        assert_eq!(dekker(Ordering::SeqCst, 2_000), 0);
+    }
+
+    /// Store-load reordering only shows when the two threads run on different cores.
+    fn single_core() -> bool {
+        thread::available_parallelism().map_or(true, |n| n.get() < 2)
+    }
+
+    #[test]
+    fn peterson_acqrel_violates() {
+        if single_core() {
+            return;
+        }
+        assert!(peterson(Ordering::AcqRel, 1_000_000) > 0);
+    }
+
+    #[test]
+    fn dekker_acqrel_violates() {
+        if single_core() {
+            return;
+        }
+        assert!(dekker(Ordering::AcqRel, 1_000_000) > 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "thread 2 is neither 0 nor 1")]
+    fn rejects_a_third_thread() {
+        Peterson::new(Ordering::SeqCst).lock(2);
+    }
+
+    proptest! {
+        #[test]
+        fn peterson_counter_is_linearizable(programs in programs(2, any::<CounterOp>())) {
+            let new = || Peterson::new(Ordering::SeqCst);
+            counter_is_linearizable(&programs, new, |lock, me, f| lock.with_lock(me, f))?;
+        }
+
+        #[test]
+        fn dekker_counter_is_linearizable(programs in programs(2, any::<CounterOp>())) {
+            let new = || Dekker::new(Ordering::SeqCst);
+            counter_is_linearizable(&programs, new, |lock, me, f| lock.with_lock(me, f))?;
+        }
+    }
+}
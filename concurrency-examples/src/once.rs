@@ -0,0 +1,275 @@
+//! One-time initialisation: [`OnceCell`] and [`Lazy`].
+//!
+//! The cell's state moves from `UNINIT` to `RUNNING` when a thread wins the race to initialise it,
+//! and then to `DONE`, or to `POISONED` if the initialiser panics. Threads that lose the race wait
+//! for the winner to finish. With the `std` feature they sleep on [`crate::futex`]; without it they
+//! spin, and the atomics come from `portable-atomic` so that targets without compare-and-swap, like
+//! the RP2040, can use the cell too. Such targets need `portable-atomic`'s `critical-section`
+//! feature enabled in the final binary.
+
+use crate::sync::{
+    atomic::{AtomicU32, Ordering},
+    UnsafeCell,
+};
+use core::mem::{self, MaybeUninit};
+use core::ops::Deref;
+
+const UNINIT: u32 = 0;
+const RUNNING: u32 = 1;
+const DONE: u32 = 2;
+const POISONED: u32 = 3;
+
+fn wait(state: &AtomicU32, expected: u32) {
+    #[cfg(feature = "std")]
+    crate::futex::atomic_wait(state, expected);
+    #[cfg(not(feature = "std"))]
+    {
+        let _ = (state, expected);
+        core::hint::spin_loop();
+    }
+}
+
+fn wake_all(state: &AtomicU32) {
+    #[cfg(feature = "std")]
+    crate::futex::wake_all(state);
+    #[cfg(not(feature = "std"))]
+    let _ = state;
+}
+
+/// Marks the cell poisoned if the initialiser unwinds.
+struct PoisonOnUnwind<'a>(&'a AtomicU32);
+
+impl Drop for PoisonOnUnwind<'_> {
+    fn drop(&mut self) {
+        self.0.store(POISONED, Ordering::Release);
+        wake_all(self.0);
+    }
+}
+
+pub struct OnceCell<T> {
+    state: AtomicU32,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+unsafe impl<T: Send + Sync> Sync for OnceCell<T> {}
+
+impl<T> OnceCell<T> {
+    #[cfg(not(loom))]
+    pub const fn new() -> Self {
+        Self {
+            state: AtomicU32::new(UNINIT),
+            value: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    // Loom's atomics can't be created in a const context
+    #[cfg(loom)]
+    pub fn new() -> Self {
+        Self {
+            state: AtomicU32::new(UNINIT),
+            value: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    /// Returns the value if the cell has been initialised.
+    pub fn get(&self) -> Option<&T> {
+        if self.state.load(Ordering::Acquire) == DONE {
+            Some(unsafe { self.value_ref() })
+        } else {
+            None
+        }
+    }
+
+    /// Initialises the cell with `value`, or gives the value back if the cell is already
+    /// initialised or being initialised.
+    pub fn set(&self, value: T) -> Result<(), T> {
+        let mut value = Some(value);
+        self.try_initialize(|| value.take().unwrap());
+        match value {
+            Some(value) => Err(value),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the value, calling `init` to create it if no other thread has. Threads that call
+    /// this while another is running its `init` wait for it. Calling it again from inside `init`
+    /// deadlocks.
+    ///
+    /// # Panics
+    ///
+    /// If an initialiser panicked.
+    pub fn get_or_init(&self, init: impl FnOnce() -> T) -> &T {
+        self.try_initialize(init);
+        self.wait()
+    }
+
+    /// Waits for another thread to initialise the cell.
+    ///
+    /// # Panics
+    ///
+    /// If the initialiser panicked.
+    pub fn wait(&self) -> &T {
+        loop {
+            match self.state.load(Ordering::Acquire) {
+                DONE => return unsafe { self.value_ref() },
+                POISONED => panic!("OnceCell poisoned by a panicking initialiser"),
+                state => wait(&self.state, state),
+            }
+        }
+    }
+
+    pub fn is_poisoned(&self) -> bool {
+        self.state.load(Ordering::Relaxed) == POISONED
+    }
+
+    /// # Safety
+    ///
+    /// The state must be `DONE`.
+    unsafe fn value_ref(&self) -> &T {
+        self.value
+            .with(|value| unsafe { (*value).assume_init_ref() })
+    }
+
+    /// Runs `init` if no other thread has started initialising the cell.
+    fn try_initialize(&self, init: impl FnOnce() -> T) {
+        if self
+            .state
+            .compare_exchange(UNINIT, RUNNING, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+        {
+            let guard = PoisonOnUnwind(&self.state);
+            let value = init();
+            self.value.with_mut(|v| unsafe { (*v).write(value) });
+            mem::forget(guard);
+            self.state.store(DONE, Ordering::Release);
+            wake_all(&self.state);
+        }
+    }
+}
+
+impl<T> Default for OnceCell<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Drop for OnceCell<T> {
+    fn drop(&mut self) {
+        if self.state.load(Ordering::Relaxed) == DONE {
+            self.value
+                .with_mut(|value| unsafe { (*value).assume_init_drop() });
+        }
+    }
+}
+
+/// A value that is created by `init` on first use.
+pub struct Lazy<T, F = fn() -> T> {
+    cell: OnceCell<T>,
+    init: UnsafeCell<Option<F>>,
+}
+
+// Only the thread that wins the race to initialise touches `init`
+unsafe impl<T: Send + Sync, F: Send> Sync for Lazy<T, F> {}
+
+impl<T, F: FnOnce() -> T> Lazy<T, F> {
+    #[cfg(not(loom))]
+    pub const fn new(init: F) -> Self {
+        Self {
+            cell: OnceCell::new(),
+            init: UnsafeCell::new(Some(init)),
+        }
+    }
+
+    #[cfg(loom)]
+    pub fn new(init: F) -> Self {
+        Self {
+            cell: OnceCell::new(),
+            init: UnsafeCell::new(Some(init)),
+        }
+    }
+
+    /// Returns the value, creating it if needed.
+    pub fn force(this: &Self) -> &T {
+        this.cell.get_or_init(|| {
+            let init = this.init.with_mut(|init| unsafe { (*init).take() });
+            init.expect("Lazy initialiser already taken")()
+        })
+    }
+}
+
+impl<T, F: FnOnce() -> T> Deref for Lazy<T, F> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        Lazy::force(self)
+    }
+}
+
+#[cfg(all(test, not(loom), not(pct)))]
+mod tests {
+    use super::*;
+    use std::panic::{self, AssertUnwindSafe};
+    use std::sync::atomic::AtomicUsize;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn initialises_once() {
+        let cell = OnceCell::new();
+        let calls = AtomicUsize::new(0);
+        thread::scope(|s| {
+            for i in 0..4 {
+                let (cell, calls) = (&cell, &calls);
+                s.spawn(move || {
+                    let value = cell.get_or_init(|| {
+                        calls.fetch_add(1, Ordering::Relaxed);
+                        // Keeps the others waiting
+                        thread::sleep(Duration::from_millis(10));
+                        i
+                    });
+                    assert_eq!(cell.get(), Some(value));
+                });
+            }
+        });
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn set_fails_once_initialised() {
+        let cell = OnceCell::new();
+        assert_eq!(cell.get(), None);
+        assert_eq!(cell.set(1), Ok(()));
+        assert_eq!(cell.set(2), Err(2));
+        assert_eq!(*cell.get_or_init(|| 3), 1);
+    }
+
+    #[test]
+    fn panicking_initialiser_poisons() {
+        let cell = OnceCell::<i32>::new();
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            cell.get_or_init(|| panic!("init failed"));
+        }));
+        assert!(result.is_err());
+        assert!(cell.is_poisoned());
+        assert_eq!(cell.get(), None);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| *cell.get_or_init(|| 1)));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn lazy_static_initialises_on_first_use() {
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+        static VALUE: Lazy<Vec<i32>> = Lazy::new(|| {
+            CALLS.fetch_add(1, Ordering::Relaxed);
+            vec![1, 2, 3]
+        });
+
+        assert_eq!(CALLS.load(Ordering::Relaxed), 0);
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| assert_eq!(VALUE.len(), 3));
+            }
+        });
+        assert_eq!(CALLS.load(Ordering::Relaxed), 1);
+    }
+}
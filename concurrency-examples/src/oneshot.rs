@@ -0,0 +1,172 @@
+//! A channel for a single value that doesn't allocate.
+//!
+//! The [`Channel`] lives wherever the caller puts it, usually on the stack, and [`Channel::split`]
+//! borrows it into a [`Sender`] and a [`Receiver`]. The receiver sleeps with `thread::park`, so it
+//! must stay on the thread that split the channel: the sender keeps that thread's handle to unpark
+//! it. The borrow keeps the channel alive until both halves are gone.
+
+use crate::channel::RecvError;
+use crate::sync::{
+    atomic::{AtomicU8, Ordering},
+    thread::{self, Thread},
+    UnsafeCell,
+};
+use std::marker::PhantomData;
+use std::mem::{ManuallyDrop, MaybeUninit};
+use std::ptr;
+
+const EMPTY: u8 = 0;
+/// The value has been sent and not yet received
+const READY: u8 = 1;
+/// The sender was dropped without sending
+const CLOSED: u8 = 2;
+
+pub struct Channel<T> {
+    value: UnsafeCell<MaybeUninit<T>>,
+    state: AtomicU8,
+}
+
+unsafe impl<T: Send> Sync for Channel<T> {}
+
+pub struct Sender<'a, T> {
+    channel: &'a Channel<T>,
+    receiving_thread: Thread,
+}
+
+pub struct Receiver<'a, T> {
+    channel: &'a Channel<T>,
+    // Must stay on the thread that `Sender` unparks
+    _not_send: PhantomData<*const ()>,
+}
+
+impl<T> Channel<T> {
+    pub fn new() -> Self {
+        Self {
+            value: UnsafeCell::new(MaybeUninit::uninit()),
+            state: AtomicU8::new(EMPTY),
+        }
+    }
+
+    /// Creates the two halves. The receiving half belongs to the calling thread.
+    pub fn split(&mut self) -> (Sender<'_, T>, Receiver<'_, T>) {
+        // Drops a value left over from an earlier use
+        *self = Self::new();
+        (
+            Sender {
+                channel: self,
+                receiving_thread: thread::current(),
+            },
+            Receiver {
+                channel: self,
+                _not_send: PhantomData,
+            },
+        )
+    }
+}
+
+impl<T> Default for Channel<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Drop for Channel<T> {
+    fn drop(&mut self) {
+        if self.state.load(Ordering::Relaxed) == READY {
+            self.value
+                .with_mut(|value| unsafe { (*value).assume_init_drop() });
+        }
+    }
+}
+
+impl<T> Sender<'_, T> {
+    pub fn send(self, value: T) {
+        // Skip `Drop`, which would mark the channel closed
+        let this = ManuallyDrop::new(self);
+        this.channel
+            .value
+            .with_mut(|v| unsafe { (*v).write(value) });
+        this.channel.state.store(READY, Ordering::Release);
+        unsafe { ptr::read(&this.receiving_thread) }.unpark();
+    }
+}
+
+impl<T> Drop for Sender<'_, T> {
+    fn drop(&mut self) {
+        self.channel.state.store(CLOSED, Ordering::Release);
+        self.receiving_thread.unpark();
+    }
+}
+
+impl<T> Receiver<'_, T> {
+    /// Returns `true` if `recv` would return without blocking.
+    pub fn is_ready(&self) -> bool {
+        self.channel.state.load(Ordering::Relaxed) != EMPTY
+    }
+
+    /// Waits for the value. Fails if the sender was dropped without sending.
+    pub fn recv(self) -> Result<T, RecvError> {
+        loop {
+            match self.channel.state.load(Ordering::Acquire) {
+                READY => {
+                    self.channel.state.store(EMPTY, Ordering::Relaxed);
+                    return Ok(self
+                        .channel
+                        .value
+                        .with(|value| unsafe { (*value).assume_init_read() }));
+                }
+                CLOSED => return Err(RecvError),
+                // Parking returns straight away if the sender unparked us in the meantime
+                _ => thread::park(),
+            }
+        }
+    }
+}
+
+#[cfg(all(test, not(loom), not(pct)))]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn sends_across_threads() {
+        let mut channel = Channel::new();
+        thread::scope(|s| {
+            let (tx, rx) = channel.split();
+            s.spawn(move || {
+                thread::sleep(Duration::from_millis(10));
+                tx.send(1);
+            });
+            assert_eq!(rx.recv(), Ok(1));
+        });
+    }
+
+    #[test]
+    fn dropped_sender_closes() {
+        let mut channel = Channel::<()>::new();
+        let (tx, rx) = channel.split();
+        thread::scope(|s| {
+            s.spawn(move || drop(tx));
+        });
+        assert!(rx.is_ready());
+        assert_eq!(rx.recv(), Err(RecvError));
+    }
+
+    #[test]
+    fn channel_is_reusable_and_drops_unreceived_value() {
+        let value = Arc::new(());
+        let mut channel = Channel::new();
+        {
+            let (tx, _rx) = channel.split();
+            tx.send(Arc::clone(&value));
+        }
+        {
+            let (tx, _rx) = channel.split();
+            assert_eq!(Arc::strong_count(&value), 1);
+            tx.send(Arc::clone(&value));
+        }
+        drop(channel);
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+}
@@ -0,0 +1,826 @@
+//! A controlled-thread runtime for randomised concurrency testing, in the style of `shuttle`.
+//!
+//! Loom explores every interleaving, which stops scaling beyond a handful of operations. Under
+//! `--cfg pct`, [`crate::sync`] re-exports this module's types instead of `std`'s. Threads are
+//! real, but only one runs at a time: every atomic access, lock, park, yield, spawn and join is a
+//! point where the scheduler picks which thread runs next. [`model::Builder::check`] runs a test
+//! body under many schedules, picked either uniformly at random or with PCT (probabilistic
+//! concurrency testing). PCT gives each thread a random priority, always runs the highest-priority
+//! thread that can run, and lowers the running thread's priority at `depth` random steps. A bug
+//! that needs `depth` such reorderings shows up with probability at least `1/(n·k^depth)` per
+//! schedule, for `n` threads and `k` steps. Yielding also lowers the priority, so spin loops make
+//! progress.
+//!
+//! Every schedule comes from a seed. A failing check panics with the schedule, and setting
+//! `PCT_SCHEDULE` to it replays that schedule alone. A replay is deterministic as long as the test
+//! body is, so it mustn't depend on time or `RandomState`. `PCT_ITERATIONS` sets the number of
+//! schedules and `PCT_STRATEGY=random` switches from PCT to random scheduling.
+//!
+//! Unlike loom, memory is sequentially consistent, so weak-memory bugs are out of reach. An
+//! [`cell::UnsafeCell`] access spans a scheduling point, and another thread writing to the cell
+//! meanwhile, or reading while it is written, fails the check. Outside a check, the types behave
+//! like `std`'s.
+//!
+//! Only code built on `crate::sync` runs under the scheduler. Larger examples that are, like
+//! `shared_mem_hash_map` with its ten threads and the striped map's locks, are checked here too.
+//! The `DashMap` and actor examples are out of reach: `DashMap` locks with its own `std` atomics,
+//! and actix runs its actors on tokio's threads, so the scheduler never sees their steps.
+
+use crate::stress::Rng;
+use std::cell::RefCell;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex as StdMutex, MutexGuard as StdMutexGuard};
+use std::time::Duration;
+
+type ThreadId = usize;
+
+const DEFAULT_ITERATIONS: usize = 1_000;
+
+const DEFAULT_MAX_STEPS: usize = 100_000;
+
+// Initial priorities are above this, and lowered ones below it
+const LOWERED: u64 = 1 << 62;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Status {
+    Runnable,
+    Joining(ThreadId),
+    JoiningAll,
+    Parked,
+    Finished,
+}
+
+struct ThreadState {
+    status: Status,
+    /// Whether an `unpark` is waiting for the next `park`
+    unparked: bool,
+    priority: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Strategy {
+    /// Picks a thread uniformly at random at every step.
+    Random,
+    /// Changes priorities at `depth` steps picked out of the first `steps`.
+    Pct { depth: usize, steps: usize },
+}
+
+/// A strategy and a seed, which together fix a schedule.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Schedule {
+    pub strategy: Strategy,
+    pub seed: u64,
+}
+
+struct State {
+    threads: Vec<ThreadState>,
+    current: ThreadId,
+    rng: Rng,
+    strategy: Strategy,
+    /// Steps at which PCT lowers the running thread's priority
+    change_points: Vec<usize>,
+    /// The priority the next lowered thread gets, below every other
+    lowest: u64,
+    steps: usize,
+    max_steps: usize,
+    failure: Option<String>,
+    /// OS threads spawned in the execution that are still running
+    live: usize,
+}
+
+struct Execution {
+    state: StdMutex<State>,
+    changed: Condvar,
+}
+
+/// The panic that unwinds the threads of a failed execution.
+struct Aborted;
+
+thread_local! {
+    static CONTEXT: RefCell<Option<(Arc<Execution>, ThreadId)>> = const { RefCell::new(None) };
+}
+
+fn context() -> Option<(Arc<Execution>, ThreadId)> {
+    CONTEXT.with(|c| c.borrow().clone())
+}
+
+fn set_context(context: Option<(Arc<Execution>, ThreadId)>) {
+    CONTEXT.with(|c| *c.borrow_mut() = context);
+}
+
+/// A point where another thread may run. Outside a check, does nothing.
+fn step() {
+    if let Some((execution, me)) = context() {
+        execution.switch(me, |_| {});
+    }
+}
+
+/// Unwinds a thread of a failed execution, unless it is already unwinding.
+fn abort() {
+    if !std::thread::panicking() {
+        panic::resume_unwind(Box::new(Aborted));
+    }
+}
+
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> Option<String> {
+    if payload.is::<Aborted>() {
+        None
+    } else if let Some(s) = payload.downcast_ref::<&str>() {
+        Some(s.to_string())
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        Some(s.clone())
+    } else {
+        Some("a panic".to_string())
+    }
+}
+
+impl State {
+    fn new(schedule: Schedule, max_steps: usize) -> Self {
+        let mut rng = Rng::new(schedule.seed, 0);
+        let change_points = match schedule.strategy {
+            Strategy::Random => Vec::new(),
+            Strategy::Pct { depth, steps } => (0..depth)
+                .map(|_| 1 + (rng.next_u64() % steps as u64) as usize)
+                .collect(),
+        };
+        Self {
+            threads: Vec::new(),
+            current: 0,
+            rng,
+            strategy: schedule.strategy,
+            change_points,
+            lowest: LOWERED,
+            steps: 0,
+            max_steps,
+            failure: None,
+            live: 0,
+        }
+    }
+
+    fn register(&mut self) -> ThreadId {
+        let priority = LOWERED + 1 + self.rng.next_u64() % LOWERED;
+        self.threads.push(ThreadState {
+            status: Status::Runnable,
+            unparked: false,
+            priority,
+        });
+        self.threads.len() - 1
+    }
+
+    fn lower(&mut self, id: ThreadId) {
+        self.threads[id].priority = self.lowest;
+        self.lowest -= 1;
+    }
+
+    fn pick(&mut self) -> Option<ThreadId> {
+        let runnable: Vec<_> = (0..self.threads.len())
+            .filter(|&id| self.threads[id].status == Status::Runnable)
+            .collect();
+        match self.strategy {
+            _ if runnable.is_empty() => None,
+            Strategy::Random => {
+                Some(runnable[(self.rng.next_u64() % runnable.len() as u64) as usize])
+            }
+            Strategy::Pct { .. } => runnable
+                .into_iter()
+                .max_by_key(|&id| self.threads[id].priority),
+        }
+    }
+
+    /// Makes `next` the running thread, or fails the execution if nothing can run.
+    fn run_next(&mut self) {
+        match self.pick() {
+            Some(next) => self.current = next,
+            None if self.threads.iter().all(|t| t.status == Status::Finished) => {}
+            None => {
+                let blocked: Vec<_> = self
+                    .threads
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, t)| t.status != Status::Finished)
+                    .map(|(id, t)| format!("thread {id} {:?}", t.status))
+                    .collect();
+                self.fail(format!("deadlock: {}", blocked.join(", ")));
+            }
+        }
+    }
+
+    fn fail(&mut self, message: String) {
+        self.failure.get_or_insert(message);
+    }
+}
+
+impl Execution {
+    fn lock(&self) -> StdMutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Applies `update` to the state, lets the scheduler pick the next thread to run, and waits
+    /// until it is `me` again.
+    fn switch(&self, me: ThreadId, update: impl FnOnce(&mut State)) {
+        let mut state = self.lock();
+        if state.failure.is_some() {
+            drop(state);
+            return abort();
+        }
+        update(&mut state);
+        state.steps += 1;
+        if state.change_points.contains(&state.steps) {
+            state.lower(me);
+        }
+        if state.steps > state.max_steps {
+            let message = format!(
+                "no progress after {} steps: threads are spinning forever, or deadlocked on locks",
+                state.max_steps
+            );
+            state.fail(message);
+        } else {
+            state.run_next();
+        }
+        self.changed.notify_all();
+        self.wait_for_turn(state, me);
+    }
+
+    fn wait_for_turn(&self, mut state: StdMutexGuard<'_, State>, me: ThreadId) {
+        while state.current != me && state.failure.is_none() {
+            state = self.changed.wait(state).unwrap_or_else(|e| e.into_inner());
+        }
+        if state.failure.is_some() {
+            drop(state);
+            abort();
+        }
+    }
+
+    fn finish(&self, me: ThreadId, panic: Option<String>) {
+        let mut state = self.lock();
+        state.threads[me].status = Status::Finished;
+        state.live -= 1;
+        if let Some(message) = panic {
+            state.fail(format!("thread {me} panicked: {message}"));
+        }
+        let all_finished = |state: &State, except| {
+            (0..state.threads.len())
+                .all(|id| id == except || state.threads[id].status == Status::Finished)
+        };
+        for id in 0..state.threads.len() {
+            let wakes = match state.threads[id].status {
+                Status::Joining(target) => target == me,
+                Status::JoiningAll => all_finished(&state, id),
+                _ => false,
+            };
+            if wakes {
+                state.threads[id].status = Status::Runnable;
+            }
+        }
+        if state.failure.is_none() && state.current == me {
+            state.run_next();
+        }
+        self.changed.notify_all();
+    }
+
+    /// Runs `f` as thread 0 and waits for every thread it spawns.
+    fn run(self: &Arc<Self>, f: &dyn Fn()) -> Result<usize, String> {
+        let main = self.lock().register();
+        set_context(Some((Arc::clone(self), main)));
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            f();
+            self.switch(main, |state| {
+                if state.threads[1..]
+                    .iter()
+                    .any(|t| t.status != Status::Finished)
+                {
+                    state.threads[main].status = Status::JoiningAll;
+                }
+            });
+        }));
+        set_context(None);
+
+        let mut state = self.lock();
+        if let Some(message) = result.err().and_then(|p| panic_message(&*p)) {
+            state.fail(format!("thread {main} panicked: {message}"));
+        }
+        if state.failure.is_some() {
+            self.changed.notify_all();
+            // Lets the other threads unwind, unless they are stuck outside the runtime's control
+            let (s, _) = self
+                .changed
+                .wait_timeout_while(state, Duration::from_secs(1), |s| s.live > 0)
+                .unwrap_or_else(|e| e.into_inner());
+            state = s;
+        }
+        match state.failure.take() {
+            Some(failure) => Err(failure),
+            None => Ok(state.steps),
+        }
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.strategy {
+            Strategy::Random => write!(f, "random:{}", self.seed),
+            Strategy::Pct { depth, steps } => write!(f, "pct:{depth}:{steps}:{}", self.seed),
+        }
+    }
+}
+
+impl std::str::FromStr for Schedule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let parts: Vec<_> = s.split(':').collect();
+        let number = |part: &str| part.parse().map_err(|_| format!("bad schedule `{s}`"));
+        let (strategy, seed) = match parts[..] {
+            ["random", seed] => (Strategy::Random, seed),
+            ["pct", depth, steps, seed] => (
+                Strategy::Pct {
+                    depth: number(depth)?,
+                    steps: number(steps)?,
+                },
+                seed,
+            ),
+            _ => return Err(format!("bad schedule `{s}`")),
+        };
+        let seed = seed.parse().map_err(|_| format!("bad schedule `{s}`"))?;
+        Ok(Self { strategy, seed })
+    }
+}
+
+/// Runs `f` under the default number of schedules.
+pub fn model<F: Fn() + Sync + Send + 'static>(f: F) {
+    model::Builder::new().check(f)
+}
+
+pub mod model {
+    use super::{Execution, Schedule, State, Strategy};
+    use crate::stress::Rng;
+    use std::collections::hash_map::RandomState;
+    use std::hash::BuildHasher;
+    use std::sync::{Arc, Condvar, Mutex};
+
+    /// Configures a check, with the same interface as `loom::model::Builder` where they overlap.
+    pub struct Builder {
+        /// The number of priority changes PCT makes in each schedule, two if unset. Loom bounds
+        /// preemptions the same way.
+        pub preemption_bound: Option<usize>,
+        /// Schedules to run, from `PCT_ITERATIONS` by default.
+        pub iterations: usize,
+        /// Picks threads uniformly at random instead of with PCT.
+        pub random: bool,
+        /// Steps after which an execution fails, since a thread is probably spinning forever.
+        pub max_steps: usize,
+    }
+
+    impl Builder {
+        pub fn new() -> Self {
+            Self {
+                preemption_bound: None,
+                iterations: std::env::var("PCT_ITERATIONS")
+                    .ok()
+                    .and_then(|n| n.parse().ok())
+                    .unwrap_or(super::DEFAULT_ITERATIONS),
+                random: std::env::var("PCT_STRATEGY").is_ok_and(|s| s == "random"),
+                max_steps: super::DEFAULT_MAX_STEPS,
+            }
+        }
+
+        /// Runs `f` under `iterations` schedules, or just the one in `PCT_SCHEDULE`. Panics with
+        /// the schedule if `f` panics, a thread it spawns panics, or its threads deadlock.
+        pub fn check<F: Fn() + Sync + Send + 'static>(&self, f: F) {
+            if let Ok(schedule) = std::env::var("PCT_SCHEDULE") {
+                let schedule = schedule.parse().unwrap();
+                if let Err(failure) = self.run(schedule, &f) {
+                    panic!("{failure}");
+                }
+                return;
+            }
+
+            let base = RandomState::new().hash_one(std::time::SystemTime::now());
+            let mut seeds = Rng::new(base, 0);
+            // PCT picks change points among the steps of a run, so it learns how long runs are
+            let mut steps = 1;
+            for _ in 0..self.iterations {
+                let strategy = if self.random {
+                    Strategy::Random
+                } else {
+                    Strategy::Pct {
+                        depth: self.preemption_bound.unwrap_or(2),
+                        steps,
+                    }
+                };
+                let schedule = Schedule {
+                    strategy,
+                    seed: seeds.next_u64(),
+                };
+                match self.run(schedule, &f) {
+                    Ok(taken) => steps = steps.max(taken),
+                    Err(failure) => panic!("{failure}\nreplay with PCT_SCHEDULE={schedule}"),
+                }
+            }
+        }
+
+        pub(super) fn run(&self, schedule: Schedule, f: &dyn Fn()) -> Result<usize, String> {
+            let execution = Arc::new(Execution {
+                state: Mutex::new(State::new(schedule, self.max_steps)),
+                changed: Condvar::new(),
+            });
+            execution.run(f)
+        }
+    }
+
+    impl Default for Builder {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+}
+
+pub mod atomic {
+    use super::step;
+    pub use std::sync::atomic::Ordering;
+
+    pub fn fence(order: Ordering) {
+        step();
+        std::sync::atomic::fence(order);
+    }
+
+    macro_rules! atomic {
+        ($name:ident $(<$param:ident>)?, $t:ty $(, $int:ident)?) => {
+            /// `std`'s atomic, with a scheduling point before every access.
+            #[derive(Debug, Default)]
+            pub struct $name<$($param)?>(std::sync::atomic::$name<$($param)?>);
+
+            impl<$($param)?> $name<$($param)?> {
+                pub const fn new(value: $t) -> Self {
+                    Self(std::sync::atomic::$name::new(value))
+                }
+
+                pub fn load(&self, order: Ordering) -> $t {
+                    step();
+                    self.0.load(order)
+                }
+
+                pub fn store(&self, value: $t, order: Ordering) {
+                    step();
+                    self.0.store(value, order)
+                }
+
+                pub fn swap(&self, value: $t, order: Ordering) -> $t {
+                    step();
+                    self.0.swap(value, order)
+                }
+
+                pub fn compare_exchange(
+                    &self,
+                    current: $t,
+                    new: $t,
+                    success: Ordering,
+                    failure: Ordering,
+                ) -> Result<$t, $t> {
+                    step();
+                    self.0.compare_exchange(current, new, success, failure)
+                }
+
+                pub fn compare_exchange_weak(
+                    &self,
+                    current: $t,
+                    new: $t,
+                    success: Ordering,
+                    failure: Ordering,
+                ) -> Result<$t, $t> {
+                    step();
+                    self.0.compare_exchange_weak(current, new, success, failure)
+                }
+
+                pub fn get_mut(&mut self) -> &mut $t {
+                    self.0.get_mut()
+                }
+
+                pub fn into_inner(self) -> $t {
+                    self.0.into_inner()
+                }
+            }
+
+            $(atomic!(@$int $name, $t);)?
+        };
+        (@int $name:ident, $t:ty) => {
+            impl $name {
+                pub fn fetch_add(&self, value: $t, order: Ordering) -> $t {
+                    step();
+                    self.0.fetch_add(value, order)
+                }
+
+                pub fn fetch_sub(&self, value: $t, order: Ordering) -> $t {
+                    step();
+                    self.0.fetch_sub(value, order)
+                }
+
+                pub fn fetch_max(&self, value: $t, order: Ordering) -> $t {
+                    step();
+                    self.0.fetch_max(value, order)
+                }
+
+                pub fn fetch_update(
+                    &self,
+                    set_order: Ordering,
+                    fetch_order: Ordering,
+                    f: impl FnMut($t) -> Option<$t>,
+                ) -> Result<$t, $t> {
+                    step();
+                    self.0.fetch_update(set_order, fetch_order, f)
+                }
+            }
+        };
+    }
+
+    atomic!(AtomicBool, bool);
+    atomic!(AtomicU8, u8, int);
+    atomic!(AtomicU32, u32, int);
+    atomic!(AtomicUsize, usize, int);
+    atomic!(AtomicPtr<T>, *mut T);
+}
+
+pub mod cell {
+    use super::step;
+    use std::sync::atomic::{AtomicUsize, Ordering::SeqCst};
+
+    // `access` while the cell is being written
+    const WRITING: usize = usize::MAX;
+
+    /// An `UnsafeCell` with loom's interface, which panics on a write that overlaps another access.
+    #[derive(Debug, Default)]
+    pub struct UnsafeCell<T> {
+        data: std::cell::UnsafeCell<T>,
+        /// The number of reads in progress, or `WRITING`
+        access: AtomicUsize,
+    }
+
+    unsafe impl<T: Send> Send for UnsafeCell<T> {}
+    unsafe impl<T: Send + Sync> Sync for UnsafeCell<T> {}
+
+    impl<T> UnsafeCell<T> {
+        pub const fn new(value: T) -> Self {
+            Self {
+                data: std::cell::UnsafeCell::new(value),
+                access: AtomicUsize::new(0),
+            }
+        }
+
+        pub fn with<R>(&self, f: impl FnOnce(*const T) -> R) -> R {
+            let entered = self
+                .access
+                .fetch_update(SeqCst, SeqCst, |n| (n != WRITING).then_some(n + 1));
+            assert!(entered.is_ok(), "read of an UnsafeCell while it is written");
+            step();
+            let result = f(self.data.get());
+            self.access.fetch_sub(1, SeqCst);
+            result
+        }
+
+        pub fn with_mut<R>(&self, f: impl FnOnce(*mut T) -> R) -> R {
+            let entered = self.access.compare_exchange(0, WRITING, SeqCst, SeqCst);
+            assert!(
+                entered.is_ok(),
+                "write to an UnsafeCell during another access"
+            );
+            step();
+            let result = f(self.data.get());
+            self.access.store(0, SeqCst);
+            result
+        }
+
+        pub fn into_inner(self) -> T {
+            self.data.into_inner()
+        }
+    }
+}
+
+pub mod hint {
+    pub fn spin_loop() {
+        super::thread::yield_now();
+    }
+}
+
+pub mod thread {
+    use super::{context, panic_message, set_context, Status, ThreadId};
+    use std::panic::{self, AssertUnwindSafe};
+    use std::sync::Arc;
+
+    pub struct JoinHandle<T> {
+        inner: std::thread::JoinHandle<T>,
+        id: Option<ThreadId>,
+    }
+
+    #[derive(Clone, Debug)]
+    pub struct Thread {
+        inner: std::thread::Thread,
+        id: Option<ThreadId>,
+    }
+
+    pub fn spawn<F, T>(f: F) -> JoinHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let Some((execution, me)) = context() else {
+            return JoinHandle {
+                inner: std::thread::spawn(f),
+                id: None,
+            };
+        };
+        let id = {
+            let mut state = execution.lock();
+            state.live += 1;
+            state.register()
+        };
+        let inner = {
+            let execution = Arc::clone(&execution);
+            std::thread::spawn(move || {
+                set_context(Some((Arc::clone(&execution), id)));
+                let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                    execution.wait_for_turn(execution.lock(), id);
+                    f()
+                }));
+                execution.finish(id, result.as_ref().err().and_then(|p| panic_message(&**p)));
+                set_context(None);
+                result.unwrap_or_else(|p| panic::resume_unwind(p))
+            })
+        };
+        execution.switch(me, |_| {});
+        JoinHandle {
+            inner,
+            id: Some(id),
+        }
+    }
+
+    impl<T> JoinHandle<T> {
+        pub fn join(self) -> std::thread::Result<T> {
+            if let (Some(target), Some((execution, me))) = (self.id, context()) {
+                execution.switch(me, |state| {
+                    if state.threads[target].status != Status::Finished {
+                        state.threads[me].status = Status::Joining(target);
+                    }
+                });
+            }
+            self.inner.join()
+        }
+    }
+
+    pub fn current() -> Thread {
+        Thread {
+            inner: std::thread::current(),
+            id: context().map(|(_, me)| me),
+        }
+    }
+
+    pub fn park() {
+        match context() {
+            Some((execution, me)) => execution.switch(me, |state| {
+                if !std::mem::take(&mut state.threads[me].unparked) {
+                    state.threads[me].status = Status::Parked;
+                }
+            }),
+            None => std::thread::park(),
+        }
+    }
+
+    impl Thread {
+        pub fn unpark(&self) {
+            match (self.id, context()) {
+                (Some(target), Some((execution, me))) => execution.switch(me, |state| {
+                    let target = &mut state.threads[target];
+                    if target.status == Status::Parked {
+                        target.status = Status::Runnable;
+                    } else {
+                        target.unparked = true;
+                    }
+                }),
+                _ => self.inner.unpark(),
+            }
+        }
+    }
+
+    /// Lets other threads run. Under PCT the thread also drops to the lowest priority, so that a
+    /// spin loop can't keep the thread it waits for from running.
+    pub fn yield_now() {
+        match context() {
+            Some((execution, me)) => execution.switch(me, |state| state.lower(me)),
+            None => std::thread::yield_now(),
+        }
+    }
+}
+
+/// `std`'s mutex, with a scheduling point before every attempt to lock it. A thread that finds it
+/// locked yields.
+#[derive(Debug, Default)]
+pub struct Mutex<T>(StdMutex<T>);
+
+impl<T> Mutex<T> {
+    pub const fn new(value: T) -> Self {
+        Self(StdMutex::new(value))
+    }
+
+    pub fn lock(&self) -> std::sync::LockResult<StdMutexGuard<'_, T>> {
+        loop {
+            step();
+            match self.0.try_lock() {
+                Ok(guard) => return Ok(guard),
+                Err(std::sync::TryLockError::Poisoned(e)) => return Err(e),
+                Err(std::sync::TryLockError::WouldBlock) => thread::yield_now(),
+            }
+        }
+    }
+
+    pub fn get_mut(&mut self) -> std::sync::LockResult<&mut T> {
+        self.0.get_mut()
+    }
+}
+
+#[cfg(all(test, pct))]
+mod tests {
+    use super::*;
+    use crate::sync::atomic::{AtomicUsize, Ordering};
+
+    fn lost_update() {
+        let count = std::sync::Arc::new(AtomicUsize::new(0));
+        let th = {
+            let count = count.clone();
+            thread::spawn(move || {
+                let c = count.load(Ordering::SeqCst);
+                count.store(c + 1, Ordering::SeqCst);
+            })
+        };
+        let c = count.load(Ordering::SeqCst);
+        count.store(c + 1, Ordering::SeqCst);
+        th.join().unwrap();
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn finds_lost_update() {
+        for random in [false, true] {
+            let failure = panic::catch_unwind(|| {
+                let mut builder = model::Builder::new();
+                builder.random = random;
+                builder.check(lost_update);
+            })
+            .unwrap_err();
+            let message = panic_message(&*failure).unwrap();
+            let schedule: Schedule = message.rsplit('=').next().unwrap().parse().unwrap();
+
+            // The printed schedule fails again, every time
+            let builder = model::Builder::new();
+            for _ in 0..10 {
+                assert!(builder.run(schedule, &lost_update).is_err());
+            }
+        }
+    }
+
+    #[test]
+    fn finds_deadlock() {
+        let failure = panic::catch_unwind(|| {
+            model(|| {
+                let a = std::sync::Arc::new((Mutex::new(()), Mutex::new(())));
+                let th = {
+                    let a = a.clone();
+                    thread::spawn(move || {
+                        let _x = a.1.lock().unwrap();
+                        let _y = a.0.lock().unwrap();
+                    })
+                };
+                let _x = a.0.lock().unwrap();
+                let _y = a.1.lock().unwrap();
+                drop((_x, _y));
+                th.join().unwrap();
+            })
+        })
+        .unwrap_err();
+        // Threads waiting for a lock spin, so a deadlock looks like a thread making no progress
+        assert!(panic_message(&*failure).unwrap().contains("no progress"));
+    }
+
+    #[test]
+    fn shared_mem_hash_map_counts_every_increment() {
+        model(|| assert_eq!(crate::shared_mem_hash_map(), 10));
+    }
+
+    #[test]
+    fn park_and_join_block() {
+        model(|| {
+            let main = thread::current();
+            let done = std::sync::Arc::new(AtomicUsize::new(0));
+            let th = {
+                let done = done.clone();
+                thread::spawn(move || {
+                    done.store(1, Ordering::SeqCst);
+                    main.unpark();
+                })
+            };
+            while done.load(Ordering::SeqCst) == 0 {
+                thread::park();
+            }
+            th.join().unwrap();
+        });
+    }
+}
@@ -0,0 +1,244 @@
+//! Read-copy-update: readers never block, and writers defer freeing what readers may still see.
+//!
+//! A writer publishes a new version of some data with an atomic store. It then either calls
+//! [`RcuDomain::synchronize`], which waits for a grace period: until every read-side critical
+//! section that started before the call has ended. Or it passes the cleanup to
+//! [`RcuDomain::call_rcu`], which runs it after such a grace period without blocking the writer.
+//!
+//! The domain numbers grace periods. A reader claims a slot and records in it the grace period it
+//! started in, and a grace period has elapsed once no slot holds an active reader from an earlier
+//! one. Readers that start later already see the new version. The number of slots bounds the number
+//! of concurrent readers; more wait for a slot to free up.
+//!
+//! [`map`] is a lookup table built on it.
+
+pub mod map;
+
+use crate::reclaim::{claim, Reclaim, Retired};
+use crate::sync::{
+    atomic::{fence, AtomicBool, AtomicPtr, AtomicUsize, Ordering},
+    thread, Mutex,
+};
+use std::collections::VecDeque;
+
+// Number of concurrent readers
+const SLOTS: usize = if cfg!(any(loom, pct)) { 4 } else { 64 };
+
+// Number of queued callbacks that triggers running those whose grace period has elapsed
+const CALLBACK_THRESHOLD: usize = if cfg!(any(loom, pct)) { 1 } else { 64 };
+
+// A slot holds the grace period its reader started in shifted left by one, and the lowest bit is
+// set while the reader is active
+const ACTIVE: usize = 1;
+
+type Callback = Box<dyn FnOnce() + Send>;
+
+struct ReaderSlot {
+    in_use: AtomicBool,
+    state: AtomicUsize,
+}
+
+pub struct RcuDomain {
+    grace_period: AtomicUsize,
+    readers: Box<[ReaderSlot]>,
+    /// Callbacks with the grace period they wait for, oldest first
+    callbacks: Mutex<VecDeque<(usize, Callback)>>,
+}
+
+/// A read-side critical section. Data loaded while it is alive stays valid until it is dropped.
+pub struct RcuReadGuard<'a> {
+    slot: &'a ReaderSlot,
+}
+
+impl RcuDomain {
+    pub fn new() -> Self {
+        Self {
+            grace_period: AtomicUsize::new(0),
+            readers: (0..SLOTS)
+                .map(|_| ReaderSlot {
+                    in_use: AtomicBool::new(false),
+                    state: AtomicUsize::new(0),
+                })
+                .collect(),
+            callbacks: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    pub fn read_lock(&self) -> RcuReadGuard<'_> {
+        let slot = claim(&self.readers, |s| &s.in_use);
+        // Acquire so that a reader that starts in a grace period sees what was unlinked before it
+        // began
+        let grace_period = self.grace_period.load(Ordering::Acquire);
+        // Release so that what earlier readers of the slot did happens before a writer sees this
+        slot.state
+            .store(grace_period << 1 | ACTIVE, Ordering::Release);
+        // Pairs with the fence in `oldest_reader`: either the writer sees this reader, or this
+        // reader sees what the writer published before its grace period
+        fence(Ordering::SeqCst);
+        RcuReadGuard { slot }
+    }
+
+    /// Waits until every read-side critical section that started before the call has ended, then
+    /// runs the callbacks that were waiting for it. Deadlocks if called inside a read-side critical
+    /// section.
+    pub fn synchronize(&self) {
+        let target = self.start_grace_period();
+        while self.oldest_reader() < target {
+            thread::yield_now();
+        }
+        self.run_callbacks(target);
+    }
+
+    /// Runs `f` after every read-side critical section that started before the call has ended.
+    /// Doesn't wait for that: callbacks run in a later `call_rcu` or `synchronize`, or when the
+    /// domain is dropped.
+    pub fn call_rcu(&self, f: impl FnOnce() + Send + 'static) {
+        let mut callbacks = self.callbacks.lock().unwrap();
+        // Queued under the lock so that the grace periods are in order
+        callbacks.push_back((self.start_grace_period(), Box::new(f)));
+        if callbacks.len() < CALLBACK_THRESHOLD {
+            return;
+        }
+        drop(callbacks);
+        self.run_callbacks(self.oldest_reader());
+    }
+
+    /// Starts a grace period and returns its number. Readers from earlier grace periods may still
+    /// see what was unlinked before the call.
+    fn start_grace_period(&self) -> usize {
+        self.grace_period.fetch_add(1, Ordering::Release) + 1
+    }
+
+    /// Returns the grace period of the oldest active reader. Every grace period up to that one has
+    /// elapsed.
+    fn oldest_reader(&self) -> usize {
+        fence(Ordering::SeqCst);
+        self.readers
+            .iter()
+            .map(|slot| slot.state.load(Ordering::Acquire))
+            .filter(|state| state & ACTIVE == ACTIVE)
+            .map(|state| state >> 1)
+            .min()
+            .unwrap_or(usize::MAX)
+    }
+
+    /// Runs the callbacks waiting for grace periods up to `elapsed`.
+    fn run_callbacks(&self, elapsed: usize) {
+        let mut callbacks = self.callbacks.lock().unwrap();
+        let ready = callbacks.partition_point(|&(grace_period, _)| grace_period <= elapsed);
+        let ready: Vec<_> = callbacks.drain(..ready).collect();
+        // Callbacks may call `call_rcu` themselves
+        drop(callbacks);
+        for (_, f) in ready {
+            f();
+        }
+    }
+}
+
+impl Default for RcuDomain {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for RcuDomain {
+    fn drop(&mut self) {
+        for (_, f) in self.callbacks.get_mut().unwrap().drain(..) {
+            f();
+        }
+    }
+}
+
+impl Drop for RcuReadGuard<'_> {
+    fn drop(&mut self) {
+        self.slot.state.store(0, Ordering::Release);
+        self.slot.in_use.store(false, Ordering::Release);
+    }
+}
+
+/// Retired pointers are freed with `call_rcu`, so RCU can reclaim memory for the other lock-free
+/// structures too.
+impl Reclaim for RcuDomain {
+    type Guard<'a> = RcuReadGuard<'a>;
+
+    fn enter(&self) -> RcuReadGuard<'_> {
+        self.read_lock()
+    }
+
+    fn protect<T>(&self, _guard: &RcuReadGuard<'_>, src: &AtomicPtr<T>) -> *mut T {
+        src.load(Ordering::Acquire)
+    }
+
+    unsafe fn retire(&self, _guard: &RcuReadGuard<'_>, ptr: *mut (), free: unsafe fn(*mut ())) {
+        let retired = Retired { ptr, free };
+        self.call_rcu(move || unsafe { retired.free() });
+    }
+}
+
+#[cfg(all(test, not(loom), not(pct)))]
+mod tests {
+    use super::*;
+    use crate::treiber::TreiberStack;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn synchronize_waits_for_readers() {
+        let rcu = RcuDomain::new();
+        let reading = AtomicBool::new(true);
+        thread::scope(|s| {
+            let guard = rcu.read_lock();
+            s.spawn(|| {
+                rcu.synchronize();
+                assert!(!reading.load(Ordering::Relaxed));
+            });
+            thread::sleep(Duration::from_millis(10));
+            reading.store(false, Ordering::Relaxed);
+            drop(guard);
+        });
+        // Nothing to wait for
+        rcu.synchronize();
+    }
+
+    #[test]
+    fn call_rcu_runs_after_grace_period() {
+        let rcu = RcuDomain::new();
+        let ran = Arc::new(AtomicUsize::new(0));
+        let guard = rcu.read_lock();
+        for _ in 0..CALLBACK_THRESHOLD {
+            let ran = Arc::clone(&ran);
+            rcu.call_rcu(move || {
+                ran.fetch_add(1, Ordering::Relaxed);
+            });
+        }
+        // The reader holds them back
+        assert_eq!(ran.load(Ordering::Relaxed), 0);
+        drop(guard);
+        rcu.synchronize();
+        assert_eq!(ran.load(Ordering::Relaxed), CALLBACK_THRESHOLD);
+
+        let ran_on_drop = Arc::clone(&ran);
+        rcu.call_rcu(move || {
+            ran_on_drop.fetch_add(1, Ordering::Relaxed);
+        });
+        drop(rcu);
+        assert_eq!(ran.load(Ordering::Relaxed), CALLBACK_THRESHOLD + 1);
+    }
+
+    #[test]
+    fn reclaims_for_treiber_stack() {
+        let stack = TreiberStack::<usize, RcuDomain>::new();
+        thread::scope(|s| {
+            for t in 0..4 {
+                let stack = &stack;
+                s.spawn(move || {
+                    for i in 0..10_000 {
+                        stack.push(t * 10_000 + i);
+                        stack.pop().unwrap();
+                    }
+                });
+            }
+        });
+        assert!(stack.is_empty());
+    }
+}
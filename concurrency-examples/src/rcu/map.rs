@@ -0,0 +1,244 @@
+//! A hash map for read-mostly data, with lookups that never block.
+//!
+//! Each bucket points to an immutable vector of entries. A lookup loads the pointer inside an RCU
+//! read-side critical section. A write copies the bucket, changes the copy and swaps the pointer,
+//! then leaves the old vector to [`RcuDomain::call_rcu`]. Writers to the same bucket take turns
+//! with a per-bucket [`Mutex`]. The number of buckets is fixed, so the map suits tables whose size
+//! is roughly known up front.
+
+use super::RcuDomain;
+use crate::mutex::Mutex;
+use crate::reclaim::Retired;
+use crate::sync::atomic::{AtomicPtr, Ordering};
+use std::borrow::Borrow;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+
+const DEFAULT_BUCKETS: usize = 64;
+
+type Entries<K, V> = Vec<(K, V)>;
+
+unsafe fn free_entries<K, V>(entries: *mut ()) {
+    drop(Box::from_raw(entries as *mut Entries<K, V>));
+}
+
+struct Bucket<K, V> {
+    entries: AtomicPtr<Entries<K, V>>,
+    write: Mutex<()>,
+}
+
+pub struct RcuMap<K, V, S = RandomState> {
+    buckets: Box<[Bucket<K, V>]>,
+    hasher: S,
+    rcu: RcuDomain,
+}
+
+unsafe impl<K: Send + Sync, V: Send + Sync, S: Send> Send for RcuMap<K, V, S> {}
+unsafe impl<K: Send + Sync, V: Send + Sync, S: Sync> Sync for RcuMap<K, V, S> {}
+
+impl<K: Hash + Eq + Clone, V: Clone> RcuMap<K, V> {
+    pub fn new() -> Self {
+        Self::with_buckets(DEFAULT_BUCKETS)
+    }
+
+    pub fn with_buckets(buckets: usize) -> Self {
+        Self::with_buckets_and_hasher(buckets, RandomState::new())
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone, S: BuildHasher> RcuMap<K, V, S> {
+    pub fn with_buckets_and_hasher(buckets: usize, hasher: S) -> Self {
+        assert!(buckets > 0, "a map needs at least one bucket");
+        Self {
+            buckets: (0..buckets)
+                .map(|_| Bucket {
+                    entries: AtomicPtr::new(Box::into_raw(Box::default())),
+                    write: Mutex::new(()),
+                })
+                .collect(),
+            hasher,
+            rcu: RcuDomain::new(),
+        }
+    }
+
+    fn bucket<Q: Hash + ?Sized>(&self, key: &Q) -> &Bucket<K, V> {
+        &self.buckets[self.hasher.hash_one(key) as usize % self.buckets.len()]
+    }
+
+    /// Calls `f` on the value for `key`, if there is one.
+    pub fn read<Q, R>(&self, key: &Q, f: impl FnOnce(&V) -> R) -> Option<R>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let bucket = self.bucket(key);
+        let _guard = self.rcu.read_lock();
+        let entries = unsafe { &*bucket.entries.load(Ordering::Acquire) };
+        entries
+            .iter()
+            .find(|(k, _)| k.borrow() == key)
+            .map(|(_, v)| f(v))
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.read(key, V::clone)
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.read(key, |_| ()).is_some()
+    }
+
+    /// Inserts a value, returning the one it replaces.
+    pub fn insert(&self, key: K, value: V) -> Option<V> {
+        let bucket = self.bucket(&key);
+        self.update(bucket, |entries| {
+            match entries.iter_mut().find(|(k, _)| *k == key) {
+                Some((_, v)) => Some(std::mem::replace(v, value)),
+                None => {
+                    entries.push((key, value));
+                    None
+                }
+            }
+        })
+    }
+
+    pub fn remove<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let bucket = self.bucket(key);
+        self.update(bucket, |entries| {
+            let i = entries.iter().position(|(k, _)| k.borrow() == key)?;
+            Some(entries.swap_remove(i).1)
+        })
+    }
+
+    /// Replaces the bucket's entries with a copy changed by `f`.
+    fn update<R>(&self, bucket: &Bucket<K, V>, f: impl FnOnce(&mut Entries<K, V>) -> R) -> R {
+        let _write = bucket.write.lock();
+        // The mutex orders this after the previous write
+        let old = bucket.entries.load(Ordering::Relaxed);
+        let mut entries = unsafe { (*old).clone() };
+        let result = f(&mut entries);
+        bucket
+            .entries
+            .store(Box::into_raw(Box::new(entries)), Ordering::Release);
+
+        let retired = Retired {
+            ptr: old.cast(),
+            free: free_entries::<K, V>,
+        };
+        self.rcu.call_rcu(move || unsafe { retired.free() });
+        result
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> Default for RcuMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V, S> Drop for RcuMap<K, V, S> {
+    fn drop(&mut self) {
+        // Replaced entries are freed with `rcu`
+        for bucket in self.buckets.iter() {
+            unsafe { free_entries::<K, V>(bucket.entries.load(Ordering::Relaxed).cast()) };
+        }
+    }
+}
+
+#[cfg(all(test, not(loom), not(pct)))]
+mod tests {
+    use super::*;
+    use crate::linearizability::models::{Map, MapOp};
+    use crate::linearizability::{assert_linearizable, map_op, programs};
+    use proptest::prelude::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn insert_get_remove() {
+        let map = RcuMap::with_buckets(4);
+        for k in 0..16 {
+            assert_eq!(map.insert(k, k * 10), None);
+        }
+        assert_eq!(map.insert(3, 0), Some(30));
+        assert_eq!(map.get(&3), Some(0));
+        assert_eq!(map.read(&4, |v| v + 1), Some(41));
+        assert_eq!(map.remove(&4), Some(40));
+        assert!(!map.contains_key(&4));
+        assert_eq!(map.remove(&4), None);
+        assert_eq!(map.get(&15), Some(150));
+    }
+
+    #[test]
+    fn borrowed_keys() {
+        let map = RcuMap::new();
+        map.insert("key".to_string(), 1);
+        assert_eq!(map.get("key"), Some(1));
+        assert_eq!(map.remove("key"), Some(1));
+    }
+
+    #[test]
+    fn drop_drops_values() {
+        let value = Arc::new(());
+        let map = RcuMap::new();
+        map.insert(1, Arc::clone(&value));
+        map.insert(2, Arc::clone(&value));
+        map.insert(1, Arc::clone(&value));
+        map.remove(&2);
+        drop(map);
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+
+    #[test]
+    fn readers_see_every_write_in_order() {
+        let map = RcuMap::with_buckets(8);
+        for k in 0..64 {
+            map.insert(k, 0);
+        }
+        thread::scope(|s| {
+            for t in 0..3 {
+                let map = &map;
+                s.spawn(move || {
+                    let mut last = [0; 64];
+                    for i in 0..20_000 {
+                        let k = (i * 7 + t) % 64;
+                        let v = map.get(&k).unwrap();
+                        assert!(v >= last[k]);
+                        last[k] = v;
+                    }
+                });
+            }
+            for v in 1..=100 {
+                for k in 0..64 {
+                    map.insert(k, v);
+                }
+            }
+        });
+        assert!((0..64).all(|k| map.get(&k) == Some(100)));
+    }
+
+    proptest! {
+        // Two buckets, so that writers to different keys also replace each other's entry lists
+        #[test]
+        fn linearizable(programs in programs(4, map_op())) {
+            let new = || RcuMap::with_buckets(2);
+            assert_linearizable::<Map<usize, usize>, _>(&programs, new, |map, op| match *op {
+                MapOp::Insert(key, value) => map.insert(key, value),
+                MapOp::Get(key) => map.get(&key),
+                MapOp::Remove(key) => map.remove(&key),
+            })?;
+        }
+    }
+}
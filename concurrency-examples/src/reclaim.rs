@@ -0,0 +1,302 @@
+//! Safe memory reclamation for lock-free data structures.
+//!
+//! A thread that unlinks a node from a lock-free structure cannot free it right away: other threads
+//! may have loaded a pointer to it before it was unlinked and still be about to read it. Freeing
+//! the node early is a use-after-free, and if the allocator hands the same address out again, a
+//! stale `compare_exchange` succeeds on the new node (the ABA problem).
+//!
+//! A [`Reclaim`] scheme defers the free until no thread can still hold the pointer:
+//!
+//! - [`Immediate`] doesn't defer at all, which is only correct without concurrent readers. It is
+//!   here to show what goes wrong.
+//! - [`HazardPointers`] makes readers publish each pointer they are about to dereference. A retired
+//!   pointer is freed once no hazard slot holds it.
+//! - [`Epoch`] makes readers announce the global epoch they are in. The epoch advances only once
+//!   every active reader has seen the current one, and a pointer retired in epoch `e` is freed once
+//!   the global epoch reaches `e + 2`.
+
+use crate::sync::{
+    atomic::{fence, AtomicBool, AtomicPtr, AtomicUsize, Ordering},
+    hint, Mutex,
+};
+use std::ptr;
+
+// Number of concurrently active guards
+const SLOTS: usize = if cfg!(any(loom, pct)) { 4 } else { 64 };
+
+// Number of retired pointers that triggers an attempt to free them. Loom runs so few operations
+// that it needs to try every time.
+const SCAN_THRESHOLD: usize = if cfg!(any(loom, pct)) { 1 } else { 64 };
+
+pub trait Reclaim: Default + Send + Sync {
+    /// Marks a read-side critical section. Pointers returned by `protect` stay valid until the
+    /// guard is dropped.
+    type Guard<'a>
+    where
+        Self: 'a;
+
+    fn enter(&self) -> Self::Guard<'_>;
+
+    /// Loads a pointer from `src` that stays valid while `guard` is alive, or until the next call
+    /// to `protect` with the same guard.
+    fn protect<T>(&self, guard: &Self::Guard<'_>, src: &AtomicPtr<T>) -> *mut T;
+
+    /// Calls `free(ptr)` once no thread can still access `ptr`. The guard no longer protects
+    /// anything afterwards.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must already be unreachable for threads that call `protect` after this call, and must
+    /// be retired only once.
+    unsafe fn retire(&self, guard: &Self::Guard<'_>, ptr: *mut (), free: unsafe fn(*mut ()));
+}
+
+pub(crate) struct Retired {
+    pub(crate) ptr: *mut (),
+    pub(crate) free: unsafe fn(*mut ()),
+}
+
+unsafe impl Send for Retired {}
+
+impl Retired {
+    pub(crate) unsafe fn free(self) {
+        (self.free)(self.ptr);
+    }
+}
+
+/// Claims the first slot whose `in_use` flag is clear, spinning while all are taken.
+pub(crate) fn claim<S>(slots: &[S], in_use: fn(&S) -> &AtomicBool) -> &S {
+    loop {
+        for slot in slots {
+            if in_use(slot)
+                .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+            {
+                return slot;
+            }
+        }
+        hint::spin_loop();
+    }
+}
+
+/// No reclamation: retired pointers are freed straight away.
+#[derive(Default)]
+pub struct Immediate;
+
+impl Reclaim for Immediate {
+    type Guard<'a> = ();
+
+    fn enter(&self) {}
+
+    fn protect<T>(&self, _guard: &(), src: &AtomicPtr<T>) -> *mut T {
+        src.load(Ordering::Acquire)
+    }
+
+    unsafe fn retire(&self, _guard: &(), ptr: *mut (), free: unsafe fn(*mut ())) {
+        free(ptr);
+    }
+}
+
+struct HazardSlot {
+    in_use: AtomicBool,
+    hazard: AtomicPtr<()>,
+}
+
+pub struct HazardPointers {
+    slots: Box<[HazardSlot]>,
+    retired: Mutex<Vec<Retired>>,
+}
+
+pub struct HazardGuard<'a> {
+    slot: &'a HazardSlot,
+}
+
+impl Default for HazardPointers {
+    fn default() -> Self {
+        Self {
+            slots: (0..SLOTS)
+                .map(|_| HazardSlot {
+                    in_use: AtomicBool::new(false),
+                    hazard: AtomicPtr::new(ptr::null_mut()),
+                })
+                .collect(),
+            retired: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+impl Reclaim for HazardPointers {
+    type Guard<'a> = HazardGuard<'a>;
+
+    fn enter(&self) -> HazardGuard<'_> {
+        HazardGuard {
+            slot: claim(&self.slots, |s| &s.in_use),
+        }
+    }
+
+    fn protect<T>(&self, guard: &HazardGuard<'_>, src: &AtomicPtr<T>) -> *mut T {
+        let mut p = src.load(Ordering::Relaxed);
+        loop {
+            // Release so that reads through the previously protected pointer happen before a scan
+            // that no longer finds it
+            guard.slot.hazard.store(p.cast(), Ordering::Release);
+            // Either the reclaimer's scan sees the hazard, or this load sees that `p` has been
+            // unlinked. Pairs with the fence in `retire`.
+            fence(Ordering::SeqCst);
+            let q = src.load(Ordering::Acquire);
+            if p == q {
+                return p;
+            }
+            p = q;
+        }
+    }
+
+    unsafe fn retire(&self, guard: &HazardGuard<'_>, ptr: *mut (), free: unsafe fn(*mut ())) {
+        guard.slot.hazard.store(ptr::null_mut(), Ordering::Release);
+
+        let mut retired = self.retired.lock().unwrap();
+        retired.push(Retired { ptr, free });
+        if retired.len() < SCAN_THRESHOLD {
+            return;
+        }
+
+        fence(Ordering::SeqCst);
+        let hazards: Vec<*mut ()> = self
+            .slots
+            .iter()
+            .map(|s| s.hazard.load(Ordering::Acquire))
+            .collect();
+        for r in std::mem::take(&mut *retired) {
+            if hazards.contains(&r.ptr) {
+                retired.push(r);
+            } else {
+                r.free();
+            }
+        }
+    }
+}
+
+impl Drop for HazardGuard<'_> {
+    fn drop(&mut self) {
+        self.slot.hazard.store(ptr::null_mut(), Ordering::Release);
+        self.slot.in_use.store(false, Ordering::Release);
+    }
+}
+
+impl Drop for HazardPointers {
+    fn drop(&mut self) {
+        for r in self.retired.get_mut().unwrap().drain(..) {
+            unsafe { r.free() };
+        }
+    }
+}
+
+// The epoch a guard is in is shifted left by one, and the lowest bit is set while pinned
+const PINNED: usize = 1;
+
+struct EpochSlot {
+    in_use: AtomicBool,
+    local: AtomicUsize,
+}
+
+pub struct Epoch {
+    global: AtomicUsize,
+    slots: Box<[EpochSlot]>,
+    garbage: Mutex<Vec<(usize, Retired)>>,
+}
+
+pub struct EpochGuard<'a> {
+    slot: &'a EpochSlot,
+}
+
+impl Default for Epoch {
+    fn default() -> Self {
+        Self {
+            global: AtomicUsize::new(0),
+            slots: (0..SLOTS)
+                .map(|_| EpochSlot {
+                    in_use: AtomicBool::new(false),
+                    local: AtomicUsize::new(0),
+                })
+                .collect(),
+            garbage: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+impl Epoch {
+    /// Advances the global epoch if every pinned guard has seen the current one. Returns the
+    /// global epoch.
+    fn try_advance(&self) -> usize {
+        let global = self.global.load(Ordering::Relaxed);
+        fence(Ordering::SeqCst);
+        for slot in self.slots.iter() {
+            let local = slot.local.load(Ordering::Relaxed);
+            if local & PINNED == PINNED && local >> 1 != global {
+                return global;
+            }
+        }
+        // Everything the guards did before unpinning or re-pinning happens before what follows
+        fence(Ordering::Acquire);
+        match self
+            .global
+            .compare_exchange(global, global + 1, Ordering::Release, Ordering::Relaxed)
+        {
+            Ok(_) => global + 1,
+            Err(current) => current,
+        }
+    }
+}
+
+impl Reclaim for Epoch {
+    type Guard<'a> = EpochGuard<'a>;
+
+    fn enter(&self) -> EpochGuard<'_> {
+        let slot = claim(&self.slots, |s| &s.in_use);
+        let global = self.global.load(Ordering::Relaxed);
+        slot.local.store(global << 1 | PINNED, Ordering::Release);
+        // Pairs with the fence in `try_advance`: either the pin is seen there, or this thread sees
+        // every pointer unlinked before the epoch advanced as unlinked.
+        fence(Ordering::SeqCst);
+        EpochGuard { slot }
+    }
+
+    fn protect<T>(&self, _guard: &EpochGuard<'_>, src: &AtomicPtr<T>) -> *mut T {
+        src.load(Ordering::Acquire)
+    }
+
+    unsafe fn retire(&self, _guard: &EpochGuard<'_>, ptr: *mut (), free: unsafe fn(*mut ())) {
+        // Threads pinned in this epoch or the one before may still hold `ptr`
+        fence(Ordering::SeqCst);
+        let epoch = self.global.load(Ordering::Relaxed);
+        let mut garbage = self.garbage.lock().unwrap();
+        garbage.push((epoch, Retired { ptr, free }));
+        if garbage.len() < SCAN_THRESHOLD {
+            return;
+        }
+
+        let global = self.try_advance();
+        for (epoch, r) in std::mem::take(&mut *garbage) {
+            if epoch + 2 <= global {
+                r.free();
+            } else {
+                garbage.push((epoch, r));
+            }
+        }
+    }
+}
+
+impl Drop for EpochGuard<'_> {
+    fn drop(&mut self) {
+        self.slot.local.store(0, Ordering::Release);
+        self.slot.in_use.store(false, Ordering::Release);
+    }
+}
+
+impl Drop for Epoch {
+    fn drop(&mut self) {
+        for (_, r) in self.garbage.get_mut().unwrap().drain(..) {
+            unsafe { r.free() };
+        }
+    }
+}
@@ -0,0 +1,444 @@
+//! Bounded ring-buffer queues for passing messages through shared memory.
+//!
+//! [`spsc`] creates a single-producer single-consumer queue. Each side owns one index and only
+//! reads the other's, so `try_send` and `try_recv` are wait-free. Each side also caches the last
+//! value it read of the other index and reloads it only when the queue looks full or empty, which
+//! keeps the cache line holding that index from bouncing between cores on every operation.
+//!
+//! [`MpmcQueue`] is Dmitry Vyukov's bounded multi-producer multi-consumer queue. Every slot has a
+//! sequence number that says whose turn it is: a producer at position `pos` waits for the slot's
+//! sequence to be `pos`, and a consumer waits for `pos + 1`. Producers and consumers claim
+//! positions with `compare_exchange` and only touch their own slot afterwards.
+//!
+//! Indices increase without bound and are masked into the buffer, so capacities are rounded up to
+//! a power of two. The blocking `send` and `recv` spin and then yield while the queue is full or
+//! empty; they don't notice if the other side has gone away.
+
+use crate::sync::{
+    atomic::{AtomicUsize, Ordering},
+    hint, thread, Arc, UnsafeCell,
+};
+use std::mem::MaybeUninit;
+use std::ops::Deref;
+
+/// Aligns a value to 128 bytes, which covers the cache line size plus the adjacent line that
+/// x86 prefetches along with it, so that it doesn't share a line with anything else.
+#[repr(align(128))]
+pub(crate) struct CachePadded<T>(pub(crate) T);
+
+impl<T> Deref for CachePadded<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+/// Waits a little longer on each call: first by spinning, then by yielding to the scheduler.
+struct Backoff {
+    step: u32,
+}
+
+impl Backoff {
+    const SPIN_LIMIT: u32 = 6;
+
+    fn new() -> Self {
+        Self { step: 0 }
+    }
+
+    fn snooze(&mut self) {
+        if self.step <= Self::SPIN_LIMIT {
+            for _ in 0..1 << self.step {
+                hint::spin_loop();
+            }
+            self.step += 1;
+        } else {
+            thread::yield_now();
+        }
+    }
+}
+
+type Slot<T> = UnsafeCell<MaybeUninit<T>>;
+
+struct Spsc<T> {
+    buffer: Box<[Slot<T>]>,
+    /// Next position to read, written only by the consumer
+    head: CachePadded<AtomicUsize>,
+    /// Next position to write, written only by the producer
+    tail: CachePadded<AtomicUsize>,
+}
+
+unsafe impl<T: Send> Sync for Spsc<T> {}
+
+impl<T> Drop for Spsc<T> {
+    fn drop(&mut self) {
+        let head = self.head.load(Ordering::Relaxed);
+        let tail = self.tail.load(Ordering::Relaxed);
+        let mask = self.buffer.len() - 1;
+        for pos in head..tail {
+            self.buffer[pos & mask].with_mut(|slot| unsafe { (*slot).assume_init_drop() });
+        }
+    }
+}
+
+pub struct Producer<T> {
+    queue: Arc<Spsc<T>>,
+    tail: usize,
+    cached_head: usize,
+}
+
+pub struct Consumer<T> {
+    queue: Arc<Spsc<T>>,
+    head: usize,
+    cached_tail: usize,
+}
+
+unsafe impl<T: Send> Send for Producer<T> {}
+unsafe impl<T: Send> Send for Consumer<T> {}
+
+/// Creates a single-producer single-consumer queue that holds at least `capacity` values.
+pub fn spsc<T>(capacity: usize) -> (Producer<T>, Consumer<T>) {
+    assert!(capacity > 0, "capacity must be positive");
+    let queue = Arc::new(Spsc {
+        buffer: (0..capacity.next_power_of_two())
+            .map(|_| UnsafeCell::new(MaybeUninit::uninit()))
+            .collect(),
+        head: CachePadded(AtomicUsize::new(0)),
+        tail: CachePadded(AtomicUsize::new(0)),
+    });
+
+    (
+        Producer {
+            queue: queue.clone(),
+            tail: 0,
+            cached_head: 0,
+        },
+        Consumer {
+            queue,
+            head: 0,
+            cached_tail: 0,
+        },
+    )
+}
+
+impl<T> Producer<T> {
+    /// Sends `value`, or gives it back if the queue is full.
+    pub fn try_send(&mut self, value: T) -> Result<(), T> {
+        let capacity = self.queue.buffer.len();
+        if self.tail - self.cached_head == capacity {
+            // Acquire so that the consumer has finished reading the slot before it is overwritten
+            self.cached_head = self.queue.head.load(Ordering::Acquire);
+            if self.tail - self.cached_head == capacity {
+                return Err(value);
+            }
+        }
+
+        self.queue.buffer[self.tail & (capacity - 1)].with_mut(|slot| unsafe {
+            (*slot).write(value);
+        });
+        self.tail += 1;
+        self.queue.tail.store(self.tail, Ordering::Release);
+        Ok(())
+    }
+
+    /// Sends `value`, waiting while the queue is full.
+    pub fn send(&mut self, mut value: T) {
+        let mut backoff = Backoff::new();
+        while let Err(v) = self.try_send(value) {
+            value = v;
+            backoff.snooze();
+        }
+    }
+}
+
+impl<T> Consumer<T> {
+    /// Receives a value, or returns `None` if the queue is empty.
+    pub fn try_recv(&mut self) -> Option<T> {
+        if self.head == self.cached_tail {
+            // Acquire so that the value written before the tail was published is visible
+            self.cached_tail = self.queue.tail.load(Ordering::Acquire);
+            if self.head == self.cached_tail {
+                return None;
+            }
+        }
+
+        let mask = self.queue.buffer.len() - 1;
+        let value =
+            self.queue.buffer[self.head & mask].with(|slot| unsafe { (*slot).assume_init_read() });
+        self.head += 1;
+        self.queue.head.store(self.head, Ordering::Release);
+        Some(value)
+    }
+
+    /// Receives a value, waiting while the queue is empty.
+    pub fn recv(&mut self) -> T {
+        let mut backoff = Backoff::new();
+        loop {
+            if let Some(value) = self.try_recv() {
+                return value;
+            }
+            backoff.snooze();
+        }
+    }
+}
+
+struct MpmcSlot<T> {
+    seq: AtomicUsize,
+    value: Slot<T>,
+}
+
+pub struct MpmcQueue<T> {
+    buffer: Box<[MpmcSlot<T>]>,
+    /// Next position to write
+    tail: CachePadded<AtomicUsize>,
+    /// Next position to read
+    head: CachePadded<AtomicUsize>,
+}
+
+unsafe impl<T: Send> Send for MpmcQueue<T> {}
+unsafe impl<T: Send> Sync for MpmcQueue<T> {}
+
+impl<T> MpmcQueue<T> {
+    /// Creates a queue that holds at least `capacity` values. A slot can't tell a full queue from
+    /// an empty one with a single slot, so the capacity is at least two.
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "capacity must be positive");
+        Self {
+            buffer: (0..capacity.next_power_of_two().max(2))
+                .map(|i| MpmcSlot {
+                    seq: AtomicUsize::new(i),
+                    value: UnsafeCell::new(MaybeUninit::uninit()),
+                })
+                .collect(),
+            tail: CachePadded(AtomicUsize::new(0)),
+            head: CachePadded(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Sends `value`, or gives it back if the queue is full.
+    pub fn try_send(&self, value: T) -> Result<(), T> {
+        let mask = self.buffer.len() - 1;
+        let mut pos = self.tail.load(Ordering::Relaxed);
+        loop {
+            let slot = &self.buffer[pos & mask];
+            // Acquire so that the consumer of the previous lap has finished reading the slot
+            let seq = slot.seq.load(Ordering::Acquire);
+            let diff = seq.wrapping_sub(pos) as isize;
+
+            if diff == 0 {
+                match self
+                    .tail
+                    .compare_exchange(pos, pos + 1, Ordering::Relaxed, Ordering::Relaxed)
+                {
+                    Ok(_) => {
+                        slot.value.with_mut(|v| unsafe { (*v).write(value) });
+                        slot.seq.store(pos + 1, Ordering::Release);
+                        return Ok(());
+                    }
+                    Err(p) => pos = p,
+                }
+            } else if diff < 0 {
+                // The slot still holds the value from the previous lap
+                return Err(value);
+            } else {
+                // Another producer has taken this position
+                pos = self.tail.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Receives a value, or returns `None` if the queue is empty.
+    pub fn try_recv(&self) -> Option<T> {
+        let mask = self.buffer.len() - 1;
+        let mut pos = self.head.load(Ordering::Relaxed);
+        loop {
+            let slot = &self.buffer[pos & mask];
+            // Acquire so that the producer's write of the value is visible
+            let seq = slot.seq.load(Ordering::Acquire);
+            let diff = seq.wrapping_sub(pos + 1) as isize;
+
+            if diff == 0 {
+                match self
+                    .head
+                    .compare_exchange(pos, pos + 1, Ordering::Relaxed, Ordering::Relaxed)
+                {
+                    Ok(_) => {
+                        let value = slot.value.with(|v| unsafe { (*v).assume_init_read() });
+                        // Hand the slot to the producer one lap ahead
+                        slot.seq.store(pos + self.buffer.len(), Ordering::Release);
+                        return Some(value);
+                    }
+                    Err(p) => pos = p,
+                }
+            } else if diff < 0 {
+                // No producer has written this position yet
+                return None;
+            } else {
+                // Another consumer has taken this position
+                pos = self.head.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Sends `value`, waiting while the queue is full.
+    pub fn send(&self, mut value: T) {
+        let mut backoff = Backoff::new();
+        while let Err(v) = self.try_send(value) {
+            value = v;
+            backoff.snooze();
+        }
+    }
+
+    /// Receives a value, waiting while the queue is empty.
+    pub fn recv(&self) -> T {
+        let mut backoff = Backoff::new();
+        loop {
+            if let Some(value) = self.try_recv() {
+                return value;
+            }
+            backoff.snooze();
+        }
+    }
+}
+
+impl<T> Drop for MpmcQueue<T> {
+    fn drop(&mut self) {
+        while self.try_recv().is_some() {}
+    }
+}
+
+#[cfg(all(test, not(loom), not(pct)))]
+mod tests {
+    use super::*;
+    use crate::linearizability::models::{PushPop, Queue};
+    use crate::linearizability::{assert_linearizable, producers_and_consumers};
+    use proptest::prelude::*;
+    use std::sync::Mutex;
+
+    /// Room for every push of a property test, so that a send never waits for a receive that
+    /// doesn't come
+    const CAPACITY: usize = 64;
+
+    #[test]
+    fn spsc_fills_and_drains() {
+        let (mut tx, mut rx) = spsc(3);
+        for i in 0..4 {
+            tx.try_send(i).unwrap();
+        }
+        assert_eq!(tx.try_send(4), Err(4));
+        for i in 0..4 {
+            assert_eq!(rx.try_recv(), Some(i));
+        }
+        assert_eq!(rx.try_recv(), None);
+    }
+
+    #[test]
+    fn spsc_passes_values_in_order() {
+        let (mut tx, mut rx) = spsc(16);
+        let producer = thread::spawn(move || {
+            for i in 0..100_000 {
+                tx.send(i);
+            }
+        });
+        for i in 0..100_000 {
+            assert_eq!(rx.recv(), i);
+        }
+        producer.join().unwrap();
+    }
+
+    #[test]
+    fn spsc_drops_unreceived_values() {
+        let value = Arc::new(());
+        let (mut tx, rx) = spsc(4);
+        tx.send(Arc::clone(&value));
+        tx.send(Arc::clone(&value));
+        drop((tx, rx));
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+
+    #[test]
+    fn mpmc_fills_and_drains() {
+        let queue = MpmcQueue::new(4);
+        for i in 0..4 {
+            queue.try_send(i).unwrap();
+        }
+        assert_eq!(queue.try_send(4), Err(4));
+        for i in 0..4 {
+            assert_eq!(queue.try_recv(), Some(i));
+        }
+        assert_eq!(queue.try_recv(), None);
+    }
+
+    #[test]
+    fn mpmc_delivers_every_value_once() {
+        let queue = Arc::new(MpmcQueue::new(16));
+
+        let producers: Vec<_> = (0..2)
+            .map(|t| {
+                let queue = Arc::clone(&queue);
+                thread::spawn(move || {
+                    for i in 0..10_000 {
+                        queue.send(t * 10_000 + i);
+                    }
+                })
+            })
+            .collect();
+        let consumers: Vec<_> = (0..2)
+            .map(|_| {
+                let queue = Arc::clone(&queue);
+                thread::spawn(move || (0..10_000).map(|_| queue.recv()).collect::<Vec<_>>())
+            })
+            .collect();
+
+        for producer in producers {
+            producer.join().unwrap();
+        }
+        let mut received: Vec<_> = consumers
+            .into_iter()
+            .flat_map(|c| c.join().unwrap())
+            .collect();
+        received.sort();
+        assert_eq!(received, (0..20_000).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn mpmc_drops_unreceived_values() {
+        let value = Arc::new(());
+        let queue = MpmcQueue::new(4);
+        queue.send(Arc::clone(&value));
+        queue.send(Arc::clone(&value));
+        drop(queue);
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+
+    // The consumers block, as a `try_recv` that finds the next slot still being written returns
+    // `None` even if later sends have finished
+    proptest! {
+        #[test]
+        fn spsc_is_linearizable(programs in producers_and_consumers(1, 1)) {
+            let new = || {
+                let (tx, rx) = spsc(CAPACITY);
+                (Mutex::new(tx), Mutex::new(rx))
+            };
+            assert_linearizable::<Queue<u64>, _>(&programs, new, |(tx, rx), op| match *op {
+                PushPop::Push(value) => {
+                    tx.lock().unwrap().send(value);
+                    None
+                }
+                PushPop::Pop => Some(rx.lock().unwrap().recv()),
+            })?;
+        }
+
+        #[test]
+        fn mpmc_is_linearizable(programs in producers_and_consumers(2, 2)) {
+            let new = || MpmcQueue::new(CAPACITY);
+            assert_linearizable::<Queue<u64>, _>(&programs, new, |queue, op| match *op {
+                PushPop::Push(value) => {
+                    queue.send(value);
+                    None
+                }
+                PushPop::Pop => Some(queue.recv()),
+            })?;
+        }
+    }
+}
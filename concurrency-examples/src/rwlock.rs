@@ -0,0 +1,423 @@
+//! Reader-writer locks.
+//!
+//! [`RwSpinLock`] keeps the writer flag and the reader count in one `AtomicUsize` and spins. New
+//! readers can keep entering while a writer waits, so a steady stream of readers starves writers.
+//!
+//! [`RwLock`] sleeps on [`crate::futex`] and prefers writers. Its state is twice the number of
+//! readers, plus one if a writer is waiting, or `u32::MAX` when write-locked. Readers don't enter
+//! while the state is odd, so a waiting writer gets in as soon as the current readers leave.
+
+use crate::futex::{atomic_wait, wake_all, wake_one};
+use crate::sync::{
+    atomic::{AtomicU32, AtomicUsize, Ordering},
+    hint, UnsafeCell,
+};
+use std::ops::{Deref, DerefMut};
+
+const WRITER: usize = 1;
+const READER: usize = 2;
+
+pub struct RwSpinLock<T> {
+    state: AtomicUsize,
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send + Sync> Sync for RwSpinLock<T> {}
+
+pub struct SpinReadGuard<'a, T> {
+    lock: &'a RwSpinLock<T>,
+}
+
+pub struct SpinWriteGuard<'a, T> {
+    lock: &'a RwSpinLock<T>,
+}
+
+impl<T> RwSpinLock<T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            state: AtomicUsize::new(0),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn read(&self) -> SpinReadGuard<'_, T> {
+        let mut s = self.state.load(Ordering::Relaxed);
+        loop {
+            if s & WRITER == 0 {
+                match self.state.compare_exchange(
+                    s,
+                    s + READER,
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => return SpinReadGuard { lock: self },
+                    Err(e) => s = e,
+                }
+            } else {
+                hint::spin_loop();
+                s = self.state.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    pub fn write(&self) -> SpinWriteGuard<'_, T> {
+        while self
+            .state
+            .compare_exchange(0, WRITER, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            hint::spin_loop();
+        }
+        SpinWriteGuard { lock: self }
+    }
+}
+
+impl<'a, T> SpinWriteGuard<'a, T> {
+    /// Turns the write lock into a read lock without letting another writer in between.
+    pub fn downgrade(guard: Self) -> SpinReadGuard<'a, T> {
+        let lock = guard.lock;
+        std::mem::forget(guard);
+        // No reader can have entered while the writer flag was set
+        lock.state.store(READER, Ordering::Release);
+        SpinReadGuard { lock }
+    }
+}
+
+impl<T> Deref for SpinReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.lock.value.with(|value| unsafe { &*value })
+    }
+}
+
+impl<T> Deref for SpinWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.lock.value.with(|value| unsafe { &*value })
+    }
+}
+
+impl<T> DerefMut for SpinWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.lock.value.with_mut(|value| unsafe { &mut *value })
+    }
+}
+
+impl<T> Drop for SpinReadGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.state.fetch_sub(READER, Ordering::Release);
+    }
+}
+
+impl<T> Drop for SpinWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.state.store(0, Ordering::Release);
+    }
+}
+
+const WRITE_LOCKED: u32 = u32::MAX;
+
+pub struct RwLock<T> {
+    state: AtomicU32,
+    /// Incremented to wake up writers
+    writer_wake_counter: AtomicU32,
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send + Sync> Sync for RwLock<T> {}
+
+pub struct ReadGuard<'a, T> {
+    lock: &'a RwLock<T>,
+}
+
+pub struct WriteGuard<'a, T> {
+    lock: &'a RwLock<T>,
+}
+
+impl<T> RwLock<T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            state: AtomicU32::new(0),
+            writer_wake_counter: AtomicU32::new(0),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn read(&self) -> ReadGuard<'_, T> {
+        let mut s = self.state.load(Ordering::Relaxed);
+        loop {
+            if s.is_multiple_of(2) {
+                assert!(s < WRITE_LOCKED - 2, "too many readers");
+                match self
+                    .state
+                    .compare_exchange(s, s + 2, Ordering::Acquire, Ordering::Relaxed)
+                {
+                    Ok(_) => return ReadGuard { lock: self },
+                    Err(e) => s = e,
+                }
+            }
+            if !s.is_multiple_of(2) {
+                atomic_wait(&self.state, s);
+                s = self.state.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    pub fn write(&self) -> WriteGuard<'_, T> {
+        let mut s = self.state.load(Ordering::Relaxed);
+        loop {
+            // Lock if there are no readers, whether or not other writers are waiting
+            if s <= 1 {
+                match self.state.compare_exchange(
+                    s,
+                    WRITE_LOCKED,
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => return WriteGuard { lock: self },
+                    Err(e) => {
+                        s = e;
+                        continue;
+                    }
+                }
+            }
+            // Keep new readers out by making the state odd
+            if s.is_multiple_of(2) {
+                if let Err(e) =
+                    self.state
+                        .compare_exchange(s, s + 1, Ordering::Relaxed, Ordering::Relaxed)
+                {
+                    s = e;
+                    continue;
+                }
+            }
+            // Sleep if it's still locked
+            let w = self.writer_wake_counter.load(Ordering::Acquire);
+            s = self.state.load(Ordering::Relaxed);
+            if s >= 2 {
+                atomic_wait(&self.writer_wake_counter, w);
+                s = self.state.load(Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+impl<'a, T> WriteGuard<'a, T> {
+    /// Turns the write lock into a read lock without letting another writer in between.
+    pub fn downgrade(guard: Self) -> ReadGuard<'a, T> {
+        let lock = guard.lock;
+        std::mem::forget(guard);
+        lock.state.store(2, Ordering::Release);
+        // The store cleared the waiting writer bit. Waiting writers set it again, and waiting
+        // readers get in alongside this one if no writer is waiting.
+        lock.writer_wake_counter.fetch_add(1, Ordering::Release);
+        wake_all(&lock.writer_wake_counter);
+        wake_all(&lock.state);
+        ReadGuard { lock }
+    }
+}
+
+impl<T> Deref for ReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.lock.value.with(|value| unsafe { &*value })
+    }
+}
+
+impl<T> Deref for WriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.lock.value.with(|value| unsafe { &*value })
+    }
+}
+
+impl<T> DerefMut for WriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.lock.value.with_mut(|value| unsafe { &mut *value })
+    }
+}
+
+impl<T> Drop for ReadGuard<'_, T> {
+    fn drop(&mut self) {
+        // Going from 3 to 1 means the last reader left while a writer is waiting
+        if self.lock.state.fetch_sub(2, Ordering::Release) == 3 {
+            self.lock
+                .writer_wake_counter
+                .fetch_add(1, Ordering::Release);
+            wake_one(&self.lock.writer_wake_counter);
+        }
+    }
+}
+
+impl<T> Drop for WriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.state.store(0, Ordering::Release);
+        self.lock
+            .writer_wake_counter
+            .fetch_add(1, Ordering::Release);
+        wake_one(&self.lock.writer_wake_counter);
+        wake_all(&self.lock.state);
+    }
+}
+
+#[cfg(all(test, not(loom), not(pct)))]
+mod tests {
+    use super::*;
+    use crate::linearizability::models::{Counter, CounterOp};
+    use crate::linearizability::{assert_linearizable, programs};
+    use proptest::prelude::*;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn spin_readers_share() {
+        let lock = RwSpinLock::new(1);
+        let r1 = lock.read();
+        let r2 = lock.read();
+        assert_eq!(*r1 + *r2, 2);
+    }
+
+    #[test]
+    fn spin_writers_exclude() {
+        let lock = Arc::new(RwSpinLock::new(0));
+
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let lock = Arc::clone(&lock);
+                thread::spawn(move || {
+                    for _ in 0..10_000 {
+                        *lock.write() += 1;
+                        assert!(*lock.read() > 0);
+                    }
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert_eq!(*lock.read(), 40_000);
+    }
+
+    #[test]
+    fn spin_downgrade_keeps_value() {
+        let lock = RwSpinLock::new(0);
+        let mut w = lock.write();
+        *w = 1;
+        let r = SpinWriteGuard::downgrade(w);
+        assert_eq!(*r, 1);
+        let r2 = lock.read();
+        assert_eq!(*r2, 1);
+    }
+
+    #[test]
+    fn readers_share() {
+        let lock = RwLock::new(1);
+        let r1 = lock.read();
+        let r2 = lock.read();
+        assert_eq!(*r1 + *r2, 2);
+    }
+
+    #[test]
+    fn writers_exclude() {
+        let lock = Arc::new(RwLock::new(0));
+
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let lock = Arc::clone(&lock);
+                thread::spawn(move || {
+                    for _ in 0..10_000 {
+                        *lock.write() += 1;
+                        assert!(*lock.read() > 0);
+                    }
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert_eq!(*lock.read(), 40_000);
+    }
+
+    #[test]
+    fn waiting_writer_blocks_new_readers() {
+        let lock = Arc::new(RwLock::new(0));
+        let r = lock.read();
+
+        let writer = {
+            let lock = Arc::clone(&lock);
+            thread::spawn(move || *lock.write() = 1)
+        };
+
+        // Wait for the writer to announce itself
+        while lock.state.load(Ordering::Relaxed).is_multiple_of(2) {
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        let reader = {
+            let lock = Arc::clone(&lock);
+            thread::spawn(move || *lock.read())
+        };
+
+        thread::sleep(Duration::from_millis(10));
+        drop(r);
+
+        writer.join().unwrap();
+        assert_eq!(reader.join().unwrap(), 1);
+    }
+
+    #[test]
+    fn downgrade_keeps_value() {
+        let lock = Arc::new(RwLock::new(0));
+        let mut w = lock.write();
+        *w = 1;
+
+        let writer = {
+            let lock = Arc::clone(&lock);
+            thread::spawn(move || *lock.write() = 2)
+        };
+
+        let r = WriteGuard::downgrade(w);
+        assert_eq!(*r, 1);
+        drop(r);
+
+        writer.join().unwrap();
+        assert_eq!(*lock.read(), 2);
+    }
+
+    proptest! {
+        #[test]
+        fn spin_counter_is_linearizable(programs in programs(4, any::<CounterOp>())) {
+            let new = || RwSpinLock::new(0);
+            assert_linearizable::<Counter, _>(&programs, new, |lock, op| match op {
+                CounterOp::Increment => {
+                    let mut count = lock.write();
+                    *count += 1;
+                    *count - 1
+                }
+                CounterOp::Get => *lock.read(),
+            })?;
+        }
+
+        #[test]
+        fn counter_is_linearizable(programs in programs(4, any::<CounterOp>())) {
+            let new = || RwLock::new(0);
+            assert_linearizable::<Counter, _>(&programs, new, |lock, op| match op {
+                CounterOp::Increment => {
+                    let mut count = lock.write();
+                    *count += 1;
+                    *count - 1
+                }
+                CounterOp::Get => *lock.read(),
+            })?;
+        }
+    }
+}
@@ -0,0 +1,229 @@
+//! A counting semaphore that sleeps on [`crate::futex`].
+//!
+//! The atomic holds the number of free permits. `acquire(n)` takes `n` of them at once with a
+//! `compare_exchange` and sleeps while fewer are free. Returning permits wakes every waiter, since
+//! waiters may need different amounts. Nothing stops a stream of small acquisitions from starving a
+//! large one.
+
+use crate::futex::{atomic_wait, atomic_wait_timeout, wake_all};
+use crate::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Duration, Instant};
+
+pub struct Semaphore {
+    permits: AtomicU32,
+}
+
+/// Permits taken from a [`Semaphore`], returned when dropped.
+pub struct Permit<'a> {
+    semaphore: &'a Semaphore,
+    n: u32,
+}
+
+impl Semaphore {
+    pub fn new(permits: u32) -> Self {
+        Self {
+            permits: AtomicU32::new(permits),
+        }
+    }
+
+    pub fn available_permits(&self) -> u32 {
+        self.permits.load(Ordering::Relaxed)
+    }
+
+    /// Takes `n` permits if that many are free. Returns the number free otherwise.
+    fn take(&self, n: u32) -> Result<Permit<'_>, u32> {
+        let mut free = self.permits.load(Ordering::Relaxed);
+        while free >= n {
+            // Acquire so that what the previous holder did happens before
+            match self.permits.compare_exchange(
+                free,
+                free - n,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return Ok(Permit { semaphore: self, n }),
+                Err(f) => free = f,
+            }
+        }
+        Err(free)
+    }
+
+    /// Waits until `n` permits are free and takes them.
+    pub fn acquire(&self, n: u32) -> Permit<'_> {
+        loop {
+            match self.take(n) {
+                Ok(permit) => return permit,
+                Err(free) => atomic_wait(&self.permits, free),
+            }
+        }
+    }
+
+    pub fn try_acquire(&self, n: u32) -> Option<Permit<'_>> {
+        self.take(n).ok()
+    }
+
+    /// Like `acquire` but gives up after `timeout`.
+    pub fn acquire_timeout(&self, n: u32, timeout: Duration) -> Option<Permit<'_>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            match self.take(n) {
+                Ok(permit) => return Some(permit),
+                Err(free) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return None;
+                    }
+                    atomic_wait_timeout(&self.permits, free, deadline - now);
+                }
+            }
+        }
+    }
+
+    /// Adds `n` permits.
+    pub fn release(&self, n: u32) {
+        self.permits.fetch_add(n, Ordering::Release);
+        wake_all(&self.permits);
+    }
+}
+
+impl Permit<'_> {
+    pub fn count(&self) -> u32 {
+        self.n
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        self.semaphore.release(self.n);
+    }
+}
+
+#[cfg(all(test, not(loom), not(pct)))]
+mod tests {
+    use super::*;
+    use crate::linearizability::{assert_linearizable, programs, Spec};
+    use proptest::prelude::*;
+    use std::sync::atomic::AtomicU32;
+    use std::thread;
+
+    #[test]
+    fn limits_concurrent_holders() {
+        let semaphore = Semaphore::new(3);
+        let holders = AtomicU32::new(0);
+        let peak = AtomicU32::new(0);
+
+        thread::scope(|s| {
+            for n in [1, 2, 1, 3, 2, 1, 1, 2] {
+                let (semaphore, holders, peak) = (&semaphore, &holders, &peak);
+                s.spawn(move || {
+                    for _ in 0..1_000 {
+                        let _permit = semaphore.acquire(n);
+                        let now = holders.fetch_add(n, Ordering::Relaxed) + n;
+                        peak.fetch_max(now, Ordering::Relaxed);
+                        holders.fetch_sub(n, Ordering::Relaxed);
+                    }
+                });
+            }
+        });
+
+        assert!(peak.load(Ordering::Relaxed) <= 3);
+        assert_eq!(semaphore.available_permits(), 3);
+    }
+
+    #[test]
+    fn try_acquire_and_timeout_fail_when_exhausted() {
+        let semaphore = Semaphore::new(2);
+        let permit = semaphore.try_acquire(2).unwrap();
+        assert_eq!(permit.count(), 2);
+        assert!(semaphore.try_acquire(1).is_none());
+        assert!(semaphore
+            .acquire_timeout(1, Duration::from_millis(10))
+            .is_none());
+        drop(permit);
+        assert!(semaphore
+            .acquire_timeout(2, Duration::from_millis(10))
+            .is_some());
+    }
+
+    #[test]
+    fn acquire_wakes_when_enough_permits_return() {
+        let semaphore = Semaphore::new(2);
+        let first = semaphore.acquire(1);
+        let second = semaphore.acquire(1);
+        thread::scope(|s| {
+            let waiter = s.spawn(|| semaphore.acquire(2).count());
+            thread::sleep(Duration::from_millis(10));
+            drop(first);
+            thread::sleep(Duration::from_millis(10));
+            drop(second);
+            assert_eq!(waiter.join().unwrap(), 2);
+        });
+    }
+
+    const PERMITS: u32 = 4;
+
+    /// The permits that are free.
+    #[derive(Clone, PartialEq, Eq, Hash)]
+    struct Permits(u32);
+
+    /// `TryAcquire` returns how many permits it took, and `Available` how many are free.
+    #[derive(Clone, Debug)]
+    enum PermitOp {
+        TryAcquire(u32),
+        Release(u32),
+        Available,
+    }
+
+    impl Default for Permits {
+        fn default() -> Self {
+            Self(PERMITS)
+        }
+    }
+
+    impl Spec for Permits {
+        type Op = PermitOp;
+        type Ret = Option<u32>;
+
+        fn apply(&mut self, op: &PermitOp) -> Option<u32> {
+            match *op {
+                PermitOp::TryAcquire(n) if n <= self.0 => {
+                    self.0 -= n;
+                    Some(n)
+                }
+                PermitOp::TryAcquire(_) => None,
+                PermitOp::Release(n) => {
+                    self.0 += n;
+                    None
+                }
+                PermitOp::Available => Some(self.0),
+            }
+        }
+    }
+
+    fn permit_op() -> impl Strategy<Value = PermitOp> {
+        prop_oneof![
+            (1..=PERMITS).prop_map(PermitOp::TryAcquire),
+            (1..=PERMITS).prop_map(PermitOp::Release),
+            Just(PermitOp::Available),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn permits_are_linearizable(programs in programs(4, permit_op())) {
+            let new = || Semaphore::new(PERMITS);
+            assert_linearizable::<Permits, _>(&programs, new, |semaphore, op| match *op {
+                // Held until a `Release`, which needn't come from the same thread
+                PermitOp::TryAcquire(n) => semaphore.try_acquire(n).map(|permit| {
+                    std::mem::forget(permit);
+                    n
+                }),
+                PermitOp::Release(n) => {
+                    semaphore.release(n);
+                    None
+                }
+                PermitOp::Available => Some(semaphore.available_permits()),
+            })?;
+        }
+    }
+}
@@ -0,0 +1,202 @@
+//! A sequence lock for read-mostly data.
+//!
+//! Readers never write to shared memory. They read the sequence number, copy the data and read the
+//! sequence number again, retrying if a writer was active in between. A writer makes the sequence
+//! number odd for the duration of the write.
+//!
+//! The data is stored as `AtomicUsize` words accessed with `Relaxed` ordering, so a reader that
+//! overlaps a writer reads a torn but well-defined value and throws it away. The fences order the
+//! data accesses with respect to the sequence number, which plain acquire/release on the sequence
+//! number alone doesn't do: a release store only orders the accesses *before* it, and the reader
+//! needs its data loads ordered before the *second* sequence load.
+//!
+//! Copying a value into words reads all of its bytes as integers, so the data must be a
+//! [`NoPadding`] type: padding bytes are uninitialised, and a pointer would lose its provenance.
+
+use crate::sync::{
+    atomic::{fence, AtomicUsize, Ordering},
+    hint,
+};
+use std::marker::PhantomData;
+use std::mem::{self, MaybeUninit};
+use std::ptr;
+
+const WORD: usize = mem::size_of::<usize>();
+
+/// Types that are plain bytes, which a [`SeqLock`] can copy to and from its words.
+///
+/// # Safety
+///
+/// Every byte of the type must be initialised: it must have no padding and no `MaybeUninit` or
+/// union fields. It must not contain pointers or references either, which can't be rebuilt from
+/// their bytes.
+pub unsafe trait NoPadding: Copy {}
+
+macro_rules! no_padding {
+    ($($t:ty),*) => {
+        $(unsafe impl NoPadding for $t {})*
+    };
+}
+
+no_padding!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64);
+
+unsafe impl<T: NoPadding, const N: usize> NoPadding for [T; N] {}
+
+pub struct SeqLock<T> {
+    seq: AtomicUsize,
+    data: Box<[AtomicUsize]>,
+    _marker: PhantomData<T>,
+}
+
+unsafe impl<T: NoPadding + Send> Sync for SeqLock<T> {}
+
+impl<T: NoPadding> SeqLock<T> {
+    pub fn new(value: T) -> Self {
+        let lock = Self {
+            seq: AtomicUsize::new(0),
+            data: (0..mem::size_of::<T>().div_ceil(WORD))
+                .map(|_| AtomicUsize::new(0))
+                .collect(),
+            _marker: PhantomData,
+        };
+        lock.store_data(value);
+        lock
+    }
+
+    /// Returns a consistent snapshot of the data, retrying while a write is in progress.
+    pub fn read(&self) -> T {
+        loop {
+            let seq1 = self.seq.load(Ordering::Acquire);
+            if seq1 & 1 == 1 {
+                hint::spin_loop();
+                continue;
+            }
+
+            let value = self.load_data();
+            // Keeps the data loads above from moving below the second sequence load
+            fence(Ordering::Acquire);
+            let seq2 = self.seq.load(Ordering::Relaxed);
+
+            if seq1 == seq2 {
+                return unsafe { value.assume_init() };
+            }
+            hint::spin_loop();
+        }
+    }
+
+    /// Replaces the data. Concurrent writers are serialised.
+    pub fn write(&self, value: T) {
+        let mut seq = self.seq.load(Ordering::Relaxed);
+        loop {
+            if seq & 1 == 1 {
+                hint::spin_loop();
+                seq = self.seq.load(Ordering::Relaxed);
+                continue;
+            }
+            match self
+                .seq
+                .compare_exchange_weak(seq, seq + 1, Ordering::Acquire, Ordering::Relaxed)
+            {
+                Ok(_) => break,
+                Err(s) => seq = s,
+            }
+        }
+        // Keeps the data stores below from moving above the odd sequence number
+        fence(Ordering::Release);
+
+        self.store_data(value);
+
+        self.seq.store(seq + 2, Ordering::Release);
+    }
+
+    fn load_data(&self) -> MaybeUninit<T> {
+        let mut value = MaybeUninit::<T>::uninit();
+        let dst = value.as_mut_ptr() as *mut u8;
+        for (i, word) in self.data.iter().enumerate() {
+            let w = word.load(Ordering::Relaxed);
+            let len = WORD.min(mem::size_of::<T>() - i * WORD);
+            unsafe {
+                ptr::copy_nonoverlapping(&w as *const usize as *const u8, dst.add(i * WORD), len)
+            };
+        }
+        value
+    }
+
+    fn store_data(&self, value: T) {
+        let src = &value as *const T as *const u8;
+        for (i, word) in self.data.iter().enumerate() {
+            let mut w = 0usize;
+            let len = WORD.min(mem::size_of::<T>() - i * WORD);
+            unsafe {
+                ptr::copy_nonoverlapping(src.add(i * WORD), &mut w as *mut usize as *mut u8, len)
+            };
+            word.store(w, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(all(test, not(loom), not(pct)))]
+mod tests {
+    use super::*;
+    use crate::linearizability::models::{Register, RegisterOp};
+    use crate::linearizability::{assert_linearizable, programs};
+    use proptest::prelude::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn read_returns_written_value() {
+        // Ten bytes, so the last word is only partly used
+        let lock = SeqLock::new([1u16, 2, 3, 4, 5]);
+        assert_eq!(lock.read(), [1, 2, 3, 4, 5]);
+        lock.write([6, 7, 8, 9, 10]);
+        assert_eq!(lock.read(), [6, 7, 8, 9, 10]);
+    }
+
+    #[test]
+    fn readers_never_see_torn_values() {
+        let lock = Arc::new(SeqLock::new([0u64; 8]));
+
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let lock = Arc::clone(&lock);
+                thread::spawn(move || {
+                    for _ in 0..100_000 {
+                        let value = lock.read();
+                        assert!(value.iter().all(|&x| x == value[0]), "torn read {value:?}");
+                    }
+                })
+            })
+            .collect();
+
+        let writers: Vec<_> = (0..2)
+            .map(|_| {
+                let lock = Arc::clone(&lock);
+                thread::spawn(move || {
+                    for i in 0..10_000 {
+                        lock.write([i; 8]);
+                    }
+                })
+            })
+            .collect();
+
+        for handle in readers.into_iter().chain(writers) {
+            handle.join().unwrap();
+        }
+    }
+
+    proptest! {
+        // A torn read returns a pair that was never written
+        #[test]
+        fn linearizable(programs in programs(4, any::<RegisterOp<[u64; 2]>>())) {
+            let new = || SeqLock::new([0, 0]);
+            assert_linearizable::<Register<[u64; 2]>, _>(&programs, new, |lock, op| match *op {
+                RegisterOp::Write(value) => {
+                    lock.write(value);
+                    None
+                }
+                RegisterOp::Read => Some(lock.read()),
+            })?;
+        }
+    }
+}
@@ -0,0 +1,367 @@
+//! A stress-test harness for concurrent maps.
+//!
+//! Writer threads insert or update keys and reader threads read them, all drawing keys from a
+//! Zipf distribution so that they often pick the same few. Every write adds one to the key's
+//! value, so the harness can check two invariants:
+//!
+//! - a reader never sees a key's value go down, or appear as zero;
+//! - once the threads finish, each key's value is the number of writes to it.
+//!
+//! Lost updates break the second; torn or stale reads usually break the first.
+//!
+//! The seed fixes every thread's sequence of keys, but not how the threads interleave, so a
+//! failing configuration may need a few runs to fail again. [`check`] shrinks a failing
+//! configuration, keeping its seed, to the smallest one that still fails within a few runs.
+
+use crate::barrier::Barrier;
+use crate::hash_map::ConcurrentMap;
+use crate::once::OnceCell;
+use std::fmt;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Runs of a candidate configuration while shrinking, any of which may fail
+const SHRINK_ATTEMPTS: usize = 5;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct StressConfig {
+    pub writers: usize,
+    pub readers: usize,
+    /// Size of the key space
+    pub keys: usize,
+    pub ops_per_thread: usize,
+    /// Exponent of the Zipf distribution of keys. 0 picks keys uniformly; larger values favour
+    /// the first few more and more.
+    pub skew: f64,
+    pub seed: u64,
+}
+
+#[derive(Debug)]
+pub struct Stats {
+    pub reads: usize,
+    pub writes: usize,
+    pub elapsed: Duration,
+}
+
+/// A broken invariant, with a configuration that replays it.
+#[derive(Debug)]
+pub struct Violation {
+    pub config: StressConfig,
+    pub message: String,
+}
+
+/// Seeded SplitMix64, so that a thread's keys depend only on the seed and its index.
+pub(crate) struct Rng(u64);
+
+/// The cumulative distribution of a Zipf distribution over the key space.
+struct Zipf {
+    cdf: Vec<f64>,
+}
+
+impl Default for StressConfig {
+    fn default() -> Self {
+        Self {
+            writers: 4,
+            readers: 4,
+            keys: 64,
+            ops_per_thread: 10_000,
+            skew: 1.0,
+            seed: 0,
+        }
+    }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}; replay with {:?}", self.message, self.config)
+    }
+}
+
+impl std::error::Error for Violation {}
+
+impl Rng {
+    pub(crate) fn new(seed: u64, thread: usize) -> Self {
+        Self(seed ^ (thread as u64 + 1).wrapping_mul(0xd1b5_4a32_d192_ed03))
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a number in [0, 1).
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+impl Zipf {
+    fn new(keys: usize, skew: f64) -> Self {
+        assert!(keys > 0, "a stress test needs at least one key");
+        let mut total = 0.0;
+        let mut cdf: Vec<f64> = (1..=keys)
+            .map(|rank| {
+                total += 1.0 / (rank as f64).powf(skew);
+                total
+            })
+            .collect();
+        for p in &mut cdf {
+            *p /= total;
+        }
+        Self { cdf }
+    }
+
+    fn sample(&self, rng: &mut Rng) -> usize {
+        let u = rng.next_f64();
+        self.cdf
+            .partition_point(|&p| p <= u)
+            .min(self.cdf.len() - 1)
+    }
+}
+
+/// Runs `config` once against a new `M`.
+pub fn run<M: ConcurrentMap<usize, u64>>(config: &StressConfig) -> Result<Stats, Violation> {
+    let zipf = Zipf::new(config.keys, config.skew);
+    let map = M::default();
+    let barrier = Barrier::new((config.writers + config.readers) as u32);
+    // The first violation a thread finds, which also tells the others to stop
+    let failure = OnceCell::new();
+    let start = Instant::now();
+
+    let writes: Vec<Vec<u64>> = thread::scope(|s| {
+        for r in 0..config.readers {
+            let (map, zipf, barrier, failure) = (&map, &zipf, &barrier, &failure);
+            s.spawn(move || {
+                let mut rng = Rng::new(config.seed, config.writers + r);
+                let mut last = vec![0; config.keys];
+                barrier.wait();
+                for _ in 0..config.ops_per_thread {
+                    if failure.get().is_some() {
+                        return;
+                    }
+                    let key = zipf.sample(&mut rng);
+                    let Some(value) = map.get(&key) else {
+                        continue;
+                    };
+                    if value == 0 || value < last[key] {
+                        let _ = failure.set(format!(
+                            "reader {r} saw key {key} go from {} to {value}",
+                            last[key]
+                        ));
+                        return;
+                    }
+                    last[key] = value;
+                }
+            });
+        }
+        let writers: Vec<_> = (0..config.writers)
+            .map(|w| {
+                let (map, zipf, barrier, failure) = (&map, &zipf, &barrier, &failure);
+                s.spawn(move || {
+                    let mut rng = Rng::new(config.seed, w);
+                    let mut writes = vec![0; config.keys];
+                    barrier.wait();
+                    for _ in 0..config.ops_per_thread {
+                        if failure.get().is_some() {
+                            break;
+                        }
+                        let key = zipf.sample(&mut rng);
+                        map.upsert(key, 1, |v| v + 1);
+                        writes[key] += 1;
+                    }
+                    writes
+                })
+            })
+            .collect();
+        writers.into_iter().map(|w| w.join().unwrap()).collect()
+    });
+    let elapsed = start.elapsed();
+
+    let violation = |message| Violation {
+        config: config.clone(),
+        message,
+    };
+    if let Some(message) = failure.get().cloned() {
+        return Err(violation(message));
+    }
+    let mut written = 0;
+    for key in 0..config.keys {
+        let expected: u64 = writes.iter().map(|w| w[key]).sum();
+        let found = map.get(&key).unwrap_or(0);
+        if found != expected {
+            return Err(violation(format!(
+                "key {key} has value {found} after {expected} writes"
+            )));
+        }
+        written += usize::from(expected > 0);
+    }
+    if map.len() != written {
+        return Err(violation(format!(
+            "map has {} keys but {written} were written",
+            map.len()
+        )));
+    }
+    Ok(Stats {
+        reads: config.readers * config.ops_per_thread,
+        writes: config.writers * config.ops_per_thread,
+        elapsed,
+    })
+}
+
+/// Runs `config` once against a new `M`, and if that breaks an invariant, shrinks the
+/// configuration to the smallest one that still does.
+pub fn check<M: ConcurrentMap<usize, u64>>(config: &StressConfig) -> Result<Stats, Violation> {
+    run::<M>(config).map_err(shrink::<M>)
+}
+
+/// Tries smaller configurations with the same seed until none of them fails.
+fn shrink<M: ConcurrentMap<usize, u64>>(mut violation: Violation) -> Violation {
+    'smaller: loop {
+        let c = &violation.config;
+        let candidates = [
+            StressConfig {
+                ops_per_thread: c.ops_per_thread / 2,
+                ..c.clone()
+            },
+            StressConfig {
+                writers: c.writers - 1,
+                ..c.clone()
+            },
+            StressConfig {
+                readers: c.readers.saturating_sub(1),
+                ..c.clone()
+            },
+            StressConfig {
+                keys: c.keys / 2,
+                ..c.clone()
+            },
+        ];
+        for candidate in candidates {
+            if candidate == *c
+                || candidate.ops_per_thread == 0
+                || candidate.writers == 0
+                || candidate.keys == 0
+            {
+                continue;
+            }
+            for _ in 0..SHRINK_ATTEMPTS {
+                if let Err(smaller) = run::<M>(&candidate) {
+                    violation = smaller;
+                    continue 'smaller;
+                }
+            }
+        }
+        return violation;
+    }
+}
+
+#[cfg(all(test, not(loom), not(pct)))]
+mod tests {
+    use super::*;
+    use crate::hash_map::{lock_free, striped};
+    use dashmap::DashMap;
+    use std::collections::HashMap;
+    use std::sync::{Mutex, RwLock};
+
+    /// A map whose `upsert` reads and writes under separate locks, so that updates get lost.
+    #[derive(Default)]
+    struct RacyMap(Mutex<HashMap<usize, u64>>);
+
+    impl ConcurrentMap<usize, u64> for RacyMap {
+        fn get(&self, key: &usize) -> Option<u64> {
+            self.0.get(key)
+        }
+
+        fn insert(&self, key: usize, value: u64) -> Option<u64> {
+            self.0.insert(key, value)
+        }
+
+        fn remove(&self, key: &usize) -> Option<u64> {
+            ConcurrentMap::remove(&self.0, key)
+        }
+
+        fn upsert(&self, key: usize, value: u64, f: impl FnOnce(&u64) -> u64) {
+            let new = self.0.get(&key).map_or(value, |v| f(&v));
+            // Let another writer in between
+            thread::yield_now();
+            self.0.insert(key, new);
+        }
+
+        fn retain(&self, f: impl FnMut(&usize, &u64) -> bool) {
+            self.0.retain(f)
+        }
+
+        fn len(&self) -> usize {
+            self.0.len()
+        }
+
+        fn entries(&self) -> Vec<(usize, u64)> {
+            self.0.entries()
+        }
+    }
+
+    fn passes<M: ConcurrentMap<usize, u64>>() {
+        for (seed, skew) in [(1, 0.0), (2, 1.0), (3, 2.0)] {
+            let config = StressConfig {
+                skew,
+                seed,
+                ..StressConfig::default()
+            };
+            let stats = check::<M>(&config).unwrap_or_else(|v| panic!("{v}"));
+            assert_eq!(stats.writes, 40_000);
+        }
+    }
+
+    #[test]
+    fn dashmap_passes() {
+        passes::<DashMap<usize, u64>>();
+    }
+
+    #[test]
+    fn mutex_hash_map_passes() {
+        passes::<Mutex<HashMap<usize, u64>>>();
+    }
+
+    #[test]
+    fn rwlock_hash_map_passes() {
+        passes::<RwLock<HashMap<usize, u64>>>();
+    }
+
+    #[test]
+    fn striped_passes() {
+        passes::<striped::ConcurrentHashMap<usize, u64>>();
+    }
+
+    #[test]
+    fn lock_free_passes() {
+        passes::<lock_free::ConcurrentHashMap<usize, u64>>();
+    }
+
+    #[test]
+    fn reports_lost_updates_with_smaller_config() {
+        let config = StressConfig {
+            seed: 42,
+            ..StressConfig::default()
+        };
+        let violation = check::<RacyMap>(&config).unwrap_err();
+        assert_eq!(violation.config.seed, 42);
+        assert!(violation.config.writers >= 2);
+        assert!(violation.config.ops_per_thread < config.ops_per_thread);
+        assert!(violation.to_string().contains("replay with"));
+    }
+
+    #[test]
+    fn zipf_favours_first_keys() {
+        let zipf = Zipf::new(100, 1.5);
+        let mut rng = Rng::new(7, 0);
+        let mut counts = [0; 100];
+        for _ in 0..10_000 {
+            counts[zipf.sample(&mut rng)] += 1;
+        }
+        assert!(counts[0] > counts[1] && counts[1] > counts[10]);
+        assert!(counts[0] > 2_000);
+    }
+}
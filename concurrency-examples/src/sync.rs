@@ -0,0 +1,53 @@
+//! The types the primitives synchronise with: `loom`'s under `cfg(loom)`, so that loom can
+//! model-check them, [`crate::pct`]'s under `cfg(pct)`, so that its scheduler controls them, and
+//! `std`'s otherwise. Code written against this module runs under all three.
+//!
+//! Without the `std` feature only the atomics, from `portable-atomic`, and [`UnsafeCell`] are
+//! available.
+
+#[cfg(all(test, pct))]
+pub(crate) use crate::pct::model;
+#[cfg(pct)]
+pub(crate) use crate::pct::{atomic, cell::UnsafeCell, hint, thread, Mutex};
+#[cfg(all(test, loom))]
+pub(crate) use loom::model;
+#[cfg(loom)]
+pub(crate) use loom::{
+    cell::UnsafeCell,
+    hint,
+    sync::{atomic, Arc, Mutex},
+    thread,
+};
+#[cfg(not(feature = "std"))]
+pub(crate) use portable_atomic as atomic;
+#[cfg(pct)]
+pub(crate) use std::sync::Arc;
+#[cfg(all(feature = "std", not(loom), not(pct)))]
+pub(crate) use std::{
+    hint,
+    sync::{atomic, Arc, Mutex},
+    thread,
+};
+
+/// An `UnsafeCell` with loom's closure-based interface, so that loom can check accesses to it.
+#[cfg(not(any(loom, pct)))]
+pub(crate) struct UnsafeCell<T>(core::cell::UnsafeCell<T>);
+
+#[cfg(not(any(loom, pct)))]
+impl<T> UnsafeCell<T> {
+    pub(crate) const fn new(value: T) -> Self {
+        Self(core::cell::UnsafeCell::new(value))
+    }
+
+    pub(crate) fn with<R>(&self, f: impl FnOnce(*const T) -> R) -> R {
+        f(self.0.get())
+    }
+
+    pub(crate) fn with_mut<R>(&self, f: impl FnOnce(*mut T) -> R) -> R {
+        f(self.0.get())
+    }
+
+    pub(crate) fn into_inner(self) -> T {
+        self.0.into_inner()
+    }
+}
@@ -0,0 +1,408 @@
+//! An in-memory log with a ring buffer per thread, for looking at what threads do without
+//! changing it.
+//!
+//! `println!` takes stdout's lock and often makes a system call, so threads that print wait for
+//! each other, which can hide the very race being looked for. [`thread_log!`](crate::thread_log!)
+//! formats into the calling thread's own ring buffer instead. [`flush`] merges the entries of every
+//! thread by timestamp and prints them, which is meant for when the threads are done, e.g. at the
+//! end of a test or at shutdown.
+//!
+//! Each slot of a ring is a [`SeqLock`], so a flush that overlaps a write reads a whole entry, and
+//! a ring that fills up overwrites its oldest entries. A thread claims a ring on its first entry
+//! and gives it back when it exits, for the next new thread to reuse, so there are only as many
+//! rings as threads ever logged at once. The slots hold plain integers and bytes, as a `SeqLock`
+//! needs, and the ring keeps which thread wrote which of its entries.
+//!
+//! Only a thread's first entry takes a lock that others take too: reusing a ring records the new
+//! owner under a lock that a flush holds while it copies the ring's owners, so that entry may wait
+//! for the copy. Later entries take no lock.
+//!
+//! Without the `thread-log` feature, `thread_log!` still type-checks its arguments but does
+//! nothing, so calls can stay in hot paths.
+
+#[cfg(feature = "thread-log")]
+use crate::once::OnceCell;
+#[cfg(feature = "thread-log")]
+use crate::seqlock::{NoPadding, SeqLock};
+use std::fmt;
+#[cfg(feature = "thread-log")]
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+#[cfg(feature = "thread-log")]
+use std::sync::Mutex;
+use std::thread::ThreadId;
+use std::time::Duration;
+#[cfg(feature = "thread-log")]
+use std::time::Instant;
+
+/// Entries each ring holds before it overwrites the oldest
+#[cfg(feature = "thread-log")]
+const CAPACITY: usize = 1024;
+
+/// Bytes of text an entry holds; longer messages are cut short
+const TEXT_CAPACITY: usize = 96;
+
+/// Formats its arguments like `format!` into the current thread's log.
+#[macro_export]
+macro_rules! thread_log {
+    ($($arg:tt)*) => {
+        $crate::thread_log::write(format_args!($($arg)*))
+    };
+}
+
+#[derive(Clone, Copy)]
+pub struct Entry {
+    /// Time since the process's first entry
+    pub time: Duration,
+    pub thread: ThreadId,
+    len: u8,
+    text: [u8; TEXT_CAPACITY],
+}
+
+/// An entry as a ring stores it, without padding.
+#[cfg(feature = "thread-log")]
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct Slot {
+    /// Nanoseconds since the process's first entry
+    time: u64,
+    /// The entry's position among all the entries ever written to its ring
+    index: u64,
+    len: u64,
+    text: [u8; TEXT_CAPACITY],
+}
+
+/// A thread's ring buffer, in a list of every ring, which is never freed.
+#[cfg(feature = "thread-log")]
+struct Ring {
+    slots: Box<[SeqLock<Slot>]>,
+    /// The threads that have owned the ring, from the index of their first entry, back to the
+    /// owner of the oldest entry not yet flushed or overwritten
+    owners: Mutex<Vec<(usize, ThreadId)>>,
+    /// Entries written so far, only changed by the owning thread
+    head: AtomicUsize,
+    /// Entries flushed so far, or overwritten before a flush could read them
+    tail: AtomicUsize,
+    owned: AtomicBool,
+    next: *const Ring,
+}
+
+/// Gives the current thread's ring back when the thread exits.
+#[cfg(feature = "thread-log")]
+struct Owner(&'static Ring);
+
+/// Copies as much of the text as fits into an entry, cutting at a character boundary.
+#[cfg(feature = "thread-log")]
+struct Truncate<'a> {
+    text: &'a mut [u8; TEXT_CAPACITY],
+    len: usize,
+}
+
+// The fields are in `repr(C)` order and add up to the size, so there's no padding between them
+#[cfg(feature = "thread-log")]
+unsafe impl NoPadding for Slot {}
+
+#[cfg(feature = "thread-log")]
+const _: () = assert!(std::mem::size_of::<Slot>() == 3 * 8 + TEXT_CAPACITY);
+
+#[cfg(feature = "thread-log")]
+static RINGS: AtomicPtr<Ring> = AtomicPtr::new(std::ptr::null_mut());
+
+#[cfg(feature = "thread-log")]
+static START: OnceCell<Instant> = OnceCell::new();
+
+/// Serialises flushes, which are the only readers of `tail`
+#[cfg(feature = "thread-log")]
+static FLUSH: Mutex<()> = Mutex::new(());
+
+#[cfg(feature = "thread-log")]
+thread_local! {
+    static OWNER: Owner = Owner(Ring::claim());
+}
+
+impl Entry {
+    pub fn text(&self) -> &str {
+        // `Truncate` only cuts at character boundaries
+        std::str::from_utf8(&self.text[..self.len as usize]).unwrap()
+    }
+}
+
+impl fmt::Debug for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Entry")
+            .field("time", &self.time)
+            .field("thread", &self.thread)
+            .field("text", &self.text())
+            .finish()
+    }
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:>12.6?} {:?}: {}", self.time, self.thread, self.text())
+    }
+}
+
+#[cfg(feature = "thread-log")]
+impl fmt::Write for Truncate<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut end = s.len().min(TEXT_CAPACITY - self.len);
+        while !s.is_char_boundary(end) {
+            end -= 1;
+        }
+        self.text[self.len..self.len + end].copy_from_slice(&s.as_bytes()[..end]);
+        self.len += end;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "thread-log")]
+impl Ring {
+    /// Takes a ring no thread owns, or adds a new one.
+    fn claim() -> &'static Ring {
+        let mut ring = RINGS.load(Ordering::Acquire) as *const Ring;
+        while let Some(r) = unsafe { ring.as_ref() } {
+            if r.owned
+                .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+            {
+                let head = r.head.load(Ordering::Relaxed);
+                let mut owners = r.owners.lock().unwrap_or_else(|e| e.into_inner());
+                forget_owners(&mut owners, head.saturating_sub(CAPACITY));
+                owners.push((head, std::thread::current().id()));
+                return r;
+            }
+            ring = r.next;
+        }
+
+        let empty = Slot {
+            time: 0,
+            index: 0,
+            len: 0,
+            text: [0; TEXT_CAPACITY],
+        };
+        let ring = Box::leak(Box::new(Ring {
+            slots: (0..CAPACITY).map(|_| SeqLock::new(empty)).collect(),
+            owners: Mutex::new(vec![(0, std::thread::current().id())]),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            owned: AtomicBool::new(true),
+            next: std::ptr::null(),
+        }));
+        let mut head = RINGS.load(Ordering::Relaxed);
+        loop {
+            ring.next = head;
+            match RINGS.compare_exchange_weak(head, ring, Ordering::Release, Ordering::Relaxed) {
+                Ok(_) => return ring,
+                Err(h) => head = h,
+            }
+        }
+    }
+
+    fn push(&self, mut slot: Slot) {
+        let index = self.head.load(Ordering::Relaxed);
+        slot.index = index as u64;
+        self.slots[index % CAPACITY].write(slot);
+        self.head.store(index + 1, Ordering::Release);
+    }
+
+    /// Adds the entries written since the last flush to `entries`, and returns how many were
+    /// overwritten before they could be.
+    fn drain(&self, entries: &mut Vec<Entry>) -> usize {
+        let head = self.head.load(Ordering::Acquire);
+        let tail = self.tail.load(Ordering::Relaxed);
+        let start = tail.max(head.saturating_sub(CAPACITY));
+        let mut lost = start - tail;
+        // A thread that claims the ring adds itself before its first entry, so the owners of the
+        // entries before `head` are all here. Copied, so that a new owner only waits for the copy.
+        let owners = self
+            .owners
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone();
+        for index in start..head {
+            let slot = self.slots[index % CAPACITY].read();
+            // The owner may have lapped the flush since it read `head`
+            if slot.index != index as u64 {
+                lost += 1;
+                continue;
+            }
+            let &(_, thread) = owners
+                .iter()
+                .rev()
+                .find(|&&(first, _)| first <= index)
+                .unwrap();
+            entries.push(Entry {
+                time: Duration::from_nanos(slot.time),
+                thread,
+                len: slot.len as u8,
+                text: slot.text,
+            });
+        }
+        let mut owners = self.owners.lock().unwrap_or_else(|e| e.into_inner());
+        forget_owners(&mut owners, head);
+        self.tail.store(head, Ordering::Relaxed);
+        lost
+    }
+}
+
+/// Forgets the owners of a ring that only wrote entries before `index`.
+#[cfg(feature = "thread-log")]
+fn forget_owners(owners: &mut Vec<(usize, ThreadId)>, index: usize) {
+    let keep = owners
+        .iter()
+        .rposition(|&(first, _)| first <= index)
+        .unwrap_or(0);
+    owners.drain(..keep);
+}
+
+#[cfg(feature = "thread-log")]
+impl Drop for Owner {
+    fn drop(&mut self) {
+        self.0.owned.store(false, Ordering::Release);
+    }
+}
+
+/// Adds an entry to the current thread's log; see [`thread_log!`](crate::thread_log!).
+#[cfg(feature = "thread-log")]
+pub fn write(args: fmt::Arguments<'_>) {
+    let time = START.get_or_init(Instant::now).elapsed();
+    let mut slot = Slot {
+        time: time.as_nanos() as u64,
+        index: 0,
+        len: 0,
+        text: [0; TEXT_CAPACITY],
+    };
+    let mut text = Truncate {
+        text: &mut slot.text,
+        len: 0,
+    };
+    let _ = fmt::write(&mut text, args);
+    slot.len = text.len as u64;
+    // A thread that is exiting has given its ring back already, and its entry is lost
+    let _ = OWNER.try_with(|owner| owner.0.push(slot));
+}
+
+#[cfg(not(feature = "thread-log"))]
+#[inline(always)]
+pub fn write(_args: fmt::Arguments<'_>) {}
+
+/// Takes the entries of every thread written since the last call, ordered by time. Also returns
+/// how many entries were overwritten before they could be taken.
+#[cfg(feature = "thread-log")]
+pub fn drain() -> (Vec<Entry>, usize) {
+    let _flushing = FLUSH.lock().unwrap_or_else(|e| e.into_inner());
+    let mut entries = Vec::new();
+    let mut lost = 0;
+    let mut ring = RINGS.load(Ordering::Acquire) as *const Ring;
+    while let Some(r) = unsafe { ring.as_ref() } {
+        lost += r.drain(&mut entries);
+        ring = r.next;
+    }
+    entries.sort_by_key(|entry| entry.time);
+    (entries, lost)
+}
+
+#[cfg(not(feature = "thread-log"))]
+pub fn drain() -> (Vec<Entry>, usize) {
+    (Vec::new(), 0)
+}
+
+/// Prints the entries of every thread written since the last call, ordered by time.
+pub fn flush() {
+    let (entries, lost) = drain();
+    if lost > 0 {
+        eprintln!("{lost} entries were overwritten before a flush");
+    }
+    for entry in entries {
+        eprintln!("{entry}");
+    }
+}
+
+#[cfg(all(test, feature = "thread-log", not(pct)))]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    // One test, since a drain takes the entries of every test's threads
+    #[test]
+    fn drain_merges_threads_and_counts_overwrites() {
+        let threads: Vec<_> = (0..4)
+            .map(|t| {
+                thread::spawn(move || {
+                    for i in 0..100 {
+                        crate::thread_log!("{t} {i}");
+                    }
+                    thread::current().id()
+                })
+            })
+            .collect();
+        let ids: Vec<_> = threads.into_iter().map(|t| t.join().unwrap()).collect();
+
+        let (entries, _) = drain();
+        let ours: Vec<_> = entries.iter().filter(|e| ids.contains(&e.thread)).collect();
+        assert_eq!(ours.len(), 400);
+        assert!(entries.windows(2).all(|w| w[0].time <= w[1].time));
+        for (t, id) in ids.iter().enumerate() {
+            let texts: Vec<_> = ours
+                .iter()
+                .filter(|e| e.thread == *id)
+                .map(|e| e.text())
+                .collect();
+            let expected: Vec<_> = (0..100).map(|i| format!("{t} {i}")).collect();
+            assert_eq!(texts, expected);
+        }
+
+        let id = thread::spawn(|| {
+            for i in 0..CAPACITY + 10 {
+                crate::thread_log!("{i}");
+            }
+            thread::current().id()
+        })
+        .join()
+        .unwrap();
+        let (entries, lost) = drain();
+        let texts: Vec<_> = entries
+            .iter()
+            .filter(|e| e.thread == id)
+            .map(|e| e.text())
+            .collect();
+        assert_eq!(texts.len(), CAPACITY);
+        assert_eq!(texts[0], "10");
+        assert!(lost >= 10);
+
+        // The second thread reuses the first one's ring before a drain
+        let ids: Vec<_> = (0..2)
+            .map(|t| {
+                thread::spawn(move || {
+                    crate::thread_log!("{t}");
+                    thread::current().id()
+                })
+                .join()
+                .unwrap()
+            })
+            .collect();
+        let (entries, _) = drain();
+        for (t, id) in ids.iter().enumerate() {
+            let texts: Vec<_> = entries
+                .iter()
+                .filter(|e| e.thread == *id)
+                .map(|e| e.text())
+                .collect();
+            assert_eq!(texts, [t.to_string()]);
+        }
+    }
+
+    #[test]
+    fn truncates_at_char_boundary() {
+        let mut text = [0; TEXT_CAPACITY];
+        let mut truncate = Truncate {
+            text: &mut text,
+            len: 0,
+        };
+        let long = "é".repeat(TEXT_CAPACITY);
+        fmt::write(&mut truncate, format_args!("a{long}")).unwrap();
+        // The last `é` would end one byte past the capacity
+        assert_eq!(truncate.len, TEXT_CAPACITY - 1);
+        assert!(std::str::from_utf8(&text[..TEXT_CAPACITY - 1]).is_ok());
+    }
+}
@@ -0,0 +1,735 @@
+//! Record and replay of thread interleavings, for bugs that go away when you look at them.
+//!
+//! A `println!` to see what the threads do takes stdout's lock, which orders the threads and can
+//! hide the bug. The types here log into a buffer per thread instead: every atomic access, lock,
+//! unlock and spawn, with the thread's id and a logical timestamp. [`record`] merges the buffers
+//! into a [`Timeline`] once the body returns, and prints it if the body panicked.
+//!
+//! [`replay`] runs a body again under a scheduler that lets a thread take its next step only when
+//! the timeline says it's that thread's turn, which forces the recorded interleaving. It panics if
+//! a step doesn't match the recording, e.g. if a load reads a different value.
+//!
+//! Only the body's thread and the threads it spawns through [`thread::spawn`] or
+//! [`thread::spawn_scoped`] are traced, and they must be joined by the time the body returns.
+//! Elsewhere the types behave like the [`crate::sync`] types they wrap. Steps are numbered under a
+//! lock, which also makes traced operations sequentially consistent with each other: a timeline is
+//! an interleaving, never a weak-memory reordering. Thread ids follow spawn order, and atomic and
+//! mutex ids the order they are first touched in, so they match between a recording and its
+//! replay.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex as StdMutex, MutexGuard as StdMutexGuard};
+use std::time::Duration;
+
+/// How long a replayed thread waits for its turn before the replay gives up
+const REPLAY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// One step of a traced thread.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Event {
+    /// The logical timestamp: the step's position in the timeline
+    pub time: usize,
+    pub thread: usize,
+    pub op: Op,
+    /// The value the step read, if any, or `Err` with the value a failed compare-exchange saw
+    pub result: Result<u64, u64>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Op {
+    /// Spawned the thread whose id is the result.
+    Spawn,
+    Load {
+        atomic: usize,
+        order: atomic::Ordering,
+    },
+    Store {
+        atomic: usize,
+        value: u64,
+        order: atomic::Ordering,
+    },
+    Swap {
+        atomic: usize,
+        value: u64,
+        order: atomic::Ordering,
+    },
+    CompareExchange {
+        atomic: usize,
+        current: u64,
+        new: u64,
+        success: atomic::Ordering,
+        failure: atomic::Ordering,
+        weak: bool,
+    },
+    FetchAdd {
+        atomic: usize,
+        value: u64,
+        order: atomic::Ordering,
+    },
+    FetchSub {
+        atomic: usize,
+        value: u64,
+        order: atomic::Ordering,
+    },
+    Lock {
+        mutex: usize,
+    },
+    Unlock {
+        mutex: usize,
+    },
+}
+
+/// The steps of a recorded run, in the order they happened.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Timeline {
+    pub events: Vec<Event>,
+}
+
+struct Session {
+    /// The steps to follow, when replaying
+    script: Option<Vec<Event>>,
+    state: StdMutex<State>,
+    /// Notified after every step of a replay
+    turn: Condvar,
+}
+
+#[derive(Default)]
+struct State {
+    /// The next step's timestamp
+    time: usize,
+    threads: usize,
+    /// Ids of the atomics and mutexes touched so far, by address
+    objects: HashMap<usize, usize>,
+    /// The logs of the threads that have finished
+    events: Vec<Event>,
+    /// Why the replay went wrong
+    diverged: Option<String>,
+}
+
+/// A traced thread's session, id and log.
+struct Local {
+    session: Arc<Session>,
+    thread: usize,
+    log: Vec<Event>,
+}
+
+/// Hands a traced thread's log over to its session when the thread finishes, even by panicking.
+struct Exit;
+
+thread_local! {
+    static LOCAL: RefCell<Option<Local>> = const { RefCell::new(None) };
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:>6} thread {}: ", self.time, self.thread)?;
+        match self.op {
+            Op::Spawn => return write!(f, "spawn thread {}", self.result.unwrap_or_default()),
+            Op::Load { atomic, order } => write!(f, "atomic {atomic}.load({order:?})"),
+            Op::Store {
+                atomic,
+                value,
+                order,
+            } => return write!(f, "atomic {atomic}.store({value}, {order:?})"),
+            Op::Swap {
+                atomic,
+                value,
+                order,
+            } => write!(f, "atomic {atomic}.swap({value}, {order:?})"),
+            Op::CompareExchange {
+                atomic,
+                current,
+                new,
+                success,
+                failure,
+                weak,
+            } => {
+                let weak = if weak { "_weak" } else { "" };
+                write!(
+                    f,
+                    "atomic {atomic}.compare_exchange{weak}({current}, {new}, {success:?}, {failure:?})"
+                )
+            }
+            Op::FetchAdd {
+                atomic,
+                value,
+                order,
+            } => write!(f, "atomic {atomic}.fetch_add({value}, {order:?})"),
+            Op::FetchSub {
+                atomic,
+                value,
+                order,
+            } => write!(f, "atomic {atomic}.fetch_sub({value}, {order:?})"),
+            Op::Lock { mutex } => return write!(f, "mutex {mutex}.lock()"),
+            Op::Unlock { mutex } => return write!(f, "mutex {mutex}.unlock()"),
+        }?;
+        match self.result {
+            Ok(value) => write!(f, " -> {value}"),
+            Err(value) => write!(f, " -> Err({value})"),
+        }
+    }
+}
+
+impl fmt::Display for Timeline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for event in &self.events {
+            writeln!(f, "{event}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Session {
+    fn lock(&self) -> StdMutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Locks the state for a step of `thread`, once it is that thread's turn when replaying.
+    /// Fails if the replay has gone wrong.
+    fn begin(&self, thread: usize) -> Result<StdMutexGuard<'_, State>, String> {
+        let mut state = self.lock();
+        let Some(script) = &self.script else {
+            return Ok(state);
+        };
+        loop {
+            if let Some(diverged) = &state.diverged {
+                return Err(diverged.clone());
+            }
+            let Some(expected) = script.get(state.time) else {
+                let diverged = format!("thread {thread} took a step after the recording ended");
+                return Err(self.diverge(&mut state, diverged));
+            };
+            if expected.thread == thread {
+                return Ok(state);
+            }
+            let (s, timeout) = self
+                .turn
+                .wait_timeout(state, REPLAY_TIMEOUT)
+                .unwrap_or_else(|e| e.into_inner());
+            state = s;
+            if timeout.timed_out() && state.diverged.is_none() {
+                let diverged = format!(
+                    "thread {thread} waited {REPLAY_TIMEOUT:?} for step `{}`",
+                    script[state.time]
+                );
+                return Err(self.diverge(&mut state, diverged));
+            }
+        }
+    }
+
+    /// Ends a step, checking it against the recording when replaying.
+    fn end(&self, state: &mut State, event: &Event) {
+        state.time += 1;
+        if let Some(script) = &self.script {
+            let expected = script[event.time];
+            if (expected.thread, expected.op, expected.result)
+                != (event.thread, event.op, event.result)
+            {
+                let diverged = format!("recorded `{expected}`, replayed `{event}`");
+                let diverged = self.diverge(state, diverged);
+                if !std::thread::panicking() {
+                    panic!("{diverged}");
+                }
+            }
+            self.turn.notify_all();
+        }
+    }
+
+    fn diverge(&self, state: &mut State, why: String) -> String {
+        let diverged = state
+            .diverged
+            .get_or_insert(format!("replay diverged: {why}"));
+        self.turn.notify_all();
+        diverged.clone()
+    }
+}
+
+impl State {
+    fn id(&mut self, object: *const ()) -> usize {
+        let ids = self.objects.len();
+        *self.objects.entry(object as usize).or_insert(ids)
+    }
+}
+
+impl Drop for Exit {
+    fn drop(&mut self) {
+        if let Some(local) = LOCAL.take() {
+            local.session.lock().events.extend(local.log);
+        }
+    }
+}
+
+/// Runs `f` as a step of the current thread, if it is traced, and logs it. `f` gets the id of
+/// `object`, or a new thread id if there is none, and returns what the step returns, what it did,
+/// and what it read. Steps that return `None` aren't logged.
+fn try_step<R>(
+    object: Option<*const ()>,
+    f: impl FnOnce(usize) -> Option<(R, Op, Result<u64, u64>)>,
+) -> Option<R> {
+    // `f` runs outside the borrow, since under loom every thread shares the thread-local
+    let session = LOCAL.with_borrow(|local| {
+        local
+            .as_ref()
+            .map(|local| (Arc::clone(&local.session), local.thread))
+    });
+    let Some((session, thread)) = session else {
+        return f(0).map(|(r, ..)| r);
+    };
+    let mut state = match session.begin(thread) {
+        Ok(state) => state,
+        // A thread unwinding from a failed replay still unlocks its mutexes on the way out
+        Err(_) if std::thread::panicking() => return f(0).map(|(r, ..)| r),
+        Err(diverged) => panic!("{diverged}"),
+    };
+    let id = match object {
+        Some(object) => state.id(object),
+        None => {
+            state.threads += 1;
+            state.threads - 1
+        }
+    };
+    let (r, op, result) = f(id)?;
+    let event = Event {
+        time: state.time,
+        thread,
+        op,
+        result,
+    };
+    session.end(&mut state, &event);
+    drop(state);
+    LOCAL.with_borrow_mut(|local| local.as_mut().unwrap().log.push(event));
+    Some(r)
+}
+
+fn step<R>(object: Option<*const ()>, f: impl FnOnce(usize) -> (R, Op, Result<u64, u64>)) -> R {
+    try_step(object, |id| Some(f(id))).unwrap()
+}
+
+fn traced() -> bool {
+    LOCAL.with_borrow(Option::is_some)
+}
+
+/// Runs `f` on this thread as thread 0 of a new session. Returns what `f` returns, the session's
+/// timeline, and why the replay went wrong, if it did.
+fn run<R>(
+    script: Option<Vec<Event>>,
+    f: impl FnOnce() -> R,
+) -> (std::thread::Result<R>, Timeline, Option<String>) {
+    assert!(!traced(), "a traced thread can't start another trace");
+    let session = Arc::new(Session {
+        script,
+        state: StdMutex::new(State {
+            threads: 1,
+            ..State::default()
+        }),
+        turn: Condvar::new(),
+    });
+    LOCAL.set(Some(Local {
+        session: Arc::clone(&session),
+        thread: 0,
+        log: Vec::new(),
+    }));
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        let _exit = Exit;
+        f()
+    }));
+
+    let mut state = session.lock();
+    let mut events = std::mem::take(&mut state.events);
+    events.sort_by_key(|event| event.time);
+    (result, Timeline { events }, state.diverged.take())
+}
+
+/// Runs `f`, recording the steps of this thread and of the threads `f` spawns. Returns what `f`
+/// returns, or its panic, with the timeline, which is also printed to stderr if `f` panicked.
+pub fn record<R>(f: impl FnOnce() -> R) -> (std::thread::Result<R>, Timeline) {
+    let (result, timeline, _) = run(None, f);
+    if result.is_err() {
+        eprintln!("recorded interleaving:\n{timeline}");
+    }
+    (result, timeline)
+}
+
+/// Runs `f` with its threads taking their steps in the order of `timeline`, and returns what `f`
+/// returns, or its panic.
+///
+/// # Panics
+///
+/// If the threads' steps don't match the timeline.
+pub fn replay<R>(timeline: &Timeline, f: impl FnOnce() -> R) -> std::thread::Result<R> {
+    let script = timeline.events.clone();
+    let steps = script.len();
+    let (result, replayed, diverged) = run(Some(script), f);
+    // A panic in a traced thread is usually the divergence, which is the more useful message
+    if let Some(diverged) = diverged {
+        panic!("{diverged}");
+    }
+    if result.is_ok() && replayed.events.len() != steps {
+        panic!(
+            "replay diverged: ran {} of the {steps} recorded steps",
+            replayed.events.len()
+        );
+    }
+    result
+}
+
+pub mod atomic {
+    use super::{step, Op};
+    pub use crate::sync::atomic::Ordering;
+
+    macro_rules! atomic {
+        ($name:ident, $t:ty $(, $int:ident)?) => {
+            /// [`crate::sync`]'s atomic, which logs every access in a traced thread.
+            #[derive(Debug, Default)]
+            pub struct $name(crate::sync::atomic::$name);
+
+            impl $name {
+                pub fn new(value: $t) -> Self {
+                    Self(crate::sync::atomic::$name::new(value))
+                }
+
+                fn addr(&self) -> Option<*const ()> {
+                    Some(self as *const Self as *const ())
+                }
+
+                pub fn load(&self, order: Ordering) -> $t {
+                    step(self.addr(), |atomic| {
+                        let value = self.0.load(order);
+                        (value, Op::Load { atomic, order }, Ok(value as u64))
+                    })
+                }
+
+                pub fn store(&self, value: $t, order: Ordering) {
+                    step(self.addr(), |atomic| {
+                        self.0.store(value, order);
+                        let op = Op::Store {
+                            atomic,
+                            value: value as u64,
+                            order,
+                        };
+                        ((), op, Ok(0))
+                    })
+                }
+
+                pub fn swap(&self, value: $t, order: Ordering) -> $t {
+                    step(self.addr(), |atomic| {
+                        let old = self.0.swap(value, order);
+                        let op = Op::Swap {
+                            atomic,
+                            value: value as u64,
+                            order,
+                        };
+                        (old, op, Ok(old as u64))
+                    })
+                }
+
+                pub fn compare_exchange(
+                    &self,
+                    current: $t,
+                    new: $t,
+                    success: Ordering,
+                    failure: Ordering,
+                ) -> Result<$t, $t> {
+                    self.cmpxchg(current, new, success, failure, false)
+                }
+
+                pub fn compare_exchange_weak(
+                    &self,
+                    current: $t,
+                    new: $t,
+                    success: Ordering,
+                    failure: Ordering,
+                ) -> Result<$t, $t> {
+                    self.cmpxchg(current, new, success, failure, true)
+                }
+
+                fn cmpxchg(
+                    &self,
+                    current: $t,
+                    new: $t,
+                    success: Ordering,
+                    failure: Ordering,
+                    weak: bool,
+                ) -> Result<$t, $t> {
+                    step(self.addr(), |atomic| {
+                        let result = if weak {
+                            self.0.compare_exchange_weak(current, new, success, failure)
+                        } else {
+                            self.0.compare_exchange(current, new, success, failure)
+                        };
+                        let op = Op::CompareExchange {
+                            atomic,
+                            current: current as u64,
+                            new: new as u64,
+                            success,
+                            failure,
+                            weak,
+                        };
+                        let read = result.map(|v| v as u64).map_err(|v| v as u64);
+                        (result, op, read)
+                    })
+                }
+            }
+
+            $(atomic!(@$int $name, $t);)?
+        };
+        (@int $name:ident, $t:ty) => {
+            impl $name {
+                pub fn fetch_add(&self, value: $t, order: Ordering) -> $t {
+                    step(self.addr(), |atomic| {
+                        let old = self.0.fetch_add(value, order);
+                        let op = Op::FetchAdd {
+                            atomic,
+                            value: value as u64,
+                            order,
+                        };
+                        (old, op, Ok(old as u64))
+                    })
+                }
+
+                pub fn fetch_sub(&self, value: $t, order: Ordering) -> $t {
+                    step(self.addr(), |atomic| {
+                        let old = self.0.fetch_sub(value, order);
+                        let op = Op::FetchSub {
+                            atomic,
+                            value: value as u64,
+                            order,
+                        };
+                        (old, op, Ok(old as u64))
+                    })
+                }
+            }
+        };
+    }
+
+    atomic!(AtomicBool, bool);
+    atomic!(AtomicU32, u32, int);
+    atomic!(AtomicUsize, usize, int);
+}
+
+pub mod thread {
+    use super::{step, Exit, Local, Op, LOCAL};
+    use crate::sync::thread::{self as sync_thread, JoinHandle};
+    use std::sync::Arc;
+
+    /// Spawns a thread, which is traced if the current one is.
+    pub fn spawn<F, T>(f: F) -> JoinHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        sync_thread::spawn(traced(f))
+    }
+
+    /// Spawns a thread in `scope`, which is traced if the current one is.
+    #[cfg(not(any(loom, pct)))]
+    pub fn spawn_scoped<'scope, F, T>(
+        scope: &'scope std::thread::Scope<'scope, '_>,
+        f: F,
+    ) -> std::thread::ScopedJoinHandle<'scope, T>
+    where
+        F: FnOnce() -> T + Send + 'scope,
+        T: Send + 'scope,
+    {
+        scope.spawn(traced(f))
+    }
+
+    /// Logs the spawn of a thread that will run `f`, and makes the thread traced, if the current
+    /// one is.
+    fn traced<F, T>(f: F) -> impl FnOnce() -> T + Send
+    where
+        F: FnOnce() -> T + Send,
+    {
+        let session = LOCAL.with_borrow(|local| local.as_ref().map(|l| Arc::clone(&l.session)));
+        let child = session.map(|session| {
+            let thread = step(None, |child| (child, Op::Spawn, Ok(child as u64)));
+            Local {
+                session,
+                thread,
+                log: Vec::new(),
+            }
+        });
+        move || {
+            let _exit = child.map(|child| {
+                LOCAL.set(Some(child));
+                Exit
+            });
+            f()
+        }
+    }
+}
+
+/// `std`'s mutex, which logs every lock and unlock in a traced thread.
+#[derive(Debug, Default)]
+pub struct Mutex<T>(StdMutex<T>);
+
+pub struct MutexGuard<'a, T> {
+    mutex: &'a Mutex<T>,
+    /// Taken when the guard unlocks
+    inner: Option<StdMutexGuard<'a, T>>,
+}
+
+impl<T> Mutex<T> {
+    pub const fn new(value: T) -> Self {
+        Self(StdMutex::new(value))
+    }
+
+    fn addr(&self) -> Option<*const ()> {
+        Some(self as *const Self as *const ())
+    }
+
+    pub fn lock(&self) -> std::sync::LockResult<MutexGuard<'_, T>> {
+        use std::sync::{PoisonError, TryLockError};
+
+        let guard = |inner| MutexGuard {
+            mutex: self,
+            inner: Some(inner),
+        };
+        if !traced() {
+            return self
+                .0
+                .lock()
+                .map(guard)
+                .map_err(|e| PoisonError::new(guard(e.into_inner())));
+        }
+        // Only a successful attempt is a step, so a replayed thread tries exactly when it took the
+        // lock in the recording
+        loop {
+            let locked = try_step(self.addr(), |mutex| {
+                let result = match self.0.try_lock() {
+                    Ok(inner) => Ok(guard(inner)),
+                    Err(TryLockError::Poisoned(e)) => Err(PoisonError::new(guard(e.into_inner()))),
+                    Err(TryLockError::WouldBlock) => return None,
+                };
+                Some((result, Op::Lock { mutex }, Ok(0)))
+            });
+            match locked {
+                Some(result) => return result,
+                None => std::thread::yield_now(),
+            }
+        }
+    }
+}
+
+impl<T> std::ops::Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.inner.as_ref().unwrap()
+    }
+}
+
+impl<T> std::ops::DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.inner.as_mut().unwrap()
+    }
+}
+
+impl<T> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        let inner = self.inner.take();
+        step(self.mutex.addr(), |mutex| {
+            drop(inner);
+            ((), Op::Unlock { mutex }, Ok(0))
+        })
+    }
+}
+
+#[cfg(all(test, not(loom), not(pct)))]
+mod tests {
+    use super::atomic::{AtomicUsize, Ordering};
+    use super::*;
+    use crate::memory_ordering::{acqrel_relaxed_ordering, relaxed_ordering};
+    use std::collections::BTreeMap;
+
+    /// Two threads increment a counter without a lock, yielding in between so that one of them
+    /// often loses its update.
+    fn racy_increments() -> usize {
+        let count = Arc::new(AtomicUsize::new(0));
+        let threads: Vec<_> = (0..2)
+            .map(|_| {
+                let count = Arc::clone(&count);
+                thread::spawn(move || {
+                    let c = count.load(Ordering::Relaxed);
+                    std::thread::yield_now();
+                    count.store(c + 1, Ordering::Relaxed);
+                })
+            })
+            .collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+        count.load(Ordering::Relaxed)
+    }
+
+    #[test]
+    fn records_lock_order() {
+        let (result, timeline) = record(crate::shared_mem_mutex);
+        assert_eq!(result.unwrap(), 10);
+
+        let spawns = timeline.events.iter().filter(|e| e.op == Op::Spawn);
+        assert_eq!(spawns.count(), 10);
+        let locking: Vec<_> = timeline
+            .events
+            .iter()
+            .filter_map(|e| match e.op {
+                Op::Lock { .. } => Some(true),
+                Op::Unlock { .. } => Some(false),
+                _ => None,
+            })
+            .collect();
+        // Every thread and then the caller take the lock once, one after another
+        assert_eq!(locking.len(), 22);
+        assert!(locking.chunks(2).all(|pair| pair == [true, false]));
+        assert!(timeline.events.iter().enumerate().all(|(i, e)| e.time == i));
+    }
+
+    #[test]
+    fn replay_repeats_each_outcome() {
+        fn check<T: Ord + Copy + std::fmt::Debug>(f: fn() -> T) {
+            let mut timelines = BTreeMap::new();
+            for _ in 0..100 {
+                let (outcome, timeline) = record(f);
+                timelines.insert(outcome.unwrap(), timeline);
+            }
+            for (outcome, timeline) in &timelines {
+                for _ in 0..10 {
+                    assert_eq!(replay(timeline, f).unwrap(), *outcome);
+                }
+            }
+        }
+
+        check(relaxed_ordering);
+        check(acqrel_relaxed_ordering);
+    }
+
+    #[test]
+    fn replays_failing_run() {
+        let failing = (0..1_000)
+            .map(|_| record(|| assert_eq!(racy_increments(), 2)))
+            .find(|(result, _)| result.is_err())
+            .map(|(_, timeline)| timeline)
+            .expect("no run lost an update");
+        for _ in 0..10 {
+            assert!(replay(&failing, || assert_eq!(racy_increments(), 2)).is_err());
+        }
+    }
+
+    #[test]
+    fn replay_detects_divergence() {
+        let store = |value| {
+            let a = AtomicUsize::new(0);
+            thread::spawn(move || a.store(value, Ordering::Relaxed))
+                .join()
+                .unwrap();
+        };
+        let (_, timeline) = record(|| store(1));
+        replay(&timeline, || store(1)).unwrap();
+        let diverged = panic::catch_unwind(|| replay(&timeline, || store(2))).unwrap_err();
+        let message = diverged.downcast_ref::<String>().unwrap();
+        assert!(message.contains("replay diverged"), "{message}");
+    }
+}
@@ -0,0 +1,215 @@
+//! Treiber's lock-free stack.
+//!
+//! `push` and `pop` swap the head pointer with `compare_exchange`. `pop` reads `next` from the head
+//! node before it swaps, so the node must not be freed while another thread is between its load of
+//! the head and that read. The [`Reclaim`] scheme decides when a popped node can be freed.
+
+use crate::reclaim::{Epoch, Reclaim};
+use crate::sync::{
+    atomic::{AtomicPtr, Ordering},
+    UnsafeCell,
+};
+use std::marker::PhantomData;
+use std::mem::ManuallyDrop;
+use std::ptr;
+
+struct Node<T> {
+    value: ManuallyDrop<T>,
+    next: UnsafeCell<*mut Node<T>>,
+}
+
+unsafe fn free_node<T>(node: *mut ()) {
+    let node = node as *mut Node<T>;
+    // Loom reports this write if a thread that still holds the node reads `next` concurrently
+    (*node).next.with_mut(|_| {});
+    // Under loom the node is leaked so that such a read hits valid memory
+    #[cfg(not(loom))]
+    drop(Box::from_raw(node));
+}
+
+pub struct TreiberStack<T, R: Reclaim = Epoch> {
+    head: AtomicPtr<Node<T>>,
+    reclaim: R,
+    _marker: PhantomData<T>,
+}
+
+unsafe impl<T: Send, R: Reclaim> Send for TreiberStack<T, R> {}
+unsafe impl<T: Send, R: Reclaim> Sync for TreiberStack<T, R> {}
+
+impl<T, R: Reclaim> TreiberStack<T, R> {
+    pub fn new() -> Self {
+        Self {
+            head: AtomicPtr::new(ptr::null_mut()),
+            reclaim: R::default(),
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn push(&self, value: T) {
+        let node = Box::into_raw(Box::new(Node {
+            value: ManuallyDrop::new(value),
+            next: UnsafeCell::new(ptr::null_mut()),
+        }));
+
+        let mut head = self.head.load(Ordering::Relaxed);
+        loop {
+            // The node isn't shared until the swap succeeds
+            unsafe { (*node).next.with_mut(|next| *next = head) };
+            match self
+                .head
+                .compare_exchange(head, node, Ordering::Release, Ordering::Relaxed)
+            {
+                Ok(_) => return,
+                Err(h) => head = h,
+            }
+        }
+    }
+
+    pub fn pop(&self) -> Option<T> {
+        let guard = self.reclaim.enter();
+        loop {
+            let head = self.reclaim.protect(&guard, &self.head);
+            if head.is_null() {
+                return None;
+            }
+
+            let next = unsafe { (*head).next.with(|next| *next) };
+            // Release so that the read of `next` happens before whoever later frees the node
+            // loads the new head
+            if self
+                .head
+                .compare_exchange(head, next, Ordering::AcqRel, Ordering::Relaxed)
+                .is_ok()
+            {
+                // Only the thread that unlinked the node takes the value out
+                let value = unsafe { ptr::read(&*(*head).value) };
+                unsafe { self.reclaim.retire(&guard, head.cast(), free_node::<T>) };
+                return Some(value);
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.head.load(Ordering::Acquire).is_null()
+    }
+}
+
+impl<T, R: Reclaim> Default for TreiberStack<T, R> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, R: Reclaim> Drop for TreiberStack<T, R> {
+    fn drop(&mut self) {
+        let mut node = self.head.load(Ordering::Relaxed);
+        while !node.is_null() {
+            unsafe {
+                let next = (*node).next.with(|next| *next);
+                ManuallyDrop::drop(&mut (*node).value);
+                free_node::<T>(node.cast());
+                node = next;
+            }
+        }
+    }
+}
+
+#[cfg(all(test, not(loom), not(pct)))]
+mod tests {
+    use super::*;
+    use crate::linearizability::models::{PushPop, Stack};
+    use crate::linearizability::{assert_linearizable, programs};
+    use crate::reclaim::HazardPointers;
+    use proptest::prelude::*;
+    use proptest::test_runner::TestCaseError;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn pops_in_reverse_order() {
+        let stack: TreiberStack<_> = TreiberStack::new();
+        for i in 0..3 {
+            stack.push(i);
+        }
+        assert_eq!(stack.pop(), Some(2));
+        assert_eq!(stack.pop(), Some(1));
+        assert_eq!(stack.pop(), Some(0));
+        assert_eq!(stack.pop(), None);
+        assert!(stack.is_empty());
+    }
+
+    #[test]
+    fn drop_drops_values() {
+        let value = Arc::new(());
+        let stack: TreiberStack<_> = TreiberStack::new();
+        stack.push(Arc::clone(&value));
+        stack.push(Arc::clone(&value));
+        drop(stack.pop());
+        drop(stack);
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+
+    /// Pushes and pops from several threads and checks that every value comes out exactly once.
+    fn push_pop_concurrently<R: Reclaim + 'static>() {
+        let stack = Arc::new(TreiberStack::<usize, R>::new());
+
+        let handles: Vec<_> = (0..4)
+            .map(|t| {
+                let stack = Arc::clone(&stack);
+                thread::spawn(move || {
+                    let mut popped = vec![];
+                    for i in 0..10_000 {
+                        stack.push(t * 10_000 + i);
+                        if let Some(v) = stack.pop() {
+                            popped.push(v);
+                        }
+                    }
+                    popped
+                })
+            })
+            .collect();
+
+        let mut popped: Vec<_> = handles
+            .into_iter()
+            .flat_map(|h| h.join().unwrap())
+            .collect();
+        while let Some(v) = stack.pop() {
+            popped.push(v);
+        }
+        popped.sort();
+        assert_eq!(popped, (0..40_000).collect::<Vec<_>>());
+    }
+
+    fn linearizable<R: Reclaim>(programs: &[Vec<PushPop<u64>>]) -> Result<(), TestCaseError> {
+        let new = TreiberStack::<u64, R>::new;
+        assert_linearizable::<Stack<u64>, _>(programs, new, |stack, op| match *op {
+            PushPop::Push(value) => {
+                stack.push(value);
+                None
+            }
+            PushPop::Pop => stack.pop(),
+        })
+    }
+
+    #[test]
+    fn hazard_pointers_push_pop() {
+        push_pop_concurrently::<HazardPointers>();
+    }
+
+    #[test]
+    fn epoch_push_pop() {
+        push_pop_concurrently::<Epoch>();
+    }
+
+    proptest! {
+        #[test]
+        fn hazard_pointers_linearizable(programs in programs(4, any::<PushPop<u64>>())) {
+            linearizable::<HazardPointers>(&programs)?;
+        }
+
+        #[test]
+        fn epoch_linearizable(programs in programs(4, any::<PushPop<u64>>())) {
+            linearizable::<Epoch>(&programs)?;
+        }
+    }
+}
@@ -0,0 +1,111 @@
+//! Fixtures for ThreadSanitizer, which only compile with `--cfg tsan`. `tsan.sh` builds them with
+//! `-Zsanitizer=thread` and runs each through `tests/tsan.rs` in its own process. TSan must report
+//! a data race in every racy fixture and none in the clean ones, so that a quiet run means
+//! something.
+//!
+//! The runner has no test harness, since libtest's own threads trip TSan when `std` isn't
+//! instrumented.
+
+use crate::memory_ordering::mutex;
+use crate::trace::atomic::AtomicBool;
+use std::cell::UnsafeCell;
+use std::sync::Arc;
+use std::thread;
+
+const INCREMENTS: usize = 1_000;
+
+pub struct Fixture {
+    pub name: &'static str,
+    pub run: fn(),
+    /// Whether TSan should report a data race
+    pub racy: bool,
+}
+
+pub const FIXTURES: &[Fixture] = &[
+    Fixture {
+        name: "racy_static_mut",
+        run: racy_static_mut,
+        racy: true,
+    },
+    Fixture {
+        name: "racy_unsafe_cell_counter",
+        run: racy_unsafe_cell_counter,
+        racy: true,
+    },
+    Fixture {
+        name: "clean_spin_mutex",
+        run: clean_spin_mutex,
+        racy: false,
+    },
+    Fixture {
+        name: "clean_shared_mem_mutex",
+        run: clean_shared_mem_mutex,
+        racy: false,
+    },
+    Fixture {
+        name: "clean_shared_mem_dashmap",
+        run: clean_shared_mem_dashmap,
+        racy: false,
+    },
+];
+
+/// A counter that lets threads increment it without synchronising.
+struct RacyCounter(UnsafeCell<usize>);
+
+unsafe impl Sync for RacyCounter {}
+
+impl RacyCounter {
+    fn increment(&self) {
+        unsafe { *self.0.get() += 1 };
+    }
+}
+
+/// Runs `f` on two threads at once.
+fn on_two_threads(f: impl Fn() + Send + Sync + 'static) {
+    let f = Arc::new(f);
+    let t = {
+        let f = Arc::clone(&f);
+        thread::spawn(move || f())
+    };
+    f();
+    t.join().unwrap();
+}
+
+// The example from the notes
+fn racy_static_mut() {
+    static mut A: usize = 0;
+
+    let t = thread::spawn(|| {
+        unsafe { A += 1 };
+    });
+    unsafe { A += 1 };
+
+    t.join().unwrap();
+}
+
+fn racy_unsafe_cell_counter() {
+    let counter = Arc::new(RacyCounter(UnsafeCell::new(0)));
+    on_two_threads(move || {
+        for _ in 0..INCREMENTS {
+            counter.increment();
+        }
+    });
+}
+
+fn clean_spin_mutex() {
+    let lock = Arc::new(AtomicBool::new(false));
+    let counter = Arc::new(RacyCounter(UnsafeCell::new(0)));
+    on_two_threads(move || {
+        for _ in 0..INCREMENTS {
+            mutex(&lock, || counter.increment());
+        }
+    });
+}
+
+fn clean_shared_mem_mutex() {
+    assert_eq!(crate::shared_mem_mutex(), 10);
+}
+
+fn clean_shared_mem_dashmap() {
+    assert_eq!(crate::shared_mem_dashmap(), 10);
+}
@@ -0,0 +1,26 @@
+//! Runs the ThreadSanitizer fixture named by the first argument, or lists the fixtures with
+//! `--list`. Only does anything with `--cfg tsan`, which `tsan.sh` sets.
+
+#[cfg(tsan)]
+fn main() {
+    use concurrency_examples::tsan::FIXTURES;
+
+    let name = std::env::args()
+        .nth(1)
+        .expect("usage: tsan <fixture> | --list");
+    if name == "--list" {
+        for fixture in FIXTURES {
+            let kind = if fixture.racy { "racy" } else { "clean" };
+            println!("{} {kind}", fixture.name);
+        }
+        return;
+    }
+    let fixture = FIXTURES
+        .iter()
+        .find(|fixture| fixture.name == name)
+        .unwrap_or_else(|| panic!("no fixture `{name}`"));
+    (fixture.run)();
+}
+
+#[cfg(not(tsan))]
+fn main() {}
@@ -0,0 +1,48 @@
+#!/bin/sh
+# Builds the fixtures in `src/tsan.rs` with ThreadSanitizer and checks that TSan reports a data race
+# in each racy fixture and nothing in the clean ones. Needs a nightly toolchain.
+#
+# With the `rust-src` component, `std` is rebuilt instrumented, as the notes describe. Without it,
+# `std` stays uninstrumented, so TSan doesn't see synchronisation that happens inside `std`.
+
+set -u
+cd "$(dirname "$0")"
+
+target=$(rustc -vV | sed -n 's/^host: //p')
+flags="'-Zsanitizer=thread', '--cfg', 'tsan'"
+build_std=-Zbuild-std
+if [ ! -d "$(rustc --print sysroot)/lib/rustlib/src/rust/library" ]; then
+    echo "rust-src isn't installed, so std won't be instrumented" >&2
+    flags="$flags, '-Cunsafe-allow-abi-mismatch=sanitizer'"
+    build_std=
+fi
+
+tsan() {
+    cargo test $build_std --target "$target" --target-dir target/tsan \
+        --config "build.rustflags = [$flags]" --test tsan "$@"
+}
+
+tsan --no-run || exit 1
+
+fixtures=$(tsan -q -- --list 2>/dev/null) || exit 1
+failed=0
+while read -r name kind; do
+    # Reports go to stderr; exit normally so that a report is told apart from a crash
+    output=$(TSAN_OPTIONS=exitcode=0 tsan -q -- "$name" 2>&1)
+    status=$?
+    if echo "$output" | grep -q "WARNING: ThreadSanitizer"; then
+        reported=racy
+    else
+        reported=clean
+    fi
+    if [ $status -ne 0 ] || [ "$reported" != "$kind" ]; then
+        echo "FAILED $name: expected $kind, TSan found it $reported (exit status $status)"
+        echo "$output"
+        failed=1
+    else
+        echo "ok $name: $kind"
+    fi
+done <<END
+$fixtures
+END
+exit $failed
@@ -7,7 +7,7 @@
 // use core::hint::spin_loop;
 // use core::sync::atomic::{AtomicBool, Ordering};
 use bsp::entry;
-use core::ptr;
+use concurrency_examples::once::OnceCell;
 use defmt::*;
 use defmt_rtt as _;
 use embedded_hal::digital::OutputPin;
@@ -20,7 +20,7 @@ use rp_pico as bsp;
 
 use bsp::{
     hal::{
-        clocks::{init_clocks_and_plls, Clock, ClocksManager},
+        clocks::{init_clocks_and_plls, Clock},
         multicore::{Multicore, Stack},
         sio::Sio,
         watchdog::Watchdog,
@@ -31,8 +31,8 @@ use bsp::{
 // Atomic flag to indicate to core1 that core0 is ready
 // static CORE0_READY: AtomicBool = AtomicBool::new(false);
 
-// Allows the clock settings to be read from core1 after initialisation on core0
-static mut CLOCKS_MANAGER: *mut ClocksManager = ptr::null_mut();
+// Set by core0 once the clocks are initialised, read by core1
+static SYSTEM_CLOCK_HZ: OnceCell<u32> = OnceCell::new();
 
 // Constants for LED blinking
 const BLINK_DELAY1: u32 = 500; // 0.5 seconds
@@ -83,15 +83,18 @@ fn main_core1() {
         sio.gpio_bank0,
         &mut pac.RESETS,
     );
-    let clocks = unsafe { CLOCKS_MANAGER.as_mut().unwrap() };
+    let freq = *SYSTEM_CLOCK_HZ.wait();
 
-    info!("core1 freq: {}", clocks.system_clock.freq().to_Hz());
+    info!("core1 freq: {}", freq);
 
-    let mut delay = cortex_m::delay::Delay::new(core.SYST, clocks.system_clock.freq().to_Hz());
+    let mut delay = cortex_m::delay::Delay::new(core.SYST, freq);
 
     // Configure GPIO 16 as output
     let mut led_pin = pins.gpio16.into_push_pull_output();
 
+    // `thread_log` needs `std`, so this loop logs through defmt, whose RTT writes take the same
+    // critical section as core0's. defmt only sends an index and the arguments, which takes a few
+    // microseconds against a 250 ms blink, so the cores barely wait for each other.
     loop {
         info!("ON");
         led_pin.set_high().unwrap();
@@ -110,7 +113,7 @@ fn main() -> ! {
     let mut watchdog = Watchdog::new(pac.WATCHDOG);
 
     // External high-speed crystal on the pico board is 12Mhz
-    let mut clocks = init_clocks_and_plls(
+    let clocks = init_clocks_and_plls(
         bsp::XOSC_CRYSTAL_FREQ,
         pac.XOSC,
         pac.CLOCKS,
@@ -122,9 +125,7 @@ fn main() -> ! {
     .ok()
     .unwrap();
 
-    unsafe {
-        CLOCKS_MANAGER = &mut clocks;
-    }
+    SYSTEM_CLOCK_HZ.set(clocks.system_clock.freq().to_Hz()).ok();
 
     info!("core0 freq: {}", clocks.system_clock.freq().to_Hz());
 